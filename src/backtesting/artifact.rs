@@ -0,0 +1,615 @@
+use std::{error::Error, path::Path, str::FromStr, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Date32Type, Float64Type, Int64Type, TimestampMillisecondType},
+    Array, ArrayRef, Date32Array, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::DataType;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use pyo3::{prelude::*, types::PyDict};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    base::{StopOrder, StopOrderStatus},
+    daily_dataframe,
+    statistics::BacktestStatistics,
+    BacktestingEngine, DailyResult,
+};
+use crate::trader::{
+    database_impl::{read_batches, write_batch},
+    object::{OrderData, TradeData},
+};
+
+/// Bumped whenever the layout changes, older artifacts stay loadable
+pub const ARTIFACT_VERSION: i64 = 1;
+
+const MANIFEST_FILE: &str = "artifact.json";
+const TRADES_FILE: &str = "trades.parquet";
+const ORDERS_FILE: &str = "orders.parquet";
+const DAILY_RESULTS_FILE: &str = "daily_results.parquet";
+
+type ArtifactResult<T> = Result<T, Box<dyn Error>>;
+
+/// Everything given to set_parameters
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactParameters {
+    pub vt_symbol: String,
+    pub interval: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub rate: f64,
+    pub slippage: f64,
+    pub size: f64,
+    pub pricetick: f64,
+    pub capital: f64,
+    pub mode: String,
+    pub risk_free: f64,
+    pub annual_days: i64,
+    pub half_life: i64,
+    /// Artifacts saved before these were recorded load with the set_parameters defaults
+    #[serde(default)]
+    pub record_equity: bool,
+    #[serde(default = "default_margin_rate")]
+    pub margin_rate: f64,
+}
+
+fn default_margin_rate() -> f64 {
+    1.0
+}
+
+#[pymethods]
+impl ArtifactParameters {
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("vt_symbol", &self.vt_symbol)?;
+        dict.set_item("interval", &self.interval)?;
+        dict.set_item("start", self.start)?;
+        dict.set_item("end", self.end)?;
+        dict.set_item("rate", self.rate)?;
+        dict.set_item("slippage", self.slippage)?;
+        dict.set_item("size", self.size)?;
+        dict.set_item("pricetick", self.pricetick)?;
+        dict.set_item("capital", self.capital)?;
+        dict.set_item("mode", &self.mode)?;
+        dict.set_item("risk_free", self.risk_free)?;
+        dict.set_item("annual_days", self.annual_days)?;
+        dict.set_item("half_life", self.half_life)?;
+        dict.set_item("record_equity", self.record_equity)?;
+        dict.set_item("margin_rate", self.margin_rate)?;
+        Ok(dict.unbind())
+    }
+}
+
+/// Stop orders are few and nested, so they're kept in the json
+#[derive(Serialize, Deserialize)]
+struct StopOrderRecord {
+    vt_symbol: String,
+    direction: String,
+    offset: String,
+    price: f64,
+    volume: f64,
+    stop_orderid: String,
+    strategy_name: String,
+    /// Milliseconds since the epoch
+    datetime: i64,
+    timezone: String,
+    lock: bool,
+    net: bool,
+    vt_orderids: Vec<String>,
+    status: String,
+}
+
+impl StopOrderRecord {
+    fn new(stop_order: &StopOrder) -> Self {
+        StopOrderRecord {
+            vt_symbol: stop_order.vt_symbol.clone(),
+            direction: stop_order.direction.to_string(),
+            offset: stop_order.offset.to_string(),
+            price: stop_order.price,
+            volume: stop_order.volume,
+            stop_orderid: stop_order.stop_orderid.clone(),
+            strategy_name: stop_order.strategy_name.clone(),
+            datetime: stop_order.datetime.timestamp_millis(),
+            timezone: stop_order.datetime.timezone().name().to_string(),
+            lock: stop_order.lock,
+            net: stop_order.net,
+            vt_orderids: stop_order.vt_orderids.clone(),
+            status: stop_order.status.to_string(),
+        }
+    }
+
+    fn stop_order(&self) -> ArtifactResult<StopOrder> {
+        Ok(StopOrder {
+            vt_symbol: self.vt_symbol.clone(),
+            direction: FromStr::from_str(&self.direction)?,
+            offset: FromStr::from_str(&self.offset)?,
+            price: self.price,
+            volume: self.volume,
+            stop_orderid: self.stop_orderid.clone(),
+            strategy_name: self.strategy_name.clone(),
+            datetime: datetime(self.datetime, Tz::from_str(&self.timezone)?)?,
+            lock: self.lock,
+            net: self.net,
+            vt_orderids: self.vt_orderids.clone(),
+            status: StopOrderStatus::from_str(&self.status)?,
+        })
+    }
+}
+
+/// The json part of an artifact, trades, orders and daily results are parquet files beside it
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: i64,
+    created: String,
+    strategy_class: String,
+    parameters: ArtifactParameters,
+    /// Strategy parameters as dumped by python's json module
+    setting: serde_json::Value,
+    statistics: BacktestStatistics,
+    logs: Vec<String>,
+    stop_orders: Vec<StopOrderRecord>,
+}
+
+/// A finished backtest saved by export_artifact, queried like the engine
+#[pyclass]
+pub struct BacktestArtifact {
+    #[pyo3(get)]
+    version: i64,
+    #[pyo3(get)]
+    created: String,
+    #[pyo3(get)]
+    strategy_class: String,
+    #[pyo3(get)]
+    parameters: ArtifactParameters,
+    setting: serde_json::Value,
+    statistics: BacktestStatistics,
+    #[pyo3(get)]
+    logs: Vec<String>,
+    trades: Vec<TradeData>,
+    orders: Vec<OrderData>,
+    stop_orders: Vec<StopOrder>,
+    daily_results: Vec<DailyResult>,
+}
+
+#[pymethods]
+impl BacktestArtifact {
+    /// Load the directory written by BacktestingEngine.export_artifact
+    #[staticmethod]
+    pub fn load(path: std::path::PathBuf) -> PyResult<Self> {
+        load_artifact(&path).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "回测结果{}读取失败：{}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    #[getter]
+    pub fn setting(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(py
+            .import("json")?
+            .call_method1("loads", (self.setting.to_string(),))?
+            .unbind())
+    }
+
+    pub fn get_all_trades(&self) -> Vec<TradeData> {
+        self.trades.clone()
+    }
+
+    pub fn get_all_orders(&self) -> Vec<OrderData> {
+        self.orders.clone()
+    }
+
+    pub fn get_all_stop_orders(&self) -> Vec<StopOrder> {
+        self.stop_orders.clone()
+    }
+
+    pub fn get_all_daily_results(&self) -> Vec<DailyResult> {
+        self.daily_results.clone()
+    }
+
+    pub fn calculate_result(&self, py: Python<'_>) -> PyResult<PyObject> {
+        daily_dataframe(py, &self.daily_results)
+    }
+
+    /// The statistics saved with the run, printed like the engine does
+    #[pyo3(signature = (output=true))]
+    pub fn calculate_statistics(&self, output: bool) -> BacktestStatistics {
+        if output {
+            for line in self.statistics.report_lines() {
+                println!("{}", line);
+            }
+        }
+        self.statistics.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "BacktestArtifact(version={}, created={}, strategy_class={}, vt_symbol={}, trades={})",
+            self.version,
+            self.created,
+            self.strategy_class,
+            self.parameters.vt_symbol,
+            self.trades.len()
+        )
+    }
+}
+
+fn datetime(timestamp: i64, tz: Tz) -> ArtifactResult<DateTime<Tz>> {
+    Ok(DateTime::from_timestamp_millis(timestamp)
+        .ok_or("时间戳超出范围")?
+        .with_timezone(&tz))
+}
+
+fn strings<T>(rows: &[T], value: impl Fn(&T) -> String) -> ArrayRef {
+    Arc::new(StringArray::from(
+        rows.iter().map(value).collect::<Vec<_>>(),
+    ))
+}
+
+fn floats<T>(rows: &[T], value: impl Fn(&T) -> f64) -> ArrayRef {
+    Arc::new(Float64Array::from(
+        rows.iter().map(value).collect::<Vec<_>>(),
+    ))
+}
+
+/// Timestamps keep the timezone of the first row, all rows of a run share it
+fn timestamps<T>(rows: &[T], value: impl Fn(&T) -> DateTime<Tz>) -> ArrayRef {
+    let tz = rows
+        .first()
+        .map(|row| value(row).timezone())
+        .unwrap_or(Tz::UTC);
+    Arc::new(
+        TimestampMillisecondArray::from(
+            rows.iter()
+                .map(|row| value(row).timestamp_millis())
+                .collect::<Vec<_>>(),
+        )
+        .with_timezone(tz.name()),
+    )
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> ArtifactResult<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| format!("缺少{}列", name).into())
+}
+
+fn string_column(batch: &RecordBatch, name: &str) -> ArtifactResult<Vec<String>> {
+    let array = column(batch, name)?.as_string::<i32>();
+    Ok((0..array.len())
+        .map(|i| array.value(i).to_string())
+        .collect())
+}
+
+fn float_column(batch: &RecordBatch, name: &str) -> ArtifactResult<Vec<f64>> {
+    Ok(column(batch, name)?
+        .as_primitive::<Float64Type>()
+        .values()
+        .to_vec())
+}
+
+fn datetime_column(batch: &RecordBatch, name: &str) -> ArtifactResult<Vec<DateTime<Tz>>> {
+    let array = column(batch, name)?;
+    let tz = match array.data_type() {
+        DataType::Timestamp(_, Some(tz)) => Tz::from_str(tz)?,
+        _ => Tz::UTC,
+    };
+    array
+        .as_primitive::<TimestampMillisecondType>()
+        .values()
+        .iter()
+        .map(|timestamp| datetime(*timestamp, tz))
+        .collect()
+}
+
+fn write_trades(path: &Path, trades: &[TradeData]) -> ArtifactResult<()> {
+    let batch = RecordBatch::try_from_iter([
+        ("datetime", timestamps(trades, |t| t.datetime)),
+        ("symbol", strings(trades, |t| t.symbol.clone())),
+        ("exchange", strings(trades, |t| t.exchange.clone())),
+        ("orderid", strings(trades, |t| t.orderid.clone())),
+        ("tradeid", strings(trades, |t| t.tradeid.clone())),
+        ("direction", strings(trades, |t| t.direction.to_string())),
+        ("offset", strings(trades, |t| t.offset.to_string())),
+        ("price", floats(trades, |t| t.price)),
+        ("volume", floats(trades, |t| t.volume)),
+    ])?;
+    write_batch(path, &batch)
+}
+
+fn read_trades(path: &Path) -> ArtifactResult<Vec<TradeData>> {
+    let mut trades = Vec::new();
+    for batch in read_batches(path, None)? {
+        let datetime = datetime_column(&batch, "datetime")?;
+        let symbol = string_column(&batch, "symbol")?;
+        let exchange = string_column(&batch, "exchange")?;
+        let orderid = string_column(&batch, "orderid")?;
+        let tradeid = string_column(&batch, "tradeid")?;
+        let direction = string_column(&batch, "direction")?;
+        let offset = string_column(&batch, "offset")?;
+        let price = float_column(&batch, "price")?;
+        let volume = float_column(&batch, "volume")?;
+        for i in 0..batch.num_rows() {
+            trades.push(TradeData {
+                gateway_name: BacktestingEngine::gateway_name(),
+                symbol: symbol[i].clone(),
+                exchange: exchange[i].clone(),
+                orderid: orderid[i].clone(),
+                tradeid: tradeid[i].clone(),
+                direction: FromStr::from_str(&direction[i])?,
+                offset: FromStr::from_str(&offset[i])?,
+                price: price[i],
+                volume: volume[i],
+                datetime: datetime[i],
+            });
+        }
+    }
+    Ok(trades)
+}
+
+fn write_orders(path: &Path, orders: &[OrderData]) -> ArtifactResult<()> {
+    let batch = RecordBatch::try_from_iter([
+        ("datetime", timestamps(orders, |o| o.datetime)),
+        ("symbol", strings(orders, |o| o.symbol.clone())),
+        ("exchange", strings(orders, |o| o.exchange.clone())),
+        ("orderid", strings(orders, |o| o.orderid.clone())),
+        ("type", strings(orders, |o| o.r#type.to_string())),
+        ("direction", strings(orders, |o| o.direction.to_string())),
+        ("offset", strings(orders, |o| o.offset.to_string())),
+        ("price", floats(orders, |o| o.price)),
+        ("volume", floats(orders, |o| o.volume)),
+        ("traded", floats(orders, |o| o.traded)),
+        ("status", strings(orders, |o| o.status.to_string())),
+        ("reference", strings(orders, |o| o.reference.clone())),
+    ])?;
+    write_batch(path, &batch)
+}
+
+fn read_orders(path: &Path) -> ArtifactResult<Vec<OrderData>> {
+    let mut orders = Vec::new();
+    for batch in read_batches(path, None)? {
+        let datetime = datetime_column(&batch, "datetime")?;
+        let symbol = string_column(&batch, "symbol")?;
+        let exchange = string_column(&batch, "exchange")?;
+        let orderid = string_column(&batch, "orderid")?;
+        let r#type = string_column(&batch, "type")?;
+        let direction = string_column(&batch, "direction")?;
+        let offset = string_column(&batch, "offset")?;
+        let price = float_column(&batch, "price")?;
+        let volume = float_column(&batch, "volume")?;
+        let traded = float_column(&batch, "traded")?;
+        let status = string_column(&batch, "status")?;
+        let reference = string_column(&batch, "reference")?;
+        for i in 0..batch.num_rows() {
+            orders.push(OrderData {
+                gateway_name: BacktestingEngine::gateway_name(),
+                symbol: symbol[i].clone(),
+                exchange: exchange[i].clone(),
+                orderid: orderid[i].clone(),
+                r#type: FromStr::from_str(&r#type[i])?,
+                direction: FromStr::from_str(&direction[i])?,
+                offset: FromStr::from_str(&offset[i])?,
+                price: price[i],
+                volume: volume[i],
+                traded: traded[i],
+                status: FromStr::from_str(&status[i])?,
+                datetime: datetime[i],
+                reference: reference[i].clone(),
+            });
+        }
+    }
+    Ok(orders)
+}
+
+fn write_daily_results(path: &Path, daily_results: &[DailyResult]) -> ArtifactResult<()> {
+    let date: ArrayRef = Arc::new(Date32Array::from(
+        daily_results
+            .iter()
+            .map(|d| Date32Type::from_naive_date(d.date))
+            .collect::<Vec<_>>(),
+    ));
+    let trade_count: ArrayRef = Arc::new(Int64Array::from(
+        daily_results
+            .iter()
+            .map(|d| d.trade_count)
+            .collect::<Vec<_>>(),
+    ));
+    let batch = RecordBatch::try_from_iter([
+        ("date", date),
+        ("close_price", floats(daily_results, |d| d.close_price)),
+        ("pre_close", floats(daily_results, |d| d.pre_close)),
+        ("trade_count", trade_count),
+        ("start_pos", floats(daily_results, |d| d.start_pos)),
+        ("end_pos", floats(daily_results, |d| d.end_pos)),
+        ("turnover", floats(daily_results, |d| d.turnover)),
+        ("commission", floats(daily_results, |d| d.commission)),
+        ("slippage", floats(daily_results, |d| d.slippage)),
+        ("trading_pnl", floats(daily_results, |d| d.trading_pnl)),
+        ("holding_pnl", floats(daily_results, |d| d.holding_pnl)),
+        ("total_pnl", floats(daily_results, |d| d.total_pnl)),
+        ("net_pnl", floats(daily_results, |d| d.net_pnl)),
+    ])?;
+    write_batch(path, &batch)
+}
+
+/// Trades of each day are taken from the trade list, as calculate_daily_results does
+fn read_daily_results(path: &Path, trades: &[TradeData]) -> ArtifactResult<Vec<DailyResult>> {
+    let mut daily_results = Vec::new();
+    for batch in read_batches(path, None)? {
+        let date = column(&batch, "date")?.as_primitive::<Date32Type>();
+        let trade_count = column(&batch, "trade_count")?.as_primitive::<Int64Type>();
+        let close_price = float_column(&batch, "close_price")?;
+        let pre_close = float_column(&batch, "pre_close")?;
+        let start_pos = float_column(&batch, "start_pos")?;
+        let end_pos = float_column(&batch, "end_pos")?;
+        let turnover = float_column(&batch, "turnover")?;
+        let commission = float_column(&batch, "commission")?;
+        let slippage = float_column(&batch, "slippage")?;
+        let trading_pnl = float_column(&batch, "trading_pnl")?;
+        let holding_pnl = float_column(&batch, "holding_pnl")?;
+        let total_pnl = float_column(&batch, "total_pnl")?;
+        let net_pnl = float_column(&batch, "net_pnl")?;
+        for i in 0..batch.num_rows() {
+            let date: NaiveDate = Date32Type::to_naive_date(date.value(i));
+            daily_results.push(DailyResult {
+                date,
+                close_price: close_price[i],
+                pre_close: pre_close[i],
+                trades: trades
+                    .iter()
+                    .filter(|trade| trade.datetime.naive_local().date() == date)
+                    .cloned()
+                    .collect(),
+                trade_count: trade_count.value(i),
+                start_pos: start_pos[i],
+                end_pos: end_pos[i],
+                turnover: turnover[i],
+                commission: commission[i],
+                slippage: slippage[i],
+                trading_pnl: trading_pnl[i],
+                holding_pnl: holding_pnl[i],
+                total_pnl: total_pnl[i],
+                net_pnl: net_pnl[i],
+            });
+        }
+    }
+    Ok(daily_results)
+}
+
+/// Data of a finished run gathered by the engine
+pub struct ArtifactData {
+    pub strategy_class: String,
+    pub parameters: ArtifactParameters,
+    pub setting: serde_json::Value,
+    pub statistics: BacktestStatistics,
+    pub logs: Vec<String>,
+    pub trades: Vec<TradeData>,
+    pub orders: Vec<OrderData>,
+    pub stop_orders: Vec<StopOrder>,
+    pub daily_results: Vec<DailyResult>,
+}
+
+/// Write the artifact into a directory, files already there are replaced
+pub fn save_artifact(path: &Path, data: ArtifactData, created: String) -> ArtifactResult<()> {
+    std::fs::create_dir_all(path)?;
+    // Drop the old manifest first, so an export interrupted while replacing
+    // the parquet files never loads as a mix of the old and the new run
+    let manifest_path = path.join(MANIFEST_FILE);
+    match std::fs::remove_file(&manifest_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    write_trades(&path.join(TRADES_FILE), &data.trades)?;
+    write_orders(&path.join(ORDERS_FILE), &data.orders)?;
+    write_daily_results(&path.join(DAILY_RESULTS_FILE), &data.daily_results)?;
+
+    // The manifest is written last, a directory without it is never a complete artifact
+    let manifest = Manifest {
+        version: ARTIFACT_VERSION,
+        created,
+        strategy_class: data.strategy_class,
+        parameters: data.parameters,
+        setting: data.setting,
+        statistics: data.statistics,
+        logs: data.logs,
+        stop_orders: data.stop_orders.iter().map(StopOrderRecord::new).collect(),
+    };
+    let tmp_path = manifest_path.with_extension("tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(&manifest)?)?;
+    std::fs::rename(&tmp_path, &manifest_path)?;
+    Ok(())
+}
+
+pub fn load_artifact(path: &Path) -> ArtifactResult<BacktestArtifact> {
+    let manifest: Manifest =
+        serde_json::from_str(&std::fs::read_to_string(path.join(MANIFEST_FILE))?)?;
+    if manifest.version > ARTIFACT_VERSION {
+        return Err(format!(
+            "版本{}高于当前支持的版本{}，请升级VnpyRS",
+            manifest.version, ARTIFACT_VERSION
+        )
+        .into());
+    }
+    let trades = read_trades(&path.join(TRADES_FILE))?;
+    let daily_results = read_daily_results(&path.join(DAILY_RESULTS_FILE), &trades)?;
+    Ok(BacktestArtifact {
+        version: manifest.version,
+        created: manifest.created,
+        strategy_class: manifest.strategy_class,
+        parameters: manifest.parameters,
+        setting: manifest.setting,
+        statistics: manifest.statistics,
+        logs: manifest.logs,
+        orders: read_orders(&path.join(ORDERS_FILE))?,
+        stop_orders: manifest
+            .stop_orders
+            .iter()
+            .map(StopOrderRecord::stop_order)
+            .collect::<ArtifactResult<_>>()?,
+        trades,
+        daily_results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters_json() -> serde_json::Value {
+        serde_json::json!({
+            "vt_symbol": "rb.SHFE",
+            "interval": "1m",
+            "start": "2024-01-02T00:00:00",
+            "end": "2024-03-31T00:00:00",
+            "rate": 0.0001,
+            "slippage": 0.2,
+            "size": 10.0,
+            "pricetick": 0.2,
+            "capital": 10000.0,
+            "mode": "BAR",
+            "risk_free": 0.0,
+            "annual_days": 240,
+            "half_life": 120,
+        })
+    }
+
+    #[test]
+    fn old_parameters_load_with_defaults() {
+        let parameters: ArtifactParameters = serde_json::from_value(parameters_json()).unwrap();
+        assert!(!parameters.record_equity);
+        assert_eq!(parameters.margin_rate, 1.0);
+    }
+
+    #[test]
+    fn interrupted_export_leaves_no_manifest() {
+        let path = std::env::temp_dir().join(format!("vnpyrs_artifact_{}", std::process::id()));
+        let data = || ArtifactData {
+            strategy_class: "ToyStrategy".to_string(),
+            parameters: serde_json::from_value(parameters_json()).unwrap(),
+            setting: serde_json::json!({}),
+            statistics: BacktestStatistics::default(),
+            logs: Vec::new(),
+            trades: Vec::new(),
+            orders: Vec::new(),
+            stop_orders: Vec::new(),
+            daily_results: Vec::new(),
+        };
+        save_artifact(&path, data(), "first".to_string()).unwrap();
+        assert!(load_artifact(&path).is_ok());
+
+        // A non empty directory in the way makes the orders file fail to be replaced
+        std::fs::remove_file(path.join(ORDERS_FILE)).unwrap();
+        std::fs::create_dir_all(path.join(ORDERS_FILE).join("blocked")).unwrap();
+        assert!(save_artifact(&path, data(), "second".to_string()).is_err());
+        assert!(!path.join(MANIFEST_FILE).exists());
+        assert!(load_artifact(&path).is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
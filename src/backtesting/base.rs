@@ -10,7 +10,7 @@ use crate::trader::constant::{Direction, Interval, Offset_};
 pub const STOPORDER_PREFIX: &'static str = "STOP";
 
 #[pyclass(eq, eq_int)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, EnumString, Display)]
 pub enum StopOrderStatus {
     WAITING,
     CANCELLED,
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use pyo3::{prelude::*, types::PyDict, IntoPyObjectExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    statistics::{finite, format_number, mean, std},
+    DailyResult,
+};
+use crate::trader::object::BarData;
+
+/// Daily returns of what the strategy is compared with
+#[derive(Debug, Clone, Default)]
+pub struct Benchmark {
+    pub name: String,
+    pub returns: BTreeMap<NaiveDate, f64>,
+}
+
+impl Benchmark {
+    /// Returns from the close of the last bar of each trading day, the first day has none
+    pub fn from_bars(name: &str, bars: impl IntoIterator<Item = BarData>) -> Benchmark {
+        let mut closes = BTreeMap::new();
+        for bar in bars {
+            closes.insert(bar.datetime.naive_local().date(), bar.close_price);
+        }
+        let closes: Vec<(NaiveDate, f64)> = closes.into_iter().collect();
+        let returns = closes
+            .windows(2)
+            .filter(|pair| pair[0].1 != 0.0)
+            .map(|pair| (pair[1].0, pair[1].1 / pair[0].1 - 1.0))
+            .collect();
+        Benchmark {
+            name: name.to_string(),
+            returns,
+        }
+    }
+
+    /// Daily returns of the strategy and the benchmark on the trading days both have
+    pub fn align<'a>(
+        &self,
+        daily_results: impl IntoIterator<Item = &'a DailyResult>,
+        capital: f64,
+    ) -> Vec<(NaiveDate, f64, f64)> {
+        let mut pre_balance = capital;
+        let mut aligned = Vec::new();
+        for daily_result in daily_results {
+            let balance = pre_balance + daily_result.net_pnl;
+            if let Some(benchmark_return) = self.returns.get(&daily_result.date) {
+                aligned.push((
+                    daily_result.date,
+                    balance / pre_balance - 1.0,
+                    *benchmark_return,
+                ));
+            }
+            pre_balance = balance;
+        }
+        aligned
+    }
+}
+
+/// Performance relative to the benchmark, returns and alpha are in percent
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchmarkStatistics {
+    pub benchmark: String,
+    pub aligned_days: i64,
+    pub benchmark_return: f64,
+    pub excess_return: f64,
+    pub alpha: f64,
+    pub beta: f64,
+    pub correlation: f64,
+    pub tracking_error: f64,
+    pub information_ratio: f64,
+    pub up_capture: f64,
+    pub down_capture: f64,
+}
+
+impl BenchmarkStatistics {
+    pub fn items(&self, py: Python<'_>) -> PyResult<Vec<(&'static str, PyObject)>> {
+        Ok(vec![
+            ("benchmark", self.benchmark.clone().into_py_any(py)?),
+            ("aligned_days", self.aligned_days.into_py_any(py)?),
+            ("benchmark_return", self.benchmark_return.into_py_any(py)?),
+            ("excess_return", self.excess_return.into_py_any(py)?),
+            ("alpha", self.alpha.into_py_any(py)?),
+            ("beta", self.beta.into_py_any(py)?),
+            ("correlation", self.correlation.into_py_any(py)?),
+            ("tracking_error", self.tracking_error.into_py_any(py)?),
+            ("information_ratio", self.information_ratio.into_py_any(py)?),
+            ("up_capture", self.up_capture.into_py_any(py)?),
+            ("down_capture", self.down_capture.into_py_any(py)?),
+        ])
+    }
+
+    pub fn report_lines(&self) -> Vec<String> {
+        vec![
+            format!("基准：\t{}", self.benchmark),
+            format!("基准收益率：\t{}%", format_number(self.benchmark_return)),
+            format!("超额收益率：\t{}%", format_number(self.excess_return)),
+            format!("Alpha：\t{}%", format_number(self.alpha)),
+            format!("Beta：\t{}", format_number(self.beta)),
+            format!("相关系数：\t{}", format_number(self.correlation)),
+            format!("跟踪误差：\t{}%", format_number(self.tracking_error)),
+            format!("信息比率：\t{}", format_number(self.information_ratio)),
+            format!("上行捕获率：\t{}", format_number(self.up_capture)),
+            format!("下行捕获率：\t{}", format_number(self.down_capture)),
+        ]
+    }
+}
+
+#[pymethods]
+impl BenchmarkStatistics {
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        for (key, value) in self.items(py)? {
+            dict.set_item(key, value)?;
+        }
+        Ok(dict.unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "BenchmarkStatistics(benchmark={}, alpha={:.2}, beta={:.4}, information_ratio={:.4})",
+            self.benchmark, self.alpha, self.beta, self.information_ratio
+        )
+    }
+}
+
+fn covariance(x: &[f64], y: &[f64]) -> f64 {
+    let (mean_x, mean_y) = (mean(x), mean(y));
+    x.iter()
+        .zip(y)
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum::<f64>()
+        / (x.len() as f64 - 1.0)
+}
+
+/// Average strategy return over the average benchmark return, on the days the benchmark went up (or down)
+fn capture(aligned: &[(NaiveDate, f64, f64)], up: bool) -> f64 {
+    let (strategy, benchmark): (Vec<f64>, Vec<f64>) = aligned
+        .iter()
+        .filter(|(_, _, b)| if up { *b > 0.0 } else { *b < 0.0 })
+        .map(|(_, s, b)| (*s, *b))
+        .unzip();
+    finite(mean(&strategy) / mean(&benchmark))
+}
+
+/// Statistics from the aligned daily returns, annualized with annual_days
+pub fn calculate_benchmark_statistics(
+    name: &str,
+    aligned: &[(NaiveDate, f64, f64)],
+    risk_free: f64,
+    annual_days: i64,
+) -> BenchmarkStatistics {
+    let mut statistics = BenchmarkStatistics {
+        benchmark: name.to_string(),
+        aligned_days: aligned.len() as i64,
+        ..Default::default()
+    };
+    if aligned.is_empty() {
+        return statistics;
+    }
+    let annual_days = annual_days as f64;
+    let strategy: Vec<f64> = aligned.iter().map(|(_, s, _)| *s).collect();
+    let benchmark: Vec<f64> = aligned.iter().map(|(_, _, b)| *b).collect();
+    let excess: Vec<f64> = strategy
+        .iter()
+        .zip(&benchmark)
+        .map(|(s, b)| s - b)
+        .collect();
+
+    let total = |returns: &[f64]| returns.iter().map(|r| 1.0 + r).product::<f64>() - 1.0;
+    statistics.benchmark_return = total(&benchmark) * 100.0;
+    statistics.excess_return = (total(&strategy) - total(&benchmark)) * 100.0;
+
+    let covariance = covariance(&strategy, &benchmark);
+    statistics.beta = finite(covariance / std(&benchmark).powi(2));
+    statistics.correlation = finite(covariance / (std(&strategy) * std(&benchmark)));
+    // risk_free is in percent, its daily part is taken as in the sharpe ratio
+    let daily_risk_free = risk_free / annual_days.sqrt() / 100.0;
+    statistics.alpha = finite(
+        (mean(&strategy)
+            - daily_risk_free
+            - statistics.beta * (mean(&benchmark) - daily_risk_free))
+            * annual_days
+            * 100.0,
+    );
+    let tracking_error = std(&excess) * annual_days.sqrt();
+    statistics.tracking_error = finite(tracking_error * 100.0);
+    statistics.information_ratio = finite(mean(&excess) * annual_days / tracking_error);
+    statistics.up_capture = capture(aligned, true);
+    statistics.down_capture = capture(aligned, false);
+    statistics
+}
+
+/// Cumulative returns in percent, and the excess of the strategy over the benchmark
+pub fn excess_curve(aligned: &[(NaiveDate, f64, f64)]) -> Vec<(NaiveDate, f64, f64, f64)> {
+    let (mut strategy, mut benchmark) = (1.0, 1.0);
+    aligned
+        .iter()
+        .map(|(date, s, b)| {
+            strategy *= 1.0 + s;
+            benchmark *= 1.0 + b;
+            (
+                *date,
+                (strategy - 1.0) * 100.0,
+                (benchmark - 1.0) * 100.0,
+                (strategy - benchmark) * 100.0,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpha_with_risk_free() {
+        let aligned: Vec<(NaiveDate, f64, f64)> =
+            [(0.01, 0.005), (-0.005, -0.01), (0.02, 0.01), (0.0, 0.002)]
+                .into_iter()
+                .enumerate()
+                .map(|(i, (s, b))| {
+                    (
+                        NaiveDate::from_ymd_opt(2024, 1, 2 + i as u32).unwrap(),
+                        s,
+                        b,
+                    )
+                })
+                .collect();
+        let statistics = calculate_benchmark_statistics("IF", &aligned, 2.0, 240);
+
+        // beta = cov(s, b) / var(b) = 0.0000854167 / 0.00007225
+        assert!((statistics.beta - 1.182237600922722).abs() < 1e-9);
+        // daily risk free = 2 / sqrt(240) / 100, alpha = (0.00625 - rf - beta * (0.00175 - rf)) * 240 * 100
+        assert!((statistics.alpha - 105.99244630865765).abs() < 1e-9);
+    }
+}
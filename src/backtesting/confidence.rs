@@ -0,0 +1,334 @@
+use pyo3::{prelude::*, IntoPyObjectExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::statistics::{finite, format_number, mean, std};
+
+/// Euler–Mascheroni constant
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+/// How the confidence statistics are calculated, set by set_confidence
+#[derive(Debug, Clone)]
+pub struct ConfidenceSetting {
+    pub samples: usize,
+    pub confidence: f64,
+    /// Average length of the resampled blocks in days
+    pub block_length: f64,
+    pub seed: u64,
+    /// Number of parameter sets tried by the optimization, for the deflated sharpe ratio
+    pub trials: Option<usize>,
+    /// Sharpe ratios of the trials, their spread is used when given
+    pub trial_sharpes: Vec<f64>,
+}
+
+/// Bootstrap confidence intervals, probabilistic and deflated sharpe ratio
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfidenceStatistics {
+    pub confidence: f64,
+    pub samples: i64,
+    pub sharpe_ratio_lower: f64,
+    pub sharpe_ratio_upper: f64,
+    pub annual_return_lower: f64,
+    pub annual_return_upper: f64,
+    pub max_ddpercent_lower: f64,
+    pub max_ddpercent_upper: f64,
+    /// Probability that the true sharpe ratio is above 0
+    pub probabilistic_sharpe: f64,
+    /// Probability that the true sharpe ratio is above the best expected from the trials by luck
+    pub deflated_sharpe: Option<f64>,
+    pub trials: Option<i64>,
+}
+
+impl ConfidenceStatistics {
+    pub fn items(&self, py: Python<'_>) -> PyResult<Vec<(&'static str, PyObject)>> {
+        let mut items = vec![
+            (
+                "sharpe_ratio_lower",
+                self.sharpe_ratio_lower.into_py_any(py)?,
+            ),
+            (
+                "sharpe_ratio_upper",
+                self.sharpe_ratio_upper.into_py_any(py)?,
+            ),
+            (
+                "annual_return_lower",
+                self.annual_return_lower.into_py_any(py)?,
+            ),
+            (
+                "annual_return_upper",
+                self.annual_return_upper.into_py_any(py)?,
+            ),
+            (
+                "max_ddpercent_lower",
+                self.max_ddpercent_lower.into_py_any(py)?,
+            ),
+            (
+                "max_ddpercent_upper",
+                self.max_ddpercent_upper.into_py_any(py)?,
+            ),
+            (
+                "probabilistic_sharpe",
+                self.probabilistic_sharpe.into_py_any(py)?,
+            ),
+        ];
+        if let Some(deflated_sharpe) = self.deflated_sharpe {
+            items.push(("deflated_sharpe", deflated_sharpe.into_py_any(py)?));
+        }
+        Ok(items)
+    }
+
+    pub fn report_lines(&self) -> Vec<String> {
+        let level = (self.confidence * 1000.0).round() / 10.0;
+        let mut lines = vec![
+            format!(
+                "Sharpe Ratio {}%置信区间：\t{} ~ {}",
+                level,
+                format_number(self.sharpe_ratio_lower),
+                format_number(self.sharpe_ratio_upper)
+            ),
+            format!(
+                "年化收益{}%置信区间：\t{}% ~ {}%",
+                level,
+                format_number(self.annual_return_lower),
+                format_number(self.annual_return_upper)
+            ),
+            format!(
+                "百分比最大回撤{}%置信区间：\t{}% ~ {}%",
+                level,
+                format_number(self.max_ddpercent_lower),
+                format_number(self.max_ddpercent_upper)
+            ),
+            format!(
+                "Probabilistic Sharpe：\t{}",
+                format_number(self.probabilistic_sharpe)
+            ),
+        ];
+        if let (Some(deflated_sharpe), Some(trials)) = (self.deflated_sharpe, self.trials) {
+            lines.push(format!(
+                "Deflated Sharpe（{}组参数）：\t{}",
+                trials,
+                format_number(deflated_sharpe)
+            ));
+        }
+        lines
+    }
+}
+
+#[pymethods]
+impl ConfidenceStatistics {
+    fn __repr__(&self) -> String {
+        format!(
+            "ConfidenceStatistics(confidence={}, sharpe_ratio=[{:.4}, {:.4}], probabilistic_sharpe={:.4}, deflated_sharpe={:?})",
+            self.confidence,
+            self.sharpe_ratio_lower,
+            self.sharpe_ratio_upper,
+            self.probabilistic_sharpe,
+            self.deflated_sharpe
+        )
+    }
+}
+
+/// Standard normal cumulative distribution, from the complementary error function
+/// of Numerical Recipes (fractional error below 1.2e-7)
+pub fn norm_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let erfc = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        1.0 - 0.5 * erfc
+    } else {
+        0.5 * erfc
+    }
+}
+
+/// Inverse of the standard normal cumulative distribution, Acklam's approximation
+pub fn norm_ppf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p <= 0.0 {
+        f64::NEG_INFINITY
+    } else if p >= 1.0 {
+        f64::INFINITY
+    } else if p < 0.024_25 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.024_25 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Sample skewness and (non excess) kurtosis
+fn skew_kurtosis(values: &[f64]) -> (f64, f64) {
+    let mean = mean(values);
+    let n = values.len() as f64;
+    let m2 = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    let m3 = values.iter().map(|v| (v - mean).powi(3)).sum::<f64>() / n;
+    let m4 = values.iter().map(|v| (v - mean).powi(4)).sum::<f64>() / n;
+    (finite(m3 / m2.powf(1.5)), finite(m4 / (m2 * m2)))
+}
+
+/// Standard deviation of the estimated (daily) sharpe ratio
+fn sharpe_std(sharpe: f64, skew: f64, kurtosis: f64, n: usize) -> f64 {
+    ((1.0 - skew * sharpe + (kurtosis - 1.0) / 4.0 * sharpe * sharpe) / (n as f64 - 1.0)).sqrt()
+}
+
+/// Sharpe ratio, annual return and max drawdown percent of a series of daily log returns,
+/// calculated the same way as calculate_statistics
+fn metrics(returns: &[f64], risk_free: f64, annual_days: f64) -> (f64, f64, f64) {
+    let daily_risk_free = risk_free / annual_days.sqrt();
+    let sharpe = finite(
+        (mean(returns) * 100.0 - daily_risk_free) / (std(returns) * 100.0) * annual_days.sqrt(),
+    );
+
+    let mut balance: f64 = 1.0;
+    let mut highlevel: f64 = 1.0;
+    let mut max_ddpercent: f64 = 0.0;
+    for r in returns {
+        balance *= r.exp();
+        highlevel = highlevel.max(balance);
+        max_ddpercent = max_ddpercent.min((balance - highlevel) / highlevel * 100.0);
+    }
+    let annual_return = (balance - 1.0) * 100.0 / returns.len() as f64 * annual_days;
+    (sharpe, annual_return, max_ddpercent)
+}
+
+/// Stationary block bootstrap: blocks start at random days and have geometric lengths
+fn resample(returns: &[f64], block_length: f64, rng: &mut StdRng, sample: &mut Vec<f64>) {
+    let n = returns.len();
+    let p = 1.0 / block_length.max(1.0);
+    sample.clear();
+    let mut index = rng.gen_range(0..n);
+    for _ in 0..n {
+        sample.push(returns[index]);
+        index = if rng.gen::<f64>() < p {
+            rng.gen_range(0..n)
+        } else {
+            (index + 1) % n
+        };
+    }
+}
+
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    // Linear interpolation, as numpy's default
+    let position = q * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+pub fn calculate_confidence(
+    returns: &[f64],
+    setting: &ConfidenceSetting,
+    risk_free: f64,
+    annual_days: i64,
+) -> ConfidenceStatistics {
+    let mut statistics = ConfidenceStatistics {
+        confidence: setting.confidence,
+        samples: setting.samples as i64,
+        ..Default::default()
+    };
+    if returns.len() < 2 || setting.samples == 0 {
+        return statistics;
+    }
+    let annual_days = annual_days as f64;
+
+    let mut rng = StdRng::seed_from_u64(setting.seed);
+    let mut sample = Vec::with_capacity(returns.len());
+    let mut sharpes = Vec::with_capacity(setting.samples);
+    let mut annual_returns = Vec::with_capacity(setting.samples);
+    let mut ddpercents = Vec::with_capacity(setting.samples);
+    for _ in 0..setting.samples {
+        resample(returns, setting.block_length, &mut rng, &mut sample);
+        let (sharpe, annual_return, ddpercent) = metrics(&sample, risk_free, annual_days);
+        sharpes.push(sharpe);
+        annual_returns.push(annual_return);
+        ddpercents.push(ddpercent);
+    }
+    let alpha = (1.0 - setting.confidence) / 2.0;
+    let interval = |values: &mut Vec<f64>| {
+        values.sort_by(f64::total_cmp);
+        (percentile(values, alpha), percentile(values, 1.0 - alpha))
+    };
+    (statistics.sharpe_ratio_lower, statistics.sharpe_ratio_upper) = interval(&mut sharpes);
+    (
+        statistics.annual_return_lower,
+        statistics.annual_return_upper,
+    ) = interval(&mut annual_returns);
+    (
+        statistics.max_ddpercent_lower,
+        statistics.max_ddpercent_upper,
+    ) = interval(&mut ddpercents);
+
+    // Probabilistic sharpe ratio works on the daily, not annualized, sharpe ratio
+    let (skew, kurtosis) = skew_kurtosis(returns);
+    let sharpe = finite(mean(returns) / std(returns));
+    let estimate_std = sharpe_std(sharpe, skew, kurtosis, returns.len());
+    statistics.probabilistic_sharpe = finite(norm_cdf(sharpe / estimate_std));
+
+    let trials = if setting.trial_sharpes.is_empty() {
+        setting.trials
+    } else {
+        Some(setting.trial_sharpes.len())
+    };
+    if let Some(trials) = trials.filter(|trials| *trials > 1) {
+        let trials_std = if setting.trial_sharpes.len() > 1 {
+            std(&setting.trial_sharpes) / annual_days.sqrt()
+        } else {
+            estimate_std
+        };
+        // Expected maximum of the sharpe ratios of trials without skill
+        let n = trials as f64;
+        let expected_max = trials_std
+            * ((1.0 - EULER_GAMMA) * norm_ppf(1.0 - 1.0 / n)
+                + EULER_GAMMA * norm_ppf(1.0 - 1.0 / (n * std::f64::consts::E)));
+        statistics.deflated_sharpe = Some(finite(norm_cdf((sharpe - expected_max) / estimate_std)));
+        statistics.trials = Some(trials as i64);
+    }
+    statistics
+}
@@ -0,0 +1,435 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use pyo3::{prelude::*, types::PyDict};
+use strum::Display;
+
+use super::{
+    artifact::BacktestArtifact, statistics::BacktestStatistics, to_dataframe, BacktestingEngine,
+    DailyResult,
+};
+use crate::trader::{
+    constant::{Direction, Offset_},
+    object::TradeData,
+};
+
+#[pyclass(eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum TradeDiffKind {
+    /// Only in the other run
+    #[strum(serialize = "新增")]
+    ADDED,
+    /// Only in the base run
+    #[strum(serialize = "删除")]
+    REMOVED,
+    /// At the same time and side in both runs, with a different price or volume
+    #[strum(serialize = "修改")]
+    CHANGED,
+}
+
+#[pymethods]
+impl TradeDiffKind {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+fn optional<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or("None".to_string(), |value| value.to_string())
+}
+
+/// A trade that differs between the runs, the side missing from a run is None
+#[pyclass(get_all)]
+#[derive(Debug, Clone)]
+pub struct TradeDiff {
+    pub kind: TradeDiffKind,
+    pub datetime: DateTime<Tz>,
+    pub direction: Direction,
+    pub offset: Offset_,
+    pub base_tradeid: Option<String>,
+    pub base_price: Option<f64>,
+    pub base_volume: Option<f64>,
+    pub other_tradeid: Option<String>,
+    pub other_price: Option<f64>,
+    pub other_volume: Option<f64>,
+}
+
+#[pymethods]
+impl TradeDiff {
+    fn __repr__(&self) -> String {
+        format!(
+            "TradeDiff({}, {}, {}, {}, {}@{} -> {}@{})",
+            self.kind,
+            self.datetime,
+            self.direction,
+            self.offset,
+            optional(self.base_volume),
+            optional(self.base_price),
+            optional(self.other_volume),
+            optional(self.other_price)
+        )
+    }
+}
+
+/// A stretch of time in which the positions of the runs differ
+#[pyclass(get_all)]
+#[derive(Debug, Clone)]
+pub struct PositionDivergence {
+    pub start: DateTime<Tz>,
+    /// When the positions are equal again, None if they never are
+    pub end: Option<DateTime<Tz>>,
+    /// Positions right after the divergence starts
+    pub base_pos: f64,
+    pub other_pos: f64,
+    pub max_difference: f64,
+}
+
+#[pymethods]
+impl PositionDivergence {
+    fn __repr__(&self) -> String {
+        format!(
+            "PositionDivergence({} - {}, {} vs {})",
+            self.start,
+            optional(self.end),
+            self.base_pos,
+            self.other_pos
+        )
+    }
+}
+
+/// What compare_runs needs from an engine or an artifact
+struct RunData {
+    trades: Vec<TradeData>,
+    daily_results: Vec<DailyResult>,
+    statistics: BacktestStatistics,
+}
+
+impl RunData {
+    fn extract(py: Python<'_>, run: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(engine) = run.downcast::<BacktestingEngine>() {
+            let mut engine = engine.try_borrow_mut()?;
+            // Statistics first, the daily pnl is calculated by them
+            let statistics = engine.rs_calculate_statistics(py, false);
+            Ok(RunData {
+                trades: engine.get_all_trades(),
+                daily_results: engine.get_all_daily_results(),
+                statistics,
+            })
+        } else if let Ok(artifact) = run.downcast::<BacktestArtifact>() {
+            let artifact = artifact.borrow();
+            Ok(RunData {
+                trades: artifact.get_all_trades(),
+                daily_results: artifact.get_all_daily_results(),
+                statistics: artifact.calculate_statistics(false),
+            })
+        } else {
+            Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                "只能比较BacktestingEngine或BacktestArtifact，实际为{}",
+                run.get_type().name()?
+            )))
+        }
+    }
+}
+
+fn signed_volume(trade: &TradeData) -> f64 {
+    match trade.direction {
+        Direction::SHORT => -trade.volume,
+        _ => trade.volume,
+    }
+}
+
+/// Pair the trades made at the same time on the same side, the rest are added or removed
+fn diff_trades(base: &[TradeData], other: &[TradeData], tolerance: f64) -> (Vec<TradeDiff>, usize) {
+    let mut groups: BTreeMap<DateTime<Tz>, (Vec<&TradeData>, Vec<&TradeData>)> = BTreeMap::new();
+    for trade in base {
+        groups.entry(trade.datetime).or_default().0.push(trade);
+    }
+    for trade in other {
+        groups.entry(trade.datetime).or_default().1.push(trade);
+    }
+
+    let diff = |kind, base: Option<&TradeData>, other: Option<&TradeData>| {
+        let trade = base.or(other).unwrap();
+        TradeDiff {
+            kind,
+            datetime: trade.datetime,
+            direction: trade.direction,
+            offset: trade.offset,
+            base_tradeid: base.map(|t| t.tradeid.trim().to_string()),
+            base_price: base.map(|t| t.price),
+            base_volume: base.map(|t| t.volume),
+            other_tradeid: other.map(|t| t.tradeid.trim().to_string()),
+            other_price: other.map(|t| t.price),
+            other_volume: other.map(|t| t.volume),
+        }
+    };
+    let mut diffs = Vec::new();
+    let mut unchanged = 0;
+    for (base_trades, mut other_trades) in groups.into_values() {
+        for trade in base_trades {
+            let matched = other_trades
+                .iter()
+                .position(|t| t.direction == trade.direction && t.offset == trade.offset);
+            match matched.map(|i| other_trades.remove(i)) {
+                Some(matched)
+                    if (matched.price - trade.price).abs() <= tolerance
+                        && (matched.volume - trade.volume).abs() <= tolerance =>
+                {
+                    unchanged += 1
+                }
+                Some(matched) => {
+                    diffs.push(diff(TradeDiffKind::CHANGED, Some(trade), Some(matched)))
+                }
+                None => diffs.push(diff(TradeDiffKind::REMOVED, Some(trade), None)),
+            }
+        }
+        for trade in other_trades {
+            diffs.push(diff(TradeDiffKind::ADDED, None, Some(trade)));
+        }
+    }
+    (diffs, unchanged)
+}
+
+/// Positions of both runs after every time either of them traded
+fn position_series(base: &[TradeData], other: &[TradeData]) -> Vec<(DateTime<Tz>, f64, f64)> {
+    let mut changes: BTreeMap<DateTime<Tz>, (f64, f64)> = BTreeMap::new();
+    for trade in base {
+        changes.entry(trade.datetime).or_default().0 += signed_volume(trade);
+    }
+    for trade in other {
+        changes.entry(trade.datetime).or_default().1 += signed_volume(trade);
+    }
+    let (mut base_pos, mut other_pos) = (0.0, 0.0);
+    changes
+        .into_iter()
+        .map(|(datetime, (base_change, other_change))| {
+            base_pos += base_change;
+            other_pos += other_change;
+            (datetime, base_pos, other_pos)
+        })
+        .collect()
+}
+
+fn divergences(positions: &[(DateTime<Tz>, f64, f64)], tolerance: f64) -> Vec<PositionDivergence> {
+    let mut divergences: Vec<PositionDivergence> = Vec::new();
+    let mut diverged = false;
+    for (datetime, base_pos, other_pos) in positions {
+        let difference = (base_pos - other_pos).abs();
+        if difference > tolerance {
+            if diverged {
+                let divergence = divergences.last_mut().unwrap();
+                divergence.max_difference = divergence.max_difference.max(difference);
+            } else {
+                divergences.push(PositionDivergence {
+                    start: *datetime,
+                    end: None,
+                    base_pos: *base_pos,
+                    other_pos: *other_pos,
+                    max_difference: difference,
+                });
+            }
+            diverged = true;
+        } else if diverged {
+            divergences.last_mut().unwrap().end = Some(*datetime);
+            diverged = false;
+        }
+    }
+    divergences
+}
+
+/// Net pnl of both runs on every day either of them has, NaN for a missing day
+fn daily_pnl(base: &[DailyResult], other: &[DailyResult]) -> Vec<(NaiveDate, f64, f64)> {
+    let mut days: BTreeMap<NaiveDate, (f64, f64)> = BTreeMap::new();
+    for daily_result in base {
+        days.entry(daily_result.date)
+            .or_insert((f64::NAN, f64::NAN))
+            .0 = daily_result.net_pnl;
+    }
+    for daily_result in other {
+        days.entry(daily_result.date)
+            .or_insert((f64::NAN, f64::NAN))
+            .1 = daily_result.net_pnl;
+    }
+    days.into_iter()
+        .map(|(date, (base, other))| (date, base, other))
+        .collect()
+}
+
+/// Numeric statistics of both runs, in the order of the base run.
+/// Statistics only one run has, like benchmark or confidence, are NaN in the other.
+fn statistics_delta(
+    py: Python<'_>,
+    base: &BacktestStatistics,
+    other: &BacktestStatistics,
+) -> PyResult<Vec<(String, f64, f64)>> {
+    let numbers = |statistics: &BacktestStatistics| -> PyResult<Vec<(&'static str, f64)>> {
+        Ok(statistics
+            .items(py)?
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.extract::<f64>(py).ok()?)))
+            .collect())
+    };
+    let base = numbers(base)?;
+    let other = numbers(other)?;
+    let mut rows: Vec<(String, f64, f64)> = base
+        .iter()
+        .map(|(key, value)| {
+            let other_value = other.iter().find(|(k, _)| k == key).map(|(_, v)| *v);
+            (key.to_string(), *value, other_value.unwrap_or(f64::NAN))
+        })
+        .collect();
+    for (key, value) in other {
+        if !base.iter().any(|(k, _)| *k == key) {
+            rows.push((key.to_string(), f64::NAN, value));
+        }
+    }
+    Ok(rows)
+}
+
+/// Differences between two runs, made by compare_runs
+#[pyclass]
+pub struct RunComparison {
+    /// Trades that were added, removed or changed, in time order
+    #[pyo3(get)]
+    trades: Vec<TradeDiff>,
+    #[pyo3(get)]
+    unchanged: usize,
+    #[pyo3(get)]
+    added: usize,
+    #[pyo3(get)]
+    removed: usize,
+    #[pyo3(get)]
+    changed: usize,
+    #[pyo3(get)]
+    divergences: Vec<PositionDivergence>,
+    positions: Vec<(DateTime<Tz>, f64, f64)>,
+    daily: Vec<(NaiveDate, f64, f64)>,
+    statistics: Vec<(String, f64, f64)>,
+}
+
+#[pymethods]
+impl RunComparison {
+    /// True when the trades and positions are the same
+    pub fn is_identical(&self) -> bool {
+        self.trades.is_empty() && self.divergences.is_empty()
+    }
+
+    /// Positions of both runs after every trade, indexed by datetime
+    pub fn get_position_series(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let results = PyDict::new(py);
+        results.set_item(
+            "datetime",
+            self.positions
+                .iter()
+                .map(|p| p.0.fixed_offset())
+                .collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "base_pos",
+            self.positions.iter().map(|p| p.1).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "other_pos",
+            self.positions.iter().map(|p| p.2).collect::<Vec<_>>(),
+        )?;
+        to_dataframe(py, results, "datetime")
+    }
+
+    /// Net pnl of both runs, their difference and its running total, indexed by date
+    pub fn get_daily_differences(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let difference: Vec<f64> = self.daily.iter().map(|d| d.2 - d.1).collect();
+        let mut total = 0.0;
+        let cumulative: Vec<f64> = difference
+            .iter()
+            .map(|d| {
+                if d.is_finite() {
+                    total += d;
+                }
+                total
+            })
+            .collect();
+        let results = PyDict::new(py);
+        results.set_item("date", self.daily.iter().map(|d| d.0).collect::<Vec<_>>())?;
+        results.set_item(
+            "base_net_pnl",
+            self.daily.iter().map(|d| d.1).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "other_net_pnl",
+            self.daily.iter().map(|d| d.2).collect::<Vec<_>>(),
+        )?;
+        results.set_item("difference", difference)?;
+        results.set_item("cumulative_difference", cumulative)?;
+        to_dataframe(py, results, "date")
+    }
+
+    /// Statistics side by side with other minus base, indexed by the statistic name
+    pub fn get_statistics_delta(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let results = PyDict::new(py);
+        results.set_item(
+            "statistic",
+            self.statistics
+                .iter()
+                .map(|s| s.0.clone())
+                .collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "base",
+            self.statistics.iter().map(|s| s.1).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "other",
+            self.statistics.iter().map(|s| s.2).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "delta",
+            self.statistics
+                .iter()
+                .map(|s| s.2 - s.1)
+                .collect::<Vec<_>>(),
+        )?;
+        to_dataframe(py, results, "statistic")
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RunComparison(unchanged={}, added={}, removed={}, changed={}, divergences={}, first_divergence={})",
+            self.unchanged,
+            self.added,
+            self.removed,
+            self.changed,
+            self.divergences.len(),
+            optional(self.divergences.first().map(|d| d.start))
+        )
+    }
+}
+
+/// Compare two runs, each a BacktestingEngine after run_backtesting or a BacktestArtifact.
+/// Prices and volumes closer than tolerance count as equal.
+#[pyfunction]
+#[pyo3(signature = (base, other, tolerance=1e-9))]
+pub fn compare_runs(
+    py: Python<'_>,
+    base: &Bound<'_, PyAny>,
+    other: &Bound<'_, PyAny>,
+    tolerance: f64,
+) -> PyResult<RunComparison> {
+    let base = RunData::extract(py, base)?;
+    let other = RunData::extract(py, other)?;
+
+    let (trades, unchanged) = diff_trades(&base.trades, &other.trades, tolerance);
+    let count = |kind| trades.iter().filter(|t| t.kind == kind).count();
+    let positions = position_series(&base.trades, &other.trades);
+    Ok(RunComparison {
+        unchanged,
+        added: count(TradeDiffKind::ADDED),
+        removed: count(TradeDiffKind::REMOVED),
+        changed: count(TradeDiffKind::CHANGED),
+        divergences: divergences(&positions, tolerance),
+        daily: daily_pnl(&base.daily_results, &other.daily_results),
+        statistics: statistics_delta(py, &base.statistics, &other.statistics)?,
+        trades,
+        positions,
+    })
+}
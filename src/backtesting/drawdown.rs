@@ -0,0 +1,168 @@
+use pyo3::{prelude::*, types::PyDict};
+
+use super::statistics::mean;
+
+/// A fall from a high of the balance until it's regained, times are dates or datetimes
+#[derive(Debug, Clone)]
+pub struct DrawdownEpisode<T> {
+    pub peak: T,
+    pub trough: T,
+    /// None if the balance never got back to the peak
+    pub recovery: Option<T>,
+    pub peak_balance: f64,
+    pub trough_balance: f64,
+    /// Periods (days or bars) from the peak to the recovery, or to the end when not recovered
+    pub length: i64,
+    /// Periods from the peak to the trough
+    pub decline: i64,
+    /// Periods from the trough to the recovery
+    pub recovery_time: Option<i64>,
+}
+
+impl<T> DrawdownEpisode<T> {
+    pub fn depth(&self) -> f64 {
+        self.trough_balance - self.peak_balance
+    }
+
+    pub fn ddpercent(&self) -> f64 {
+        self.depth() / self.peak_balance * 100.0
+    }
+}
+
+/// Drawdown and drawdown percent at every point
+pub fn underwater<T: Copy>(series: &[(T, f64)]) -> Vec<(T, f64, f64)> {
+    let mut highlevel = f64::MIN;
+    series
+        .iter()
+        .map(|(time, value)| {
+            highlevel = highlevel.max(*value);
+            (
+                *time,
+                value - highlevel,
+                (value - highlevel) / highlevel * 100.0,
+            )
+        })
+        .collect()
+}
+
+/// All episodes in time order
+pub fn drawdown_episodes<T: Copy>(series: &[(T, f64)]) -> Vec<DrawdownEpisode<T>> {
+    let mut episodes = Vec::new();
+    let mut peak = 0;
+    let mut trough: Option<usize> = None;
+    for (i, (_, value)) in series.iter().enumerate() {
+        if *value >= series[peak].1 {
+            if let Some(trough) = trough.take() {
+                episodes.push(DrawdownEpisode {
+                    peak: series[peak].0,
+                    trough: series[trough].0,
+                    recovery: Some(series[i].0),
+                    peak_balance: series[peak].1,
+                    trough_balance: series[trough].1,
+                    length: (i - peak) as i64,
+                    decline: (trough - peak) as i64,
+                    recovery_time: Some((i - trough) as i64),
+                });
+            }
+            peak = i;
+        } else if trough.is_none_or(|trough| *value < series[trough].1) {
+            trough = Some(i);
+        }
+    }
+    if let Some(trough) = trough {
+        episodes.push(DrawdownEpisode {
+            peak: series[peak].0,
+            trough: series[trough].0,
+            recovery: None,
+            peak_balance: series[peak].1,
+            trough_balance: series[trough].1,
+            length: (series.len() - 1 - peak) as i64,
+            decline: (trough - peak) as i64,
+            recovery_time: None,
+        });
+    }
+    episodes
+}
+
+/// The deepest episodes by drawdown percent
+pub fn top_episodes<T: Copy>(series: &[(T, f64)], top: usize) -> Vec<DrawdownEpisode<T>> {
+    let mut episodes = drawdown_episodes(series);
+    episodes.sort_by(|a, b| a.ddpercent().total_cmp(&b.ddpercent()));
+    episodes.truncate(top);
+    episodes
+}
+
+/// How often and how deep the balance is under water, drawdowns are in percent
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default)]
+pub struct DrawdownStatistics {
+    pub episode_count: i64,
+    pub max_ddpercent: f64,
+    /// Average depth of the episodes
+    pub average_drawdown: f64,
+    /// Average length of the episodes in periods
+    pub average_length: f64,
+    pub longest_length: i64,
+    /// Share of periods spent below a previous high
+    pub underwater_ratio: f64,
+    /// Root mean square of the drawdown percent
+    pub ulcer_index: f64,
+    /// Mean of the drawdown percent, as a positive number
+    pub pain_index: f64,
+}
+
+#[pymethods]
+impl DrawdownStatistics {
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("episode_count", self.episode_count)?;
+        dict.set_item("max_ddpercent", self.max_ddpercent)?;
+        dict.set_item("average_drawdown", self.average_drawdown)?;
+        dict.set_item("average_length", self.average_length)?;
+        dict.set_item("longest_length", self.longest_length)?;
+        dict.set_item("underwater_ratio", self.underwater_ratio)?;
+        dict.set_item("ulcer_index", self.ulcer_index)?;
+        dict.set_item("pain_index", self.pain_index)?;
+        Ok(dict.unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DrawdownStatistics(episode_count={}, max_ddpercent={:.2}, ulcer_index={:.4}, pain_index={:.4})",
+            self.episode_count, self.max_ddpercent, self.ulcer_index, self.pain_index
+        )
+    }
+}
+
+pub fn drawdown_statistics<T: Copy>(series: &[(T, f64)]) -> DrawdownStatistics {
+    if series.is_empty() {
+        return DrawdownStatistics::default();
+    }
+    let episodes = drawdown_episodes(series);
+    let ddpercent: Vec<f64> = underwater(series).iter().map(|(_, _, dd)| *dd).collect();
+    let mut statistics = DrawdownStatistics {
+        episode_count: episodes.len() as i64,
+        max_ddpercent: ddpercent.iter().cloned().fold(0.0, f64::min),
+        underwater_ratio: ddpercent.iter().filter(|dd| **dd < 0.0).count() as f64
+            / ddpercent.len() as f64,
+        ulcer_index: mean(&ddpercent.iter().map(|dd| dd * dd).collect::<Vec<_>>()).sqrt(),
+        pain_index: mean(&ddpercent.iter().map(|dd| dd.abs()).collect::<Vec<_>>()),
+        ..Default::default()
+    };
+    if !episodes.is_empty() {
+        statistics.average_drawdown = mean(
+            &episodes
+                .iter()
+                .map(|episode| episode.ddpercent())
+                .collect::<Vec<_>>(),
+        );
+        statistics.average_length = mean(
+            &episodes
+                .iter()
+                .map(|episode| episode.length as f64)
+                .collect::<Vec<_>>(),
+        );
+        statistics.longest_length = episodes.iter().map(|e| e.length).max().unwrap_or(0);
+    }
+    statistics
+}
@@ -0,0 +1,148 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use super::round_trip::VOLUME_EPSILON;
+use crate::trader::constant::Direction;
+
+/// Account state marked to market at every bar or tick, kept as columns
+#[derive(Default)]
+pub struct EquityCurve {
+    /// Position and its average entry price, updated by every fill
+    pos: f64,
+    avg_price: f64,
+    /// Closed pnl after commission and slippage
+    realized_pnl: f64,
+
+    pub timezone: Option<Tz>,
+    /// Nanoseconds since the epoch, so the column can be read as datetime64[ns]
+    pub datetime: Vec<i64>,
+    pub balance: Vec<f64>,
+    pub position: Vec<f64>,
+    pub unrealized_pnl: Vec<f64>,
+    pub margin: Vec<f64>,
+}
+
+impl EquityCurve {
+    pub fn add_trade(
+        &mut self,
+        direction: Direction,
+        price: f64,
+        volume: f64,
+        size: f64,
+        rate: f64,
+        slippage: f64,
+    ) {
+        self.realized_pnl -= volume * size * (price * rate + slippage);
+
+        let change = if direction == Direction::LONG {
+            volume
+        } else {
+            -volume
+        };
+        if self.pos * change >= 0.0 {
+            // Adding to the position, or opening one
+            let pos = self.pos + change;
+            self.avg_price = (self.avg_price * self.pos + price * change) / pos;
+            self.pos = pos;
+            return;
+        }
+        let closed = change.abs().min(self.pos.abs());
+        self.realized_pnl += (price - self.avg_price) * closed * size * self.pos.signum();
+        self.pos += change;
+        if self.pos.abs() <= VOLUME_EPSILON {
+            // Fractional fills may leave a dust position instead of none
+            self.pos = 0.0;
+            self.avg_price = 0.0;
+        } else if self.pos * change > 0.0 {
+            // Reversed, the rest of the fill opens a new position
+            self.avg_price = price;
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        datetime: DateTime<Tz>,
+        price: f64,
+        capital: f64,
+        size: f64,
+        margin_rate: f64,
+    ) {
+        self.timezone.get_or_insert(datetime.timezone());
+        self.datetime
+            .push(datetime.timestamp_nanos_opt().unwrap_or_default());
+        self.balance.push(capital + self.realized_pnl);
+        self.position.push(self.pos);
+        self.unrealized_pnl
+            .push((price - self.avg_price) * self.pos * size);
+        self.margin
+            .push(self.pos.abs() * price * size * margin_rate);
+    }
+
+    /// Balance plus unrealized pnl
+    pub fn equity(&self) -> Vec<f64> {
+        self.balance
+            .iter()
+            .zip(&self.unrealized_pnl)
+            .map(|(balance, pnl)| balance + pnl)
+            .collect()
+    }
+
+    /// Equity with the datetime of every bar or tick
+    pub fn series(&self) -> Vec<(DateTime<Tz>, f64)> {
+        let tz = self.timezone.unwrap_or(Tz::UTC);
+        self.datetime
+            .iter()
+            .map(|ns| DateTime::from_timestamp_nanos(*ns).with_timezone(&tz))
+            .zip(self.equity())
+            .collect()
+    }
+
+    /// Max drawdown and max drawdown percent of the equity, None if nothing was recorded
+    pub fn max_drawdown(&self) -> Option<(f64, f64)> {
+        if self.balance.is_empty() {
+            return None;
+        }
+        let mut highlevel = f64::MIN;
+        let mut max_drawdown = 0.0;
+        let mut max_ddpercent = 0.0;
+        for equity in self.equity() {
+            highlevel = highlevel.max(equity);
+            let drawdown = equity - highlevel;
+            if drawdown < max_drawdown {
+                max_drawdown = drawdown;
+            }
+            let ddpercent = drawdown / highlevel * 100.0;
+            if ddpercent < max_ddpercent {
+                max_ddpercent = ddpercent;
+            }
+        }
+        Some((max_drawdown, max_ddpercent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn fractional_fills_close_the_position() {
+        let mut curve = EquityCurve::default();
+        let datetime = Tz::Asia__Shanghai
+            .with_ymd_and_hms(2024, 1, 2, 9, 0, 0)
+            .unwrap();
+        curve.add_trade(Direction::LONG, 100.0, 0.1, 1.0, 0.0, 0.0);
+        curve.add_trade(Direction::LONG, 100.0, 0.2, 1.0, 0.0, 0.0);
+        curve.add_trade(Direction::SHORT, 101.0, 0.3, 1.0, 0.0, 0.0);
+        curve.record(datetime, 101.0, 1000.0, 1.0, 1.0);
+        assert_eq!(curve.position[0], 0.0);
+        assert_eq!(curve.unrealized_pnl[0], 0.0);
+
+        curve.add_trade(Direction::SHORT, 105.0, 0.1, 1.0, 0.0, 0.0);
+        curve.record(datetime, 104.0, 1000.0, 1.0, 1.0);
+        assert_eq!(curve.position[1], -0.1);
+        assert!((curve.unrealized_pnl[1] - 0.1).abs() < 1e-12);
+        assert!((curve.balance[1] - 1000.3).abs() < 1e-9);
+    }
+}
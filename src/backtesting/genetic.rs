@@ -0,0 +1,167 @@
+/*! Genetic algorithm searching the grid of an optimization setting, the fitness of each combination is evaluated once. */
+
+use std::collections::{HashMap, HashSet};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Index of the chosen value in the list of every parameter
+pub type Genes = Vec<usize>;
+
+pub struct GeneticSetting {
+    pub population_size: usize,
+    /// Probability that a pair of parents is crossed instead of copied
+    pub crossover_rate: f64,
+    /// Probability that each gene of an offspring is replaced by a random value
+    pub mutation_rate: f64,
+    /// Individuals competing for each parent slot
+    pub tournament_size: usize,
+    pub seed: u64,
+}
+
+pub struct GeneticSearch {
+    /// Number of values of every parameter
+    sizes: Vec<usize>,
+    setting: GeneticSetting,
+    rng: StdRng,
+    fitness: HashMap<Genes, f64>,
+}
+
+impl GeneticSearch {
+    pub fn new(sizes: Vec<usize>, setting: GeneticSetting) -> Self {
+        let rng = StdRng::seed_from_u64(setting.seed);
+        GeneticSearch {
+            sizes,
+            setting,
+            rng,
+            fitness: HashMap::new(),
+        }
+    }
+
+    /// Number of parameter combinations in the whole grid
+    pub fn space(&self) -> usize {
+        self.sizes
+            .iter()
+            .fold(1usize, |space, size| space.saturating_mul(*size))
+    }
+
+    /// True once every combination of the grid has been evaluated
+    pub fn exhausted(&self) -> bool {
+        self.fitness.len() >= self.space()
+    }
+
+    pub fn evaluated(&self) -> usize {
+        self.fitness.len()
+    }
+
+    pub fn record(&mut self, genes: Genes, fitness: f64) {
+        self.fitness.insert(genes, fitness);
+    }
+
+    fn fitness(&self, genes: &Genes) -> f64 {
+        self.fitness
+            .get(genes)
+            .copied()
+            .unwrap_or(f64::NEG_INFINITY)
+    }
+
+    pub fn best(&self) -> Option<f64> {
+        self.fitness.values().copied().reduce(f64::max)
+    }
+
+    fn random_individual(&mut self) -> Genes {
+        let sizes = self.sizes.clone();
+        sizes
+            .into_iter()
+            .map(|size| self.rng.gen_range(0..size))
+            .collect()
+    }
+
+    /// Distinct random individuals, fewer when the grid is smaller than the population
+    pub fn initial_population(&mut self) -> Vec<Genes> {
+        let size = self.setting.population_size.min(self.space());
+        let mut population = HashSet::new();
+        let mut attempts = 0;
+        while population.len() < size && attempts < size * 20 {
+            population.insert(self.random_individual());
+            attempts += 1;
+        }
+        let mut population: Vec<Genes> = population.into_iter().collect();
+        // HashSet order isn't stable across runs, the seed has to decide everything
+        population.sort();
+        population
+    }
+
+    /// Distinct individuals whose fitness isn't known yet, in order of appearance
+    pub fn unevaluated(&self, individuals: &[Genes]) -> Vec<Genes> {
+        let mut seen = HashSet::new();
+        individuals
+            .iter()
+            .filter(|genes| !self.fitness.contains_key(*genes) && seen.insert(*genes))
+            .cloned()
+            .collect()
+    }
+
+    fn tournament<'a>(&mut self, population: &'a [Genes]) -> &'a Genes {
+        let mut winner = &population[self.rng.gen_range(0..population.len())];
+        for _ in 1..self.setting.tournament_size {
+            let challenger = &population[self.rng.gen_range(0..population.len())];
+            if self.fitness(challenger) > self.fitness(winner) {
+                winner = challenger;
+            }
+        }
+        winner
+    }
+
+    /// Genes between two random cut points are swapped
+    fn crossover(&mut self, a: &mut Genes, b: &mut Genes) {
+        if a.len() < 2 {
+            return;
+        }
+        let mut start = self.rng.gen_range(0..a.len());
+        let mut end = self.rng.gen_range(0..a.len());
+        if start > end {
+            std::mem::swap(&mut start, &mut end);
+        }
+        for i in start..=end {
+            std::mem::swap(&mut a[i], &mut b[i]);
+        }
+    }
+
+    fn mutate(&mut self, genes: &mut Genes) {
+        for (gene, size) in genes.iter_mut().zip(self.sizes.iter()) {
+            if self.rng.gen_bool(self.setting.mutation_rate) {
+                *gene = self.rng.gen_range(0..*size);
+            }
+        }
+    }
+
+    /// As many children as the population, bred from parents chosen by tournament
+    pub fn offspring(&mut self, population: &[Genes]) -> Vec<Genes> {
+        let mut offspring = Vec::with_capacity(population.len() + 1);
+        while offspring.len() < population.len() {
+            let mut a = self.tournament(population).clone();
+            let mut b = self.tournament(population).clone();
+            if self.rng.gen_bool(self.setting.crossover_rate) {
+                self.crossover(&mut a, &mut b);
+            }
+            self.mutate(&mut a);
+            self.mutate(&mut b);
+            offspring.push(a);
+            offspring.push(b);
+        }
+        offspring.truncate(population.len());
+        offspring
+    }
+
+    /// The fittest distinct individuals survive into the next generation
+    pub fn select(&self, individuals: Vec<Genes>) -> Vec<Genes> {
+        let mut seen = HashSet::new();
+        let mut survivors: Vec<Genes> = individuals
+            .into_iter()
+            .filter(|genes| seen.insert(genes.clone()))
+            .collect();
+        survivors.sort_by(|a, b| self.fitness(b).total_cmp(&self.fitness(a)));
+        survivors.truncate(self.setting.population_size);
+        survivors
+    }
+}
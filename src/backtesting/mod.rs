@@ -1,31 +1,82 @@
+pub mod artifact;
 pub mod base;
+pub mod benchmark;
+pub mod confidence;
+pub mod diff;
+pub mod drawdown;
+pub mod equity;
+pub mod genetic;
+pub mod montecarlo;
+pub mod optimization;
+pub mod periodic;
+pub mod report;
+pub mod round_trip;
+pub mod sampler;
+pub mod snapshot;
+pub mod statistics;
+pub mod validation;
+pub mod walkforward;
 
 use std::{
-    collections::{BTreeMap, LinkedList},
+    collections::{BTreeMap, HashSet, LinkedList},
     ops::Deref,
+    path::PathBuf,
     str::FromStr,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
 };
 
 pub use base::BacktestingMode;
 
+use artifact::{save_artifact, ArtifactData, ArtifactParameters, BacktestArtifact};
 use base::{EngineType, StopOrder, StopOrderStatus, INTERVAL_DELTA_MAP, STOPORDER_PREFIX};
-use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use benchmark::{calculate_benchmark_statistics, excess_curve, Benchmark, BenchmarkStatistics};
+use chrono::{
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday,
+};
 use chrono_tz::Tz;
+use confidence::{calculate_confidence, ConfidenceSetting, ConfidenceStatistics};
+use diff::{compare_runs, PositionDivergence, RunComparison, TradeDiff, TradeDiffKind};
+use drawdown::{
+    drawdown_statistics, top_episodes, underwater, DrawdownEpisode, DrawdownStatistics,
+};
+use equity::EquityCurve;
+use genetic::{GeneticSearch, GeneticSetting};
+use montecarlo::{run_monte_carlo, MonteCarloResult};
+use optimization::{engine_parameters, sort_results, target_value, Progress, WorkerPool};
+use periodic::{
+    daily_balance, log_returns, monthly_returns, rolling_statistics, yearly_statistics,
+};
 use pyo3::{
     prelude::*,
-    types::{PyDict, PyList},
+    types::{PyBytes, PyDict, PyList},
 };
+use report::Report;
+use round_trip::{
+    match_round_trips, round_trip_statistics, MatchingMethod, RoundTrip, RoundTripStatistics,
+    TradeTrace,
+};
+use sampler::{sample_settings, ParameterRange};
+use snapshot::{read_snapshot, write_snapshot};
+use statistics::{calculate_statistics, BacktestStatistics};
+use validation::{DataIssue, DataIssueKind, ValidationReport, ValidationSetting};
+use walkforward::{run_walk_forward, WalkForwardResult, WalkForwardWindow};
 
 use crate::trader::{
     constant::{Direction, Interval, Offset_, OrderType, Status},
-    database::get_database,
+    database::{get_database, get_database_by_config, DatabaseConfig},
+    database_impl::{with_retry, BaseDatabase, DatabaseError, MemoryDatabase},
     object::{BarData, MixData, OrderData, TickData, TradeData},
+    setting::try_settings,
     utility::{extract_vt_symbol, round_to},
 };
 
 static GLOBAL_HISTORY_DATA: Mutex<LinkedList<MixData>> = Mutex::new(LinkedList::new());
 static GLOBAL_HISTORY_DATA_KEY: Mutex<String> = Mutex::new(String::new());
+/// Number of times validate_data has fixed history data, so that every fix gets its own key
+static FIXED_HISTORY_DATA_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 #[pyclass]
 pub struct BacktestingEngine {
@@ -55,6 +106,12 @@ pub struct BacktestingEngine {
     half_life: i64,
     #[pyo3(get, set)]
     mode: BacktestingMode,
+    /// Mark the account to market at every bar or tick, not only at the end of the day
+    #[pyo3(get, set)]
+    record_equity: bool,
+    /// Margin as a ratio of the position value
+    #[pyo3(get, set)]
+    margin_rate: f64,
 
     #[pyo3(get, set)]
     strategy_class: Option<PyObject>,
@@ -78,14 +135,27 @@ pub struct BacktestingEngine {
 
     trade_count: Mutex<i64>,
     trades: Mutex<BTreeMap<String, Arc<Mutex<TradeData>>>>,
+    trade_trace: Mutex<TradeTrace>,
+    equity: Mutex<EquityCurve>,
 
     logs: Mutex<Vec<String>>,
 
     daily_results: Mutex<BTreeMap<NaiveDate, DailyResult>>,
+    /// Daily pnl is calculated once per run, by calculate_result or calculate_statistics
+    daily_calculated: bool,
     #[pyo3(get, set)]
     daily_df: Option<PyObject>,
 
     rs_pyfunc_output: Option<PyObject>,
+
+    database: Option<Arc<dyn BaseDatabase>>,
+    #[pyo3(get)]
+    database_config: Option<DatabaseConfig>,
+
+    benchmark: Option<Benchmark>,
+    confidence: Option<ConfidenceSetting>,
+    /// Set once validate_data has fixed the loaded history data of this engine
+    fixed_data_id: Option<usize>,
 }
 
 #[pymethods]
@@ -100,13 +170,14 @@ impl BacktestingEngine {
     }
 
     #[new]
+    #[pyo3(signature = (database=None))]
     #[allow(unexpected_cfgs)]
-    pub fn __new__() -> Self {
+    pub fn __new__(database: Option<&Bound<'_, PyAny>>) -> PyResult<Self> {
         #[cfg(Py_LIMITED_API)]
         {
             println!("您正在使用通用版，兼容Python3.7以上所有版本，但是会降低运行性能。建议拉取源代码自行编译，或下载针对特定Python版本的whl包并通过pip安装");
         }
-        BacktestingEngine {
+        let mut engine = BacktestingEngine {
             vt_symbol: "".to_string(),
             symbol: "".to_string(),
             exchange: "".to_string(),
@@ -121,6 +192,8 @@ impl BacktestingEngine {
             annual_days: 240,
             half_life: 120,
             mode: BacktestingMode::BAR,
+            record_equity: false,
+            margin_rate: 1.0,
 
             strategy_class: None,
             strategy: None,
@@ -141,14 +214,28 @@ impl BacktestingEngine {
 
             trade_count: Mutex::new(0),
             trades: Mutex::new(BTreeMap::new()),
+            trade_trace: Mutex::new(TradeTrace::default()),
+            equity: Mutex::new(EquityCurve::default()),
 
             logs: Mutex::new(Vec::new()),
 
             daily_results: Mutex::new(BTreeMap::new()),
+            daily_calculated: false,
             daily_df: None,
 
             rs_pyfunc_output: None,
+
+            database: None,
+            database_config: None,
+
+            benchmark: None,
+            confidence: None,
+            fixed_data_id: None,
+        };
+        if let Some(database) = database {
+            engine.set_database(database)?;
         }
+        Ok(engine)
     }
 
     pub fn clear_data(&mut self) {
@@ -167,12 +254,15 @@ impl BacktestingEngine {
 
         *self.trade_count.lock().unwrap() = 0;
         self.trades.lock().unwrap().clear();
+        *self.trade_trace.lock().unwrap() = TradeTrace::default();
+        *self.equity.lock().unwrap() = EquityCurve::default();
 
         self.logs.lock().unwrap().clear();
         self.daily_results.lock().unwrap().clear();
+        self.daily_calculated = false;
     }
 
-    #[pyo3(signature = (vt_symbol,interval,start,rate,slippage,size,pricetick,capital,end=NaiveDateTime::default(),mode="BAR",risk_free=0.0,annual_days=240,half_life=120))]
+    #[pyo3(signature = (vt_symbol,interval,start,rate,slippage,size,pricetick,capital,end=NaiveDateTime::default(),mode="BAR",risk_free=0.0,annual_days=240,half_life=120,record_equity=false,margin_rate=1.0))]
     pub fn set_parameters(
         &mut self,
         vt_symbol: &str,
@@ -188,6 +278,8 @@ impl BacktestingEngine {
         risk_free: f64,
         annual_days: i64,
         half_life: i64,
+        record_equity: bool,
+        margin_rate: f64,
     ) {
         self.mode = BacktestingMode::from_str(mode).expect("mode字段只能是BAR,TICK中的一个");
         self.vt_symbol = vt_symbol.to_string();
@@ -208,20 +300,13 @@ impl BacktestingEngine {
         if end == NaiveDateTime::default() {
             end = Local::now().naive_local();
         }
-        self.end = Local
-            .from_local_datetime(&end)
-            .unwrap()
-            .with_hour(23)
-            .unwrap()
-            .with_minute(59)
-            .unwrap()
-            .with_second(59)
-            .unwrap()
-            .naive_local();
+        self.end = end.date().and_hms_opt(23, 59, 59).unwrap();
 
         self.risk_free = risk_free;
         self.annual_days = annual_days;
         self.half_life = half_life;
+        self.record_equity = record_equity;
+        self.margin_rate = margin_rate;
     }
 
     pub fn add_strategy(
@@ -249,18 +334,41 @@ impl BacktestingEngine {
     }
 
     pub fn rs_get_history_data_key(&self) -> String {
-        format!(
+        let mut key = format!(
             "{} {} {} {} {}",
             self.symbol,
             self.exchange,
             self.interval.unwrap(),
             self.start,
             self.end
-        )
+        );
+        if let Some(config) = self.database_config.as_ref() {
+            key += &format!(" {}", config.__repr__());
+        }
+        key
+    }
+
+    /// Use the given database instead of the one configured in vt_setting.json.
+    /// database is either a MemoryDatabase or a DatabaseConfig.
+    pub fn set_database(&mut self, database: &Bound<'_, PyAny>) -> PyResult<()> {
+        if let Ok(memory) = database.extract::<MemoryDatabase>() {
+            self.database = Some(Arc::new(memory));
+            self.database_config = None;
+        } else {
+            let config = database.extract::<DatabaseConfig>()?;
+            self.database = Some(get_database_by_config(&config)?);
+            self.database_config = Some(config);
+        }
+        Ok(())
     }
 
     pub fn load_data(&mut self, py: Python<'_>) -> PyResult<()> {
-        if *GLOBAL_HISTORY_DATA_KEY.lock().unwrap() == self.rs_get_history_data_key() {
+        if reusable_history_data(
+            &GLOBAL_HISTORY_DATA_KEY.lock().unwrap(),
+            &self.rs_get_history_data_key(),
+            self.history_data_cacheable(),
+            self.fixed_data_key().as_deref(),
+        ) {
             return Ok(());
         }
         self.output(py, "开始加载历史数据");
@@ -271,9 +379,12 @@ impl BacktestingEngine {
             self.output(py, "起始日期必须小于结束日期");
             return Ok(());
         }
+        // The key only comes back once the load has succeeded, a failed or cancelled
+        // load must not leave it pointing at the emptied data
+        GLOBAL_HISTORY_DATA_KEY.lock().unwrap().clear();
         GLOBAL_HISTORY_DATA.lock().unwrap().clear();
 
-        // Load 30 days of data each time and allow for progress update
+        // Split the range into chunks, which are loaded concurrently and merged in order
         let total_days = (self.end - self.start).num_days();
         let progress_days = i64::max(total_days / 10, 1);
         let progress_delta = TimeDelta::days(progress_days);
@@ -282,52 +393,187 @@ impl BacktestingEngine {
             .unwrap()
             .clone();
 
+        let mut ranges: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
         let mut start = self.start;
         let mut end = self.start + progress_delta;
-        let mut progress: f64 = 0.0;
-
         while start < self.end {
-            py.check_signals()?;
-            let progress_bar = "#".repeat((progress * 10.0 + 1.0) as usize);
-            self.output(
-                py,
-                &format!(
-                    "加载进度：{progress_bar} [{progress:.0}%]",
-                    progress_bar = progress_bar,
-                    progress = progress * 100.0
-                ),
-            );
-
             end = NaiveDateTime::min(end, self.end); // Make sure end time stays within set range
+            ranges.push((start, end));
+            start = end + interval_delta;
+            end += progress_delta
+        }
 
-            if self.mode == BacktestingMode::BAR {
-                let mut data: LinkedList<MixData> = load_bar_data(
-                    &self.symbol,
-                    &self.exchange,
-                    self.interval.unwrap(),
-                    start,
-                    end,
-                );
-                GLOBAL_HISTORY_DATA.lock().unwrap().append(&mut data);
-            } else {
-                let mut data: LinkedList<MixData> =
-                    load_tick_data(&self.symbol, &self.exchange, start, end);
-                GLOBAL_HISTORY_DATA.lock().unwrap().append(&mut data);
+        let database = self.database()?;
+        let (symbol, exchange) = (self.symbol.as_str(), self.exchange.as_str());
+        let (interval, mode) = (self.interval.unwrap(), self.mode);
+        let concurrency = usize::max(try_settings()?.database_concurrency as usize, 1);
+        let next_chunk = AtomicUsize::new(0);
+        let cancelled = AtomicBool::new(false);
+        let mut chunks: Vec<LinkedList<MixData>> = vec![LinkedList::new(); ranges.len()];
+
+        let result = std::thread::scope(|s| -> PyResult<()> {
+            let (tx, mut rx) =
+                mpsc::channel::<(usize, Result<LinkedList<MixData>, DatabaseError>)>();
+            for _ in 0..usize::min(concurrency, ranges.len()) {
+                let tx = tx.clone();
+                let (database, ranges) = (database.clone(), &ranges);
+                let (next_chunk, cancelled) = (&next_chunk, &cancelled);
+                s.spawn(move || loop {
+                    let i = next_chunk.fetch_add(1, Ordering::Relaxed);
+                    if i >= ranges.len() || cancelled.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let (start, end) = ranges[i];
+                    let data = if mode == BacktestingMode::BAR {
+                        load_bar_data(database.clone(), symbol, exchange, interval, start, end)
+                    } else {
+                        load_tick_data(database.clone(), symbol, exchange, start, end)
+                    };
+                    if tx.send((i, data)).is_err() {
+                        break;
+                    }
+                });
             }
+            drop(tx);
+
+            let mut finished = 0;
+            let mut rows = 0;
+            loop {
+                // Release the GIL while waiting, loader threads never need it
+                let received;
+                (rx, received) = py.allow_threads(move || {
+                    let received = rx.recv();
+                    (rx, received)
+                });
+                let Ok((i, data)) = received else {
+                    break;
+                };
+                let data = match data {
+                    Ok(data) => data,
+                    Err(e) => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return Err(e.into());
+                    }
+                };
+                finished += 1;
+                rows += data.len();
+                chunks[i] = data;
 
-            progress += progress_days as f64 / total_days as f64;
-            progress = f64::min(progress, 1.0);
+                let progress = finished as f64 / ranges.len() as f64;
+                let progress_bar = "#".repeat((progress * 10.0 + 1.0) as usize);
+                self.output(
+                    py,
+                    &format!(
+                        "加载进度：{progress_bar} [{progress:.0}%]，已接收数据：{rows}条",
+                        progress_bar = progress_bar,
+                        progress = progress * 100.0,
+                        rows = rows
+                    ),
+                );
+                if let Err(e) = py.check_signals() {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+            Ok(())
+        });
+        result?;
 
-            start = end + interval_delta;
-            end += progress_delta
+        let mut history_data = GLOBAL_HISTORY_DATA.lock().unwrap();
+        for mut data in chunks {
+            history_data.append(&mut data);
         }
+        drop(history_data);
 
         let len = GLOBAL_HISTORY_DATA.lock().unwrap().len();
         self.output(py, format!("历史数据加载完成，数据量：{}", len).as_str());
-        *GLOBAL_HISTORY_DATA_KEY.lock().unwrap() = self.rs_get_history_data_key();
+        if self.history_data_cacheable() {
+            *GLOBAL_HISTORY_DATA_KEY.lock().unwrap() = self.rs_get_history_data_key();
+        } else {
+            GLOBAL_HISTORY_DATA_KEY.lock().unwrap().clear();
+        }
         Ok(())
     }
 
+    /// Check the loaded history data, and optionally fix it in place before run_backtesting.
+    /// sessions is a list of (start, end) times used to find missing minute bars, they open on
+    /// weekdays (Monday is 0) except on holidays. Fixed data is only reused by this engine's loads.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (sessions=Vec::new(),return_sigma=10.0,volume_sigma=10.0,dedupe=false,drop_invalid=false,forward_fill=false,weekdays=vec![0, 1, 2, 3, 4],holidays=HashSet::new()))]
+    pub fn validate_data(
+        &mut self,
+        py: Python<'_>,
+        sessions: Vec<(NaiveTime, NaiveTime)>,
+        return_sigma: f64,
+        volume_sigma: f64,
+        dedupe: bool,
+        drop_invalid: bool,
+        forward_fill: bool,
+        weekdays: Vec<u8>,
+        holidays: HashSet<NaiveDate>,
+    ) -> PyResult<ValidationReport> {
+        let weekdays = weekdays
+            .into_iter()
+            .map(Weekday::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| pyo3::exceptions::PyValueError::new_err("weekdays只能是0到6的整数"))?;
+        self.output(py, "开始检查历史数据");
+        let setting = ValidationSetting {
+            sessions,
+            weekdays,
+            holidays,
+            return_sigma,
+            volume_sigma,
+        };
+
+        let mut history_data = GLOBAL_HISTORY_DATA.lock().unwrap();
+        let mut report = validation::validate(&history_data, self.interval, &setting);
+        self.output(
+            py,
+            &format!(
+                "数据量：{}，重复：{}，倒序：{}，价格为0：{}，OHLC错误：{}，收益率异常：{}，成交量异常：{}，缺失：{}处共{}根",
+                report.total,
+                report.duplicates,
+                report.reversed,
+                report.zero_prices,
+                report.ohlc_errors,
+                report.outlier_returns,
+                report.volume_anomalies,
+                report.gaps,
+                report.missing_bars
+            ),
+        );
+
+        if dedupe || drop_invalid || forward_fill {
+            // Other engines loading the same key must get the data of the database,
+            // while the optimizations of this engine keep running on the fixed one
+            self.fixed_data_id = Some(FIXED_HISTORY_DATA_COUNT.fetch_add(1, Ordering::Relaxed));
+            *GLOBAL_HISTORY_DATA_KEY.lock().unwrap() = self.fixed_data_key().unwrap();
+            let data = std::mem::take(&mut *history_data);
+            *history_data = validation::fix(
+                data,
+                self.interval,
+                &setting,
+                &mut report,
+                dedupe,
+                drop_invalid,
+                forward_fill,
+            );
+            self.output(
+                py,
+                &format!(
+                    "去重：{}，删除：{}，填充：{}，修复后数据量：{}",
+                    report.deduped,
+                    report.dropped,
+                    report.filled,
+                    history_data.len()
+                ),
+            );
+        }
+        self.output(py, "历史数据检查完成");
+        Ok(report)
+    }
+
     pub fn run_backtesting(&self, py: Python<'_>) -> PyResult<()> {
         self.strategy
             .as_ref()
@@ -393,6 +639,671 @@ impl BacktestingEngine {
         if self.trades.lock().unwrap().len() == 0 {
             self.output(py, "回测成交记录为空");
         }
+        self.calculate_daily_results();
+
+        self.daily_df = Some(daily_dataframe(
+            py,
+            self.daily_results.lock().unwrap().values(),
+        )?);
+
+        self.output(py, "逐日盯市盈亏计算完成");
+        Ok(self.daily_df.as_ref().unwrap().clone_ref(py))
+    }
+
+    /// Statistics of the daily results, without pandas.
+    /// Daily pnl is calculated first if calculate_result hasn't been called.
+    #[pyo3(signature = (output=true))]
+    pub fn rs_calculate_statistics(&mut self, py: Python<'_>, output: bool) -> BacktestStatistics {
+        self.output(py, "开始计算策略统计指标");
+        self.calculate_daily_results();
+
+        let (statistics, positive_balance) = calculate_statistics(
+            self.daily_results.lock().unwrap().values(),
+            self.capital,
+            self.risk_free,
+            self.annual_days,
+            self.half_life,
+        );
+        let (statistics, positive_balance) = match self.equity.lock().unwrap().max_drawdown() {
+            Some((max_drawdown, max_ddpercent)) => (
+                BacktestStatistics {
+                    intraday_max_drawdown: Some(max_drawdown),
+                    intraday_max_ddpercent: Some(max_ddpercent),
+                    ..statistics
+                },
+                positive_balance,
+            ),
+            None => (statistics, positive_balance),
+        };
+        let statistics = match self.benchmark.as_ref() {
+            Some(benchmark) if positive_balance => BacktestStatistics {
+                benchmark: Some(calculate_benchmark_statistics(
+                    &benchmark.name,
+                    &benchmark.align(self.daily_results.lock().unwrap().values(), self.capital),
+                    self.risk_free,
+                    self.annual_days,
+                )),
+                ..statistics
+            },
+            _ => statistics,
+        };
+        let statistics = match self.confidence.as_ref() {
+            Some(setting) if positive_balance => {
+                let balance =
+                    daily_balance(self.daily_results.lock().unwrap().values(), self.capital);
+                BacktestStatistics {
+                    confidence: Some(calculate_confidence(
+                        &log_returns(&balance, self.capital),
+                        setting,
+                        self.risk_free,
+                        self.annual_days,
+                    )),
+                    ..statistics
+                }
+            }
+            _ => statistics,
+        };
+        if !positive_balance {
+            self.output(py, "回测中出现爆仓（资金小于等于0），无法计算策略统计指标");
+        }
+        if output {
+            for line in statistics.report_lines() {
+                self.output(py, &line);
+            }
+        }
+        self.output(py, "策略统计指标计算完成");
+        statistics
+    }
+
+    /// Compare the strategy with the bars of a vt_symbol loaded from the database,
+    /// or with daily returns given as a dict of date to return. Call it after set_parameters.
+    #[pyo3(signature = (vt_symbol=None, returns=None, interval="d"))]
+    pub fn set_benchmark(
+        &mut self,
+        py: Python<'_>,
+        vt_symbol: Option<&str>,
+        returns: Option<BTreeMap<NaiveDate, f64>>,
+        interval: &str,
+    ) -> PyResult<()> {
+        self.benchmark = match (vt_symbol, returns) {
+            (Some(vt_symbol), _) => {
+                let interval = Interval::from_str(interval).map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err(
+                        "interval字段只能是1m,1h,d,w,tick中的一个",
+                    )
+                })?;
+                let (symbol, exchange) = extract_vt_symbol(vt_symbol);
+                // Start a few days earlier, so the first trading day has a return
+                let bars = load_bar_data(
+                    self.database()?,
+                    &symbol,
+                    &exchange,
+                    interval,
+                    self.start - TimeDelta::days(15),
+                    self.end,
+                )?;
+                let benchmark = Benchmark::from_bars(
+                    vt_symbol,
+                    bars.into_iter().filter_map(|data| match data {
+                        MixData::BarData(bar) => Some(bar),
+                        MixData::TickData(_) => None,
+                    }),
+                );
+                if benchmark.returns.is_empty() {
+                    self.output(py, &format!("基准{}没有历史数据", vt_symbol));
+                }
+                Some(benchmark)
+            }
+            (None, Some(returns)) => Some(Benchmark {
+                name: "自定义".to_string(),
+                returns,
+            }),
+            (None, None) => None,
+        };
+        Ok(())
+    }
+
+    /// Add bootstrap confidence intervals, the probabilistic sharpe ratio and, with the number
+    /// of trials (or their sharpe ratios) from an optimization, the deflated sharpe ratio
+    /// to the statistics. samples=0 turns it off.
+    #[pyo3(signature = (samples=1000, confidence=0.95, block_length=5.0, seed=0, trials=None, trial_sharpes=None))]
+    pub fn set_confidence(
+        &mut self,
+        samples: usize,
+        confidence: f64,
+        block_length: f64,
+        seed: u64,
+        trials: Option<usize>,
+        trial_sharpes: Option<Vec<f64>>,
+    ) -> PyResult<()> {
+        if !(confidence > 0.0 && confidence < 1.0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "confidence必须在0和1之间，实际为{}",
+                confidence
+            )));
+        }
+        self.confidence = (samples > 0).then(|| ConfidenceSetting {
+            samples,
+            confidence,
+            block_length,
+            seed,
+            trials,
+            trial_sharpes: trial_sharpes.unwrap_or_default(),
+        });
+        Ok(())
+    }
+
+    /// Cumulative returns of the strategy and the benchmark and the excess return, in percent
+    pub fn get_excess_returns(&mut self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        self.calculate_daily_results();
+        let curve = match self.benchmark.as_ref() {
+            Some(benchmark) => excess_curve(
+                &benchmark.align(self.daily_results.lock().unwrap().values(), self.capital),
+            ),
+            None => Vec::new(),
+        };
+        let dict = PyDict::new(py);
+        dict.set_item("date", curve.iter().map(|c| c.0).collect::<Vec<_>>())?;
+        dict.set_item(
+            "strategy_return",
+            curve.iter().map(|c| c.1).collect::<Vec<_>>(),
+        )?;
+        dict.set_item(
+            "benchmark_return",
+            curve.iter().map(|c| c.2).collect::<Vec<_>>(),
+        )?;
+        dict.set_item(
+            "excess_return",
+            curve.iter().map(|c| c.3).collect::<Vec<_>>(),
+        )?;
+        Ok(dict.unbind())
+    }
+
+    /// Monthly returns in percent, one row per year with a column per month and the yearly total
+    pub fn get_monthly_returns(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        self.calculate_daily_results();
+        let balance = daily_balance(self.daily_results.lock().unwrap().values(), self.capital);
+        let table = monthly_returns(&balance, self.capital);
+
+        let results = PyDict::new(py);
+        results.set_item("year", table.iter().map(|row| row.0).collect::<Vec<_>>())?;
+        for month in 0..12 {
+            results.set_item(
+                month + 1,
+                table.iter().map(|row| row.1[month]).collect::<Vec<_>>(),
+            )?;
+        }
+        results.set_item("total", table.iter().map(|row| row.2).collect::<Vec<_>>())?;
+        to_dataframe(py, results, "year")
+    }
+
+    /// Return, max drawdown and sharpe ratio of every calendar year
+    pub fn get_yearly_statistics(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        self.calculate_daily_results();
+        let balance = daily_balance(self.daily_results.lock().unwrap().values(), self.capital);
+        let table = yearly_statistics(&balance, self.capital, self.risk_free, self.annual_days);
+
+        let results = PyDict::new(py);
+        results.set_item("year", table.iter().map(|y| y.year).collect::<Vec<_>>())?;
+        results.set_item(
+            "trading_days",
+            table.iter().map(|y| y.trading_days).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "total_return",
+            table.iter().map(|y| y.total_return).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "max_ddpercent",
+            table.iter().map(|y| y.max_ddpercent).collect::<Vec<_>>(),
+        )?;
+        results.set_item(
+            "sharpe_ratio",
+            table.iter().map(|y| y.sharpe_ratio).collect::<Vec<_>>(),
+        )?;
+        to_dataframe(py, results, "year")
+    }
+
+    /// Sharpe ratio, volatility and max drawdown percent over trailing windows of trading days
+    #[pyo3(signature = (windows=vec![60, 120, 250]))]
+    pub fn get_rolling_statistics(
+        &mut self,
+        py: Python<'_>,
+        windows: Vec<usize>,
+    ) -> PyResult<PyObject> {
+        self.calculate_daily_results();
+        let balance = daily_balance(self.daily_results.lock().unwrap().values(), self.capital);
+
+        let results = PyDict::new(py);
+        results.set_item("date", balance.iter().map(|b| b.0).collect::<Vec<_>>())?;
+        for window in windows {
+            let (sharpe, volatility, drawdown) = rolling_statistics(
+                &balance,
+                self.capital,
+                window,
+                self.risk_free,
+                self.annual_days,
+            );
+            results.set_item(format!("sharpe_ratio_{}", window), sharpe)?;
+            results.set_item(format!("volatility_{}", window), volatility)?;
+            results.set_item(format!("max_ddpercent_{}", window), drawdown)?;
+        }
+        to_dataframe(py, results, "date")
+    }
+
+    /// The deepest drawdown episodes of the daily balance, or of the equity of every bar
+    #[pyo3(signature = (top=5, bar_level=false))]
+    pub fn get_drawdown_episodes(
+        &mut self,
+        py: Python<'_>,
+        top: usize,
+        bar_level: bool,
+    ) -> PyResult<PyObject> {
+        if bar_level {
+            episodes_dataframe(py, &top_episodes(&self.equity_series()?, top))
+        } else {
+            episodes_dataframe(py, &top_episodes(&self.daily_series(), top))
+        }
+    }
+
+    /// Drawdown and drawdown percent at every day, or at every bar
+    #[pyo3(signature = (bar_level=false))]
+    pub fn get_underwater_curve(&mut self, py: Python<'_>, bar_level: bool) -> PyResult<PyObject> {
+        if bar_level {
+            underwater_dataframe(py, &self.equity_series()?, "datetime")
+        } else {
+            underwater_dataframe(py, &self.daily_series(), "date")
+        }
+    }
+
+    #[pyo3(signature = (bar_level=false))]
+    pub fn calculate_drawdown_statistics(
+        &mut self,
+        bar_level: bool,
+    ) -> PyResult<DrawdownStatistics> {
+        if bar_level {
+            Ok(drawdown_statistics(&self.equity_series()?))
+        } else {
+            Ok(drawdown_statistics(&self.daily_series()))
+        }
+    }
+
+    /// Write a single html file with the charts, statistics, trades and parameters,
+    /// it's rendered offline without plotly
+    pub fn export_report(&mut self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        let statistics = self.rs_calculate_statistics(py, false);
+        let mut balance = self.capital;
+        let daily = self
+            .daily_results
+            .lock()
+            .unwrap()
+            .values()
+            .map(|daily_result| {
+                balance += daily_result.net_pnl;
+                (daily_result.date, balance, daily_result.net_pnl)
+            })
+            .collect();
+
+        let (class_name, strategy_setting) = self.strategy_setting(py)?;
+        let title = match class_name {
+            Some(class_name) => format!("{} 回测报告", class_name),
+            None => "回测报告".to_string(),
+        };
+        let mut setting = Vec::new();
+        for (name, value) in strategy_setting.iter() {
+            setting.push((name.str()?.to_string(), value.str()?.to_string()));
+        }
+
+        let parameters = [
+            ("本地代码", self.vt_symbol.clone()),
+            (
+                "K线周期",
+                self.interval.map(|i| i.to_string()).unwrap_or_default(),
+            ),
+            ("开始日期", self.start.to_string()),
+            ("结束日期", self.end.to_string()),
+            ("手续费率", self.rate.to_string()),
+            ("交易滑点", self.slippage.to_string()),
+            ("合约乘数", self.size.to_string()),
+            ("价格跳动", self.pricetick.to_string()),
+            ("回测资金", self.capital.to_string()),
+            ("回测模式", self.mode.to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+        let data_range = {
+            let history_data = GLOBAL_HISTORY_DATA.lock().unwrap();
+            let datetime = |item: Option<&MixData>| match item {
+                Some(MixData::BarData(bar)) => bar.datetime.to_string(),
+                Some(MixData::TickData(tick)) => tick.datetime.to_string(),
+                None => String::new(),
+            };
+            vec![
+                ("开始时间".to_string(), datetime(history_data.front())),
+                ("结束时间".to_string(), datetime(history_data.back())),
+                ("数据条数".to_string(), history_data.len().to_string()),
+            ]
+        };
+
+        let report = Report {
+            title,
+            created: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            statistics: statistics.report_lines(),
+            parameters,
+            setting,
+            data_range,
+            daily,
+            trades: self.get_all_trades(),
+        };
+        std::fs::write(&path, report.render()).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "回测报告写入{}失败：{}",
+                path.display(),
+                e
+            ))
+        })?;
+        self.output(py, &format!("回测报告已保存至{}", path.display()));
+        Ok(())
+    }
+
+    /// Save the whole run into a directory, artifact.json holds the parameters, strategy setting,
+    /// statistics, logs and stop orders, trades, orders and daily results are parquet files.
+    /// BacktestArtifact.load reads it back.
+    pub fn export_artifact(&mut self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        let statistics = self.rs_calculate_statistics(py, false);
+        let (class_name, setting) = self.strategy_setting(py)?;
+        // Values json can't encode are saved as their str
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("default", py.get_type::<pyo3::types::PyString>())?;
+        let setting: String = py
+            .import("json")?
+            .call_method("dumps", (setting,), Some(&kwargs))?
+            .extract()?;
+        let data = ArtifactData {
+            strategy_class: class_name.unwrap_or_default(),
+            parameters: ArtifactParameters {
+                vt_symbol: self.vt_symbol.clone(),
+                interval: self.interval.map(|i| i.to_string()).unwrap_or_default(),
+                start: self.start,
+                end: self.end,
+                rate: self.rate,
+                slippage: self.slippage,
+                size: self.size,
+                pricetick: self.pricetick,
+                capital: self.capital,
+                mode: self.mode.to_string(),
+                risk_free: self.risk_free,
+                annual_days: self.annual_days,
+                half_life: self.half_life,
+                record_equity: self.record_equity,
+                margin_rate: self.margin_rate,
+            },
+            setting: serde_json::from_str(&setting)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?,
+            statistics,
+            logs: self.logs.lock().unwrap().clone(),
+            trades: self.get_all_trades(),
+            orders: self.get_all_orders(),
+            stop_orders: self.get_all_stop_orders(),
+            daily_results: self.get_all_daily_results(),
+        };
+        let created = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        save_artifact(&path, data, created).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "回测结果写入{}失败：{}",
+                path.display(),
+                e
+            ))
+        })?;
+        self.output(py, &format!("回测结果已保存至{}", path.display()));
+        Ok(())
+    }
+
+    fn show_chart(&mut self) {}
+
+    /// Run every setting in worker processes, which share the history data loaded here
+    /// through a snapshot file. Results are sorted by the target, best first.
+    /// callback, if given, is called with (result, finished, total, eta) as each setting finishes.
+    #[pyo3(signature = (settings, target_name, max_workers=None, callback=None))]
+    pub fn rs_run_bf_optimization(
+        &mut self,
+        py: Python<'_>,
+        settings: Vec<Bound<'_, PyDict>>,
+        target_name: &str,
+        max_workers: Option<usize>,
+        callback: Option<PyObject>,
+    ) -> PyResult<Vec<PyObject>> {
+        self.output(py, "开始执行穷举算法优化");
+        self.output(py, &format!("参数优化空间：{}", settings.len()));
+        self.load_data(py)?;
+        if GLOBAL_HISTORY_DATA.lock().unwrap().is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "历史数据为空，无法执行优化",
+            ));
+        }
+
+        let pool = WorkerPool::start(
+            py,
+            self,
+            &engine_parameters(py, self)?,
+            &GLOBAL_HISTORY_DATA.lock().unwrap(),
+            target_name,
+            max_workers,
+        )?;
+        let mut progress = Progress::new(settings.len());
+        let mut results = Vec::new();
+        pool.evaluate(settings, |result| {
+            let report = progress.advance();
+            if let Some(callback) = callback.as_ref() {
+                callback.call1(
+                    py,
+                    (&result, progress.finished, progress.total, progress.eta()),
+                )?;
+            }
+            if report {
+                self.output(py, &progress.message());
+            }
+            results.push(result.unbind());
+            Ok(())
+        })?;
+        drop(pool);
+
+        self.output(
+            py,
+            &format!("穷举算法优化完成，耗时{:.0}秒", progress.elapsed()),
+        );
+        Ok(sort_results(py, results))
+    }
+
+    /// Search the parameter grid with a genetic algorithm, every combination is
+    /// evaluated at most once on the worker processes. params maps each parameter to its values.
+    /// Every evaluation is returned as (setting, target value, statistics, generation),
+    /// best target first. callback, if given, is called with (result, generation, eta)
+    /// as each evaluation finishes. The search stops after budget evaluations if given.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (params, target_name, max_workers=None, population_size=100, ngen_size=30, crossover_rate=0.95, mutation_rate=0.1, tournament_size=3, seed=0, callback=None, budget=None))]
+    pub fn rs_run_ga_optimization(
+        &mut self,
+        py: Python<'_>,
+        params: &Bound<'_, PyDict>,
+        target_name: &str,
+        max_workers: Option<usize>,
+        population_size: usize,
+        ngen_size: usize,
+        crossover_rate: f64,
+        mutation_rate: f64,
+        tournament_size: usize,
+        seed: u64,
+        callback: Option<PyObject>,
+        budget: Option<usize>,
+    ) -> PyResult<Vec<PyObject>> {
+        let params = params
+            .iter()
+            .map(|(name, values)| Ok((name.extract()?, values.extract()?)))
+            .collect::<PyResult<Vec<(String, Vec<PyObject>)>>>()?;
+        if params.iter().any(|(_, values)| values.is_empty()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "每个参数至少需要一个取值",
+            ));
+        }
+        if population_size == 0 || tournament_size == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "population_size和tournament_size必须大于0",
+            ));
+        }
+        if !(0.0..=1.0).contains(&crossover_rate) || !(0.0..=1.0).contains(&mutation_rate) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "crossover_rate和mutation_rate必须在0到1之间",
+            ));
+        }
+
+        let mut search = GeneticSearch::new(
+            params.iter().map(|(_, values)| values.len()).collect(),
+            GeneticSetting {
+                population_size,
+                crossover_rate,
+                mutation_rate,
+                tournament_size,
+                seed,
+            },
+        );
+        self.output(py, "开始执行遗传算法优化");
+        self.output(py, &format!("参数优化空间：{}", search.space()));
+        self.output(py, &format!("每代族群总数：{}", population_size));
+        self.output(py, &format!("迭代次数：{}", ngen_size));
+        self.output(py, &format!("交叉概率：{:.0}%", crossover_rate * 100.0));
+        self.output(py, &format!("突变概率：{:.0}%", mutation_rate * 100.0));
+        self.output(py, &format!("锦标赛规模：{}", tournament_size));
+        self.load_data(py)?;
+        if GLOBAL_HISTORY_DATA.lock().unwrap().is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "历史数据为空，无法执行优化",
+            ));
+        }
+
+        let pool = WorkerPool::start(
+            py,
+            self,
+            &engine_parameters(py, self)?,
+            &GLOBAL_HISTORY_DATA.lock().unwrap(),
+            target_name,
+            max_workers,
+        )?;
+        let mut progress = Progress::new(ngen_size + 1);
+        let mut results = Vec::new();
+        let mut population = search.initial_population();
+        for generation in 0..=ngen_size {
+            let mut candidates = if generation == 0 {
+                Vec::new()
+            } else {
+                search.offspring(&population)
+            };
+            candidates.append(&mut population);
+            let mut batch = search.unevaluated(&candidates);
+            if let Some(budget) = budget {
+                batch.truncate(budget.saturating_sub(search.evaluated()));
+            }
+            let settings = batch
+                .iter()
+                .map(|genes| {
+                    let setting = PyDict::new(py);
+                    for ((name, values), index) in params.iter().zip(genes) {
+                        setting.set_item(name, values[*index].bind(py))?;
+                    }
+                    Ok(setting)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            let mut batch = batch.into_iter();
+            pool.evaluate_ordered(settings, |result| {
+                search.record(batch.next().unwrap(), target_value(&result));
+                let result = (
+                    result.get_item(0)?,
+                    result.get_item(1)?,
+                    result.get_item(2)?,
+                    generation,
+                )
+                    .into_pyobject(py)?
+                    .into_any()
+                    .unbind();
+                if let Some(callback) = callback.as_ref() {
+                    callback.call1(py, (&result, generation, progress.eta()))?;
+                }
+                results.push(result);
+                Ok(())
+            })?;
+            population = search.select(candidates);
+            progress.advance();
+
+            self.output(
+                py,
+                &format!(
+                    "第{}代：累计评估{}组，最优目标{}，已用时{:.0}秒，预计剩余{:.0}秒",
+                    generation,
+                    search.evaluated(),
+                    search.best().unwrap_or(f64::NAN),
+                    progress.elapsed(),
+                    progress.eta()
+                ),
+            );
+            if search.exhausted() {
+                self.output(py, "参数优化空间已全部评估，提前结束迭代");
+                break;
+            }
+            if budget.is_some_and(|budget| search.evaluated() >= budget) {
+                self.output(py, "已达到评估次数上限，提前结束迭代");
+                break;
+            }
+        }
+        drop(pool);
+
+        self.output(
+            py,
+            &format!("遗传算法优化完成，耗时{:.0}秒", progress.elapsed()),
+        );
+        Ok(sort_results(py, results))
+    }
+
+    /// Walk-forward analysis: every in-sample window is optimized over the settings,
+    /// and its best setting is run on the out-of-sample window right after it.
+    /// in_sample and out_sample are counted in unit, which is DAYS or BARS.
+    /// Anchored in-sample windows all start at the beginning of the history data.
+    /// callback, if given, is called with (window, finished, total, eta) after each window.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (settings, target_name, in_sample, out_sample, unit="DAYS", anchored=false, max_workers=None, callback=None))]
+    pub fn rs_run_walk_forward(
+        &mut self,
+        py: Python<'_>,
+        settings: Vec<Bound<'_, PyDict>>,
+        target_name: &str,
+        in_sample: usize,
+        out_sample: usize,
+        unit: &str,
+        anchored: bool,
+        max_workers: Option<usize>,
+        callback: Option<PyObject>,
+    ) -> PyResult<WalkForwardResult> {
+        self.load_data(py)?;
+        run_walk_forward(
+            py,
+            self,
+            settings,
+            target_name,
+            in_sample,
+            out_sample,
+            unit,
+            anchored,
+            max_workers,
+            callback,
+        )
+    }
+
+    fn calculate_daily_results(&mut self) {
+        if self.daily_calculated {
+            return;
+        }
+        self.daily_calculated = true;
 
         // Add trade data into daily reuslt.
         for trade in self.trades.lock().unwrap().values() {
@@ -412,70 +1323,8 @@ impl BacktestingEngine {
             pre_close = daily_result.close_price;
             start_pos = daily_result.end_pos;
         }
-
-        // Generate dataframe
-        let mut date: Vec<NaiveDate> = Vec::new();
-        let mut close_price = Vec::new();
-        let mut pre_close = Vec::new();
-        let mut trade_count = Vec::new();
-        let mut start_pos = Vec::new();
-        let mut end_pos = Vec::new();
-        let mut turnover = Vec::new();
-        let mut commission = Vec::new();
-        let mut slippage = Vec::new();
-        let mut trading_pnl = Vec::new();
-        let mut holding_pnl = Vec::new();
-        let mut total_pnl = Vec::new();
-        let mut net_pnl = Vec::new();
-        for daily_result in self.daily_results.lock().unwrap().values() {
-            date.push(daily_result.date);
-            close_price.push(daily_result.close_price);
-            pre_close.push(daily_result.pre_close);
-            trade_count.push(daily_result.trade_count);
-            start_pos.push(daily_result.start_pos);
-            end_pos.push(daily_result.end_pos);
-            turnover.push(daily_result.turnover);
-            commission.push(daily_result.commission);
-            slippage.push(daily_result.slippage);
-            trading_pnl.push(daily_result.trading_pnl);
-            holding_pnl.push(daily_result.holding_pnl);
-            total_pnl.push(daily_result.total_pnl);
-            net_pnl.push(daily_result.net_pnl);
-        }
-        let results = PyDict::new(py);
-        results.set_item("date", date)?;
-        results.set_item("close_price", close_price)?;
-        results.set_item("pre_close", pre_close)?;
-        results.set_item("trade_count", trade_count)?;
-        results.set_item("start_pos", start_pos)?;
-        results.set_item("end_pos", end_pos)?;
-        results.set_item("turnover", turnover)?;
-        results.set_item("commission", commission)?;
-        results.set_item("slippage", slippage)?;
-        results.set_item("trading_pnl", trading_pnl)?;
-        results.set_item("holding_pnl", holding_pnl)?;
-        results.set_item("total_pnl", total_pnl)?;
-        results.set_item("net_pnl", net_pnl)?;
-
-        let pd = PyModule::import(py, "pandas")?;
-        let dataframe = pd.getattr("DataFrame")?;
-        let daily_df = dataframe
-            .call_method1("from_dict", (results,))?
-            .call_method1("set_index", ("date",))?;
-        self.daily_df = Some(daily_df.unbind());
-
-        self.output(py, "逐日盯市盈亏计算完成");
-        Ok(self.daily_df.as_ref().unwrap().clone_ref(py))
     }
 
-    fn calculate_statistics(&mut self) {}
-
-    fn show_chart(&mut self) {}
-
-    fn run_bf_optimization(&self) {}
-
-    fn run_ga_optimization(&self) {}
-
     fn update_daily_close(&self, price: f64) {
         let d = self.datetime.lock().unwrap().unwrap().naive_local().date();
 
@@ -488,6 +1337,10 @@ impl BacktestingEngine {
     }
 
     fn new_bar(&self, py: Python<'_>, bar: &BarData) -> PyResult<()> {
+        self.trade_trace
+            .lock()
+            .unwrap()
+            .record_bar(bar.high_price, bar.low_price);
         self.bar.lock().unwrap().replace(bar.clone());
         self.datetime.lock().unwrap().replace(bar.datetime);
 
@@ -499,10 +1352,15 @@ impl BacktestingEngine {
             .call_method1(py, "on_bar", (bar.clone(),))?;
 
         self.update_daily_close(bar.close_price);
+        self.update_equity(bar.datetime, bar.close_price);
         Ok(())
     }
 
     fn new_tick(&self, py: Python<'_>, tick: &TickData) -> PyResult<()> {
+        self.trade_trace
+            .lock()
+            .unwrap()
+            .record_bar(tick.last_price, tick.last_price);
         self.tick.lock().unwrap().replace(tick.clone());
         self.datetime.lock().unwrap().replace(tick.datetime.clone());
 
@@ -514,6 +1372,7 @@ impl BacktestingEngine {
             .call_method1(py, "on_tick", (tick.clone(),))?;
 
         self.update_daily_close(tick.last_price);
+        self.update_equity(tick.datetime, tick.last_price);
         Ok(())
     }
 
@@ -620,10 +1479,7 @@ impl BacktestingEngine {
                 (trade.lock().unwrap().clone(),),
             )?;
 
-            self.trades
-                .lock()
-                .unwrap()
-                .insert(trade.lock().unwrap().vt_tradeid(), trade.clone());
+            self.record_trade(trade.clone());
         }
         Ok(())
     }
@@ -717,10 +1573,7 @@ impl BacktestingEngine {
                 gateway_name: BacktestingEngine::gateway_name(),
             }));
 
-            self.trades
-                .lock()
-                .unwrap()
-                .insert(trade.lock().unwrap().vt_tradeid(), trade.clone());
+            self.record_trade(trade.clone());
 
             // Update stop order.
             stop_order
@@ -771,13 +1624,20 @@ impl BacktestingEngine {
         interval: Interval,
         _callback: PyObject,
         _use_database: bool,
-    ) -> Vec<BarData> {
+    ) -> PyResult<Vec<BarData>> {
         let init_end = self.start - INTERVAL_DELTA_MAP[&interval];
         let init_start = self.start - TimeDelta::days(days);
 
         let (symbol, exchange) = extract_vt_symbol(vt_symbol);
 
-        let bars_mixed = load_bar_data(&symbol, &exchange, interval, init_start, init_end);
+        let bars_mixed = load_bar_data(
+            self.database()?,
+            &symbol,
+            &exchange,
+            interval,
+            init_start,
+            init_end,
+        )?;
         let mut bars: Vec<BarData> = Vec::new();
         for mix_data in bars_mixed {
             if let MixData::BarData(bar_data) = mix_data {
@@ -785,16 +1645,22 @@ impl BacktestingEngine {
             }
         }
 
-        return bars;
+        return Ok(bars);
     }
 
-    pub fn load_tick(&self, vt_symbol: &str, days: i64, _callback: PyObject) -> Vec<TickData> {
+    pub fn load_tick(
+        &self,
+        vt_symbol: &str,
+        days: i64,
+        _callback: PyObject,
+    ) -> PyResult<Vec<TickData>> {
         let init_end = self.start - TimeDelta::seconds(1);
         let init_start = self.start - TimeDelta::days(days);
 
         let (symbol, exchange) = extract_vt_symbol(vt_symbol);
 
-        let ticks_mixed = load_tick_data(&symbol, &exchange, init_start, init_end);
+        let ticks_mixed =
+            load_tick_data(self.database()?, &symbol, &exchange, init_start, init_end)?;
         let mut ticks: Vec<TickData> = Vec::new();
         for mix_data in ticks_mixed {
             if let MixData::TickData(tick_data) = mix_data {
@@ -802,7 +1668,7 @@ impl BacktestingEngine {
             }
         }
 
-        return ticks;
+        return Ok(ticks);
     }
 
     pub fn send_order(
@@ -1029,6 +1895,113 @@ impl BacktestingEngine {
             .collect()
     }
 
+    /// Pair the fills into closed round trips, matching closing fills FIFO or LIFO
+    #[pyo3(signature = (method="FIFO"))]
+    pub fn get_round_trips(&self, method: &str) -> PyResult<Vec<RoundTrip>> {
+        let method = MatchingMethod::from_str(&method.to_uppercase()).map_err(|_| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "配对方式只能是FIFO或LIFO，实际为{}",
+                method
+            ))
+        })?;
+        Ok(match_round_trips(
+            &self.get_all_trades(),
+            &self.trade_trace.lock().unwrap(),
+            method,
+            self.size,
+            self.rate,
+            self.slippage,
+        ))
+    }
+
+    /// MAE and MFE of every round trip next to its final pnl, ready for scatter plots
+    #[pyo3(signature = (method="FIFO"))]
+    pub fn get_excursions(&self, method: &str) -> PyResult<BTreeMap<&'static str, Vec<f64>>> {
+        let trips = self.get_round_trips(method)?;
+        Ok(BTreeMap::from([
+            ("mae", trips.iter().map(|t| t.mae).collect()),
+            ("mfe", trips.iter().map(|t| t.mfe).collect()),
+            ("net_pnl", trips.iter().map(|t| t.net_pnl).collect()),
+        ]))
+    }
+
+    /// Resample or reshuffle the net pnl of the round trips to get the distribution of the final
+    /// balance, max drawdown and trades under water, and the probability of losing each ruin level
+    /// (a ratio of the capital). threads=0 uses all cores.
+    #[pyo3(signature = (simulations=10000, method="RESAMPLE", seed=0, percentiles=vec![1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0], ruin_levels=vec![0.1, 0.2, 0.3, 0.5, 1.0], matching="FIFO", threads=0))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn run_monte_carlo(
+        &self,
+        py: Python<'_>,
+        simulations: usize,
+        method: &str,
+        seed: u64,
+        percentiles: Vec<f64>,
+        ruin_levels: Vec<f64>,
+        matching: &str,
+        threads: usize,
+    ) -> PyResult<MonteCarloResult> {
+        let pnls: Vec<f64> = self
+            .get_round_trips(matching)?
+            .iter()
+            .map(|trip| trip.net_pnl)
+            .collect();
+        if pnls.is_empty() {
+            self.output(py, "回测成交记录为空");
+        }
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        };
+        let capital = self.capital;
+        py.allow_threads(|| {
+            run_monte_carlo(
+                &pnls,
+                capital,
+                simulations,
+                method,
+                seed,
+                percentiles,
+                ruin_levels,
+                threads,
+            )
+        })
+    }
+
+    #[pyo3(signature = (method="FIFO"))]
+    pub fn calculate_trade_statistics(&self, method: &str) -> PyResult<RoundTripStatistics> {
+        Ok(round_trip_statistics(&self.get_round_trips(method)?))
+    }
+
+    /// Columns of the equity curve as raw native-endian buffers, read by numpy without copying.
+    /// datetime is int64 nanoseconds since the epoch, the others are float64.
+    pub fn rs_get_equity_curve(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        fn bytes<T: Copy, const N: usize>(
+            py: Python<'_>,
+            values: &[T],
+            to_bytes: fn(T) -> [u8; N],
+        ) -> Py<PyBytes> {
+            let buffer: Vec<u8> = values.iter().flat_map(|v| to_bytes(*v)).collect();
+            PyBytes::new(py, &buffer).unbind()
+        }
+        let equity = self.equity.lock().unwrap();
+        let dict = PyDict::new(py);
+        dict.set_item(
+            "timezone",
+            equity.timezone.map(|tz| tz.name()).unwrap_or("UTC"),
+        )?;
+        dict.set_item("datetime", bytes(py, &equity.datetime, i64::to_ne_bytes))?;
+        dict.set_item("balance", bytes(py, &equity.balance, f64::to_ne_bytes))?;
+        dict.set_item("position", bytes(py, &equity.position, f64::to_ne_bytes))?;
+        dict.set_item(
+            "unrealized_pnl",
+            bytes(py, &equity.unrealized_pnl, f64::to_ne_bytes),
+        )?;
+        dict.set_item("equity", bytes(py, &equity.equity(), f64::to_ne_bytes))?;
+        dict.set_item("margin", bytes(py, &equity.margin, f64::to_ne_bytes))?;
+        Ok(dict.unbind())
+    }
+
     pub fn get_all_orders(&self) -> Vec<OrderData> {
         self.limit_orders
             .lock()
@@ -1038,6 +2011,15 @@ impl BacktestingEngine {
             .collect()
     }
 
+    pub fn get_all_stop_orders(&self) -> Vec<StopOrder> {
+        self.stop_orders
+            .lock()
+            .unwrap()
+            .values()
+            .map(|item| item.lock().unwrap().clone())
+            .collect()
+    }
+
     pub fn get_all_daily_results(&self) -> Vec<DailyResult> {
         self.daily_results
             .lock()
@@ -1052,6 +2034,36 @@ impl BacktestingEngine {
         Ok(())
     }
 
+    /// Save the loaded history data into a file, load_history_snapshot reads it back
+    pub fn save_history_snapshot(&self, path: PathBuf) -> PyResult<()> {
+        write_snapshot(&path, &GLOBAL_HISTORY_DATA.lock().unwrap()).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "历史数据快照{}写入失败：{}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Replace the history data with a snapshot, load_data won't load it again
+    /// as long as the parameters are the same
+    pub fn load_history_snapshot(&self, path: PathBuf) -> PyResult<()> {
+        let history_data = read_snapshot(&path).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "历史数据快照{}读取失败：{}",
+                path.display(),
+                e
+            ))
+        })?;
+        *GLOBAL_HISTORY_DATA.lock().unwrap() = history_data;
+        if self.history_data_cacheable() && self.interval.is_some() {
+            *GLOBAL_HISTORY_DATA_KEY.lock().unwrap() = self.rs_get_history_data_key();
+        } else {
+            GLOBAL_HISTORY_DATA_KEY.lock().unwrap().clear();
+        }
+        Ok(())
+    }
+
     pub fn has_history_data(&self) -> bool {
         !GLOBAL_HISTORY_DATA.lock().unwrap().is_empty()
     }
@@ -1096,6 +2108,100 @@ impl BacktestingEngine {
     }
 }
 
+impl BacktestingEngine {
+    fn update_equity(&self, datetime: DateTime<Tz>, price: f64) {
+        if self.record_equity {
+            self.equity.lock().unwrap().record(
+                datetime,
+                price,
+                self.capital,
+                self.size,
+                self.margin_rate,
+            );
+        }
+    }
+
+    /// Class name of the strategy and its parameters, read from the `parameters` attribute
+    fn strategy_setting<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> PyResult<(Option<String>, Bound<'py, PyDict>)> {
+        let setting = PyDict::new(py);
+        let Some(strategy) = self.strategy.as_ref() else {
+            return Ok((None, setting));
+        };
+        let strategy = strategy.bind(py);
+        if let Ok(parameters) = strategy.getattr("parameters") {
+            for name in parameters.try_iter()? {
+                let name = name?;
+                setting.set_item(&name, strategy.getattr(name.str()?)?)?;
+            }
+        }
+        Ok((Some(strategy.get_type().name()?.to_string()), setting))
+    }
+
+    fn daily_series(&mut self) -> Vec<(NaiveDate, f64)> {
+        self.calculate_daily_results();
+        daily_balance(self.daily_results.lock().unwrap().values(), self.capital)
+    }
+
+    /// Equity of every bar, the datetimes carry a fixed offset so that they convert to python
+    fn equity_series(&self) -> PyResult<Vec<(DateTime<FixedOffset>, f64)>> {
+        let series: Vec<_> = self
+            .equity
+            .lock()
+            .unwrap()
+            .series()
+            .into_iter()
+            .map(|(datetime, equity)| (datetime.fixed_offset(), equity))
+            .collect();
+        if series.is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "没有记录资金曲线，请在set_parameters时设置record_equity=True",
+            ));
+        }
+        Ok(series)
+    }
+
+    /// Store a fill along with the index of the bar (or tick) it happened on
+    fn record_trade(&self, trade: Arc<Mutex<TradeData>>) {
+        let vt_tradeid = trade.lock().unwrap().vt_tradeid();
+        let mut trace = self.trade_trace.lock().unwrap();
+        let bar = trace.bar_count();
+        trace.trade_bars.insert(vt_tradeid.clone(), bar);
+        if self.record_equity {
+            let trade = trade.lock().unwrap();
+            self.equity.lock().unwrap().add_trade(
+                trade.direction,
+                trade.price,
+                trade.volume,
+                self.size,
+                self.rate,
+                self.slippage,
+            );
+        }
+        self.trades.lock().unwrap().insert(vt_tradeid, trade);
+    }
+
+    /// Data of a memory database may change at any time, so it's never cached
+    /// Key of the history data once validate_data has fixed it, None before
+    fn fixed_data_key(&self) -> Option<String> {
+        self.fixed_data_id
+            .map(|id| format!("{} fixed {}", self.rs_get_history_data_key(), id))
+    }
+
+    fn history_data_cacheable(&self) -> bool {
+        self.database.is_none() || self.database_config.is_some()
+    }
+
+    fn database(&self) -> Result<Arc<dyn BaseDatabase>, DatabaseError> {
+        match self.database.as_ref() {
+            Some(database) => Ok(database.clone()),
+            None => get_database(),
+        }
+    }
+}
+
 #[pyclass(get_all)]
 #[derive(Default, Clone)]
 pub struct DailyResult {
@@ -1181,27 +2287,179 @@ impl DailyResult {
     }
 }
 
+/// The daily results as a DataFrame indexed by date, as calculate_result returns
+fn daily_dataframe<'a>(
+    py: Python<'_>,
+    daily_results: impl IntoIterator<Item = &'a DailyResult>,
+) -> PyResult<PyObject> {
+    let mut date: Vec<NaiveDate> = Vec::new();
+    let mut close_price = Vec::new();
+    let mut pre_close = Vec::new();
+    let mut trade_count = Vec::new();
+    let mut start_pos = Vec::new();
+    let mut end_pos = Vec::new();
+    let mut turnover = Vec::new();
+    let mut commission = Vec::new();
+    let mut slippage = Vec::new();
+    let mut trading_pnl = Vec::new();
+    let mut holding_pnl = Vec::new();
+    let mut total_pnl = Vec::new();
+    let mut net_pnl = Vec::new();
+    for daily_result in daily_results {
+        date.push(daily_result.date);
+        close_price.push(daily_result.close_price);
+        pre_close.push(daily_result.pre_close);
+        trade_count.push(daily_result.trade_count);
+        start_pos.push(daily_result.start_pos);
+        end_pos.push(daily_result.end_pos);
+        turnover.push(daily_result.turnover);
+        commission.push(daily_result.commission);
+        slippage.push(daily_result.slippage);
+        trading_pnl.push(daily_result.trading_pnl);
+        holding_pnl.push(daily_result.holding_pnl);
+        total_pnl.push(daily_result.total_pnl);
+        net_pnl.push(daily_result.net_pnl);
+    }
+    let results = PyDict::new(py);
+    results.set_item("date", date)?;
+    results.set_item("close_price", close_price)?;
+    results.set_item("pre_close", pre_close)?;
+    results.set_item("trade_count", trade_count)?;
+    results.set_item("start_pos", start_pos)?;
+    results.set_item("end_pos", end_pos)?;
+    results.set_item("turnover", turnover)?;
+    results.set_item("commission", commission)?;
+    results.set_item("slippage", slippage)?;
+    results.set_item("trading_pnl", trading_pnl)?;
+    results.set_item("holding_pnl", holding_pnl)?;
+    results.set_item("total_pnl", total_pnl)?;
+    results.set_item("net_pnl", net_pnl)?;
+
+    to_dataframe(py, results, "date")
+}
+
+fn to_dataframe(py: Python<'_>, results: Bound<'_, PyDict>, index: &str) -> PyResult<PyObject> {
+    let pd = PyModule::import(py, "pandas")?;
+    Ok(pd
+        .getattr("DataFrame")?
+        .call_method1("from_dict", (results,))?
+        .call_method1("set_index", (index,))?
+        .unbind())
+}
+
+fn episodes_dataframe<T>(py: Python<'_>, episodes: &[DrawdownEpisode<T>]) -> PyResult<PyObject>
+where
+    T: Copy + for<'py> IntoPyObject<'py>,
+{
+    let results = PyDict::new(py);
+    results.set_item("peak", episodes.iter().map(|e| e.peak).collect::<Vec<_>>())?;
+    results.set_item(
+        "trough",
+        episodes.iter().map(|e| e.trough).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "recovery",
+        episodes.iter().map(|e| e.recovery).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "recovered",
+        episodes
+            .iter()
+            .map(|e| e.recovery.is_some())
+            .collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "peak_balance",
+        episodes.iter().map(|e| e.peak_balance).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "trough_balance",
+        episodes
+            .iter()
+            .map(|e| e.trough_balance)
+            .collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "depth",
+        episodes.iter().map(|e| e.depth()).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "ddpercent",
+        episodes.iter().map(|e| e.ddpercent()).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "length",
+        episodes.iter().map(|e| e.length).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "decline",
+        episodes.iter().map(|e| e.decline).collect::<Vec<_>>(),
+    )?;
+    results.set_item(
+        "recovery_time",
+        episodes.iter().map(|e| e.recovery_time).collect::<Vec<_>>(),
+    )?;
+    let pd = PyModule::import(py, "pandas")?;
+    Ok(pd
+        .getattr("DataFrame")?
+        .call_method1("from_dict", (results,))?
+        .unbind())
+}
+
+fn underwater_dataframe<T>(py: Python<'_>, series: &[(T, f64)], index: &str) -> PyResult<PyObject>
+where
+    T: Copy + for<'py> IntoPyObject<'py>,
+{
+    let curve = underwater(series);
+    let results = PyDict::new(py);
+    results.set_item(index, curve.iter().map(|c| c.0).collect::<Vec<_>>())?;
+    results.set_item("drawdown", curve.iter().map(|c| c.1).collect::<Vec<_>>())?;
+    results.set_item("ddpercent", curve.iter().map(|c| c.2).collect::<Vec<_>>())?;
+    to_dataframe(py, results, index)
+}
+
+/// Whether the loaded history data can be used instead of loading it again: it has the same
+/// key and was loaded from a config, or it was fixed by the engine whose fixed key is given
+fn reusable_history_data(
+    loaded_key: &str,
+    key: &str,
+    cacheable: bool,
+    fixed_key: Option<&str>,
+) -> bool {
+    (cacheable && loaded_key == key) || fixed_key == Some(loaded_key)
+}
+
 fn load_bar_data(
+    database: Arc<dyn BaseDatabase>,
     symbol: &str,
     exchange: &str,
     interval: Interval,
     start: NaiveDateTime,
     end: NaiveDateTime,
-) -> LinkedList<MixData> {
-    let database = get_database();
-
-    return database.load_bar_data(symbol, exchange, interval, start, end);
+) -> Result<LinkedList<MixData>, DatabaseError> {
+    return with_retry(|| database.load_bar_data(symbol, exchange, interval, start, end)).map_err(
+        |e| {
+            e.context(&format!(
+                "{}.{} {}至{}的K线数据",
+                symbol, exchange, start, end
+            ))
+        },
+    );
 }
 
 fn load_tick_data(
+    database: Arc<dyn BaseDatabase>,
     symbol: &str,
     exchange: &str,
     start: NaiveDateTime,
     end: NaiveDateTime,
-) -> LinkedList<MixData> {
-    let database = get_database();
-
-    return database.load_tick_data(symbol, exchange, start, end);
+) -> Result<LinkedList<MixData>, DatabaseError> {
+    return with_retry(|| database.load_tick_data(symbol, exchange, start, end)).map_err(|e| {
+        e.context(&format!(
+            "{}.{} {}至{}的Tick数据",
+            symbol, exchange, start, end
+        ))
+    });
 }
 
 #[pymodule]
@@ -1209,5 +2467,53 @@ pub fn backtesting(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<EngineType>()?;
     m.add_class::<BacktestingMode>()?;
     m.add_class::<BacktestingEngine>()?;
+    m.add_class::<DataIssueKind>()?;
+    m.add_class::<DataIssue>()?;
+    m.add_class::<ValidationReport>()?;
+    m.add_class::<BacktestStatistics>()?;
+    m.add_class::<BacktestArtifact>()?;
+    m.add_class::<ArtifactParameters>()?;
+    m.add_class::<TradeDiffKind>()?;
+    m.add_class::<TradeDiff>()?;
+    m.add_class::<PositionDivergence>()?;
+    m.add_class::<RunComparison>()?;
+    m.add_function(wrap_pyfunction!(compare_runs, m)?)?;
+    m.add_class::<BenchmarkStatistics>()?;
+    m.add_class::<DrawdownStatistics>()?;
+    m.add_class::<ConfidenceStatistics>()?;
+    m.add_class::<MonteCarloResult>()?;
+    m.add_class::<RoundTrip>()?;
+    m.add_class::<RoundTripStatistics>()?;
+    m.add_class::<WalkForwardWindow>()?;
+    m.add_class::<WalkForwardResult>()?;
+    m.add_class::<ParameterRange>()?;
+    m.add_function(wrap_pyfunction!(sample_settings, m)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_history_data_survives_reload() {
+        let key = "rb SHFE 1m 2024-01-01 00:00:00 2024-02-01 23:59:59";
+        let fixed = format!("{} fixed 0", key);
+        // A plain load caches the data of the database
+        assert!(reusable_history_data(key, key, true, None));
+        assert!(!reusable_history_data(key, key, false, None));
+        // Once fixed, the engine which fixed it keeps the data, even with a memory database
+        assert!(reusable_history_data(&fixed, key, true, Some(&fixed)));
+        assert!(reusable_history_data(&fixed, key, false, Some(&fixed)));
+        // Other engines with the same key load again from the database
+        assert!(!reusable_history_data(&fixed, key, true, None));
+        assert!(!reusable_history_data(
+            &fixed,
+            key,
+            true,
+            Some(&format!("{} fixed 1", key))
+        ));
+        // Data loaded after the fix isn't mistaken for the fixed data
+        assert!(!reusable_history_data(key, key, false, Some(&fixed)));
+    }
+}
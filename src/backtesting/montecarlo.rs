@@ -0,0 +1,217 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use strum::{Display, EnumString};
+
+/// How a simulated trade sequence is drawn from the closed trades
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+pub enum SimulationMethod {
+    /// Draw trades with replacement
+    RESAMPLE,
+    /// Reorder the same trades
+    SHUFFLE,
+}
+
+/// Simulations run on each thread at a time
+const BATCH_SIZE: usize = 64;
+
+/// Outcome of one simulated trade sequence
+struct Path {
+    final_balance: f64,
+    max_drawdown: f64,
+    max_ddpercent: f64,
+    /// Longest run of trades spent below a previous high
+    max_underwater: usize,
+    /// Lowest balance as a ratio of the capital
+    low_ratio: f64,
+}
+
+fn simulate(pnls: &[f64], capital: f64, method: SimulationMethod, rng: &mut StdRng) -> Path {
+    let mut sequence = pnls.to_vec();
+    match method {
+        SimulationMethod::RESAMPLE => {
+            for pnl in sequence.iter_mut() {
+                *pnl = pnls[rng.gen_range(0..pnls.len())];
+            }
+        }
+        SimulationMethod::SHUFFLE => sequence.shuffle(rng),
+    }
+
+    let mut balance = capital;
+    let mut highlevel = capital;
+    let mut low = capital;
+    let (mut max_drawdown, mut max_ddpercent) = (0.0, 0.0);
+    let (mut underwater, mut max_underwater) = (0, 0);
+    for pnl in sequence {
+        balance += pnl;
+        low = low.min(balance);
+        if balance >= highlevel {
+            highlevel = balance;
+            underwater = 0;
+        } else {
+            underwater += 1;
+            max_underwater = max_underwater.max(underwater);
+        }
+        let drawdown = balance - highlevel;
+        if drawdown < max_drawdown {
+            max_drawdown = drawdown;
+        }
+        let ddpercent = drawdown / highlevel * 100.0;
+        if ddpercent < max_ddpercent {
+            max_ddpercent = ddpercent;
+        }
+    }
+    Path {
+        final_balance: balance,
+        max_drawdown,
+        max_ddpercent,
+        max_underwater,
+        low_ratio: low / capital,
+    }
+}
+
+/// Percentile tables of the simulated outcomes and the probability of ruin
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default)]
+pub struct MonteCarloResult {
+    pub method: String,
+    pub simulations: i64,
+    pub trades: i64,
+    pub seed: u64,
+    pub capital: f64,
+    pub percentiles: Vec<f64>,
+    pub final_balance: Vec<f64>,
+    pub max_drawdown: Vec<f64>,
+    pub max_ddpercent: Vec<f64>,
+    pub max_underwater: Vec<f64>,
+    /// Losses, as ratios of the capital, counted as ruin
+    pub ruin_levels: Vec<f64>,
+    /// Share of simulations whose balance ever lost that much
+    pub ruin_probability: Vec<f64>,
+}
+
+#[pymethods]
+impl MonteCarloResult {
+    /// The percentile table as a DataFrame indexed by percentile
+    pub fn to_dataframe(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let results = PyDict::new(py);
+        results.set_item("percentile", self.percentiles.clone())?;
+        results.set_item("final_balance", self.final_balance.clone())?;
+        results.set_item("max_drawdown", self.max_drawdown.clone())?;
+        results.set_item("max_ddpercent", self.max_ddpercent.clone())?;
+        results.set_item("max_underwater", self.max_underwater.clone())?;
+        super::to_dataframe(py, results, "percentile")
+    }
+
+    /// Ruin level to the probability of losing that much of the capital
+    pub fn risk_of_ruin(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        for (level, probability) in self.ruin_levels.iter().zip(&self.ruin_probability) {
+            dict.set_item(level, probability)?;
+        }
+        Ok(dict.unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MonteCarloResult(method={}, simulations={}, trades={}, ruin_levels={:?}, ruin_probability={:?})",
+            self.method, self.simulations, self.trades, self.ruin_levels, self.ruin_probability
+        )
+    }
+}
+
+/// Linear interpolation between the nearest ranks, as numpy's default
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let position = q / 100.0 * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn run_monte_carlo(
+    pnls: &[f64],
+    capital: f64,
+    simulations: usize,
+    method: &str,
+    seed: u64,
+    percentiles: Vec<f64>,
+    ruin_levels: Vec<f64>,
+    threads: usize,
+) -> PyResult<MonteCarloResult> {
+    let method = SimulationMethod::from_str(&method.to_uppercase()).map_err(|_| {
+        PyValueError::new_err(format!("模拟方式只能是RESAMPLE或SHUFFLE，实际为{}", method))
+    })?;
+    if percentiles.iter().any(|q| !(0.0..=100.0).contains(q)) {
+        return Err(PyValueError::new_err("percentiles必须在0和100之间"));
+    }
+    let mut result = MonteCarloResult {
+        method: method.to_string(),
+        simulations: simulations as i64,
+        trades: pnls.len() as i64,
+        seed,
+        capital,
+        ruin_levels: ruin_levels.clone(),
+        ruin_probability: vec![0.0; ruin_levels.len()],
+        ..Default::default()
+    };
+    if pnls.is_empty() || simulations == 0 {
+        return Ok(result);
+    }
+
+    // Every simulation has its own seed, so results don't depend on the number of threads
+    let mut rng = StdRng::seed_from_u64(seed);
+    let seeds: Vec<u64> = (0..simulations).map(|_| rng.gen()).collect();
+    let next_batch = AtomicUsize::new(0);
+    let paths: Vec<Path> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let (next_batch, seeds) = (&next_batch, &seeds);
+                s.spawn(move || {
+                    let mut paths = Vec::new();
+                    loop {
+                        let start = next_batch.fetch_add(BATCH_SIZE, Ordering::Relaxed);
+                        if start >= simulations {
+                            break;
+                        }
+                        for seed in &seeds[start..(start + BATCH_SIZE).min(simulations)] {
+                            let mut rng = StdRng::seed_from_u64(*seed);
+                            paths.push(simulate(pnls, capital, method, &mut rng));
+                        }
+                    }
+                    paths
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    let column = |value: fn(&Path) -> f64| {
+        let mut values: Vec<f64> = paths.iter().map(value).collect();
+        values.sort_by(f64::total_cmp);
+        percentiles
+            .iter()
+            .map(|q| percentile(&values, *q))
+            .collect::<Vec<_>>()
+    };
+    result.final_balance = column(|p| p.final_balance);
+    result.max_drawdown = column(|p| p.max_drawdown);
+    result.max_ddpercent = column(|p| p.max_ddpercent);
+    result.max_underwater = column(|p| p.max_underwater as f64);
+    result.ruin_probability = ruin_levels
+        .iter()
+        .map(|level| {
+            paths.iter().filter(|p| p.low_ratio <= 1.0 - level).count() as f64 / simulations as f64
+        })
+        .collect();
+    result.percentiles = percentiles;
+    Ok(result)
+}
@@ -0,0 +1,218 @@
+/*! Process pool running strategy settings against history data shared through a snapshot file. */
+
+use std::{
+    collections::LinkedList,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use pyo3::{prelude::*, types::PyDict};
+
+use super::{snapshot::write_snapshot, BacktestingEngine};
+use crate::trader::object::MixData;
+
+/// Tells apart the snapshots of optimizations run one after another in a process
+static SNAPSHOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Worker processes started with the spawn method. Every worker attaches the history snapshot
+/// once in its initializer and then only runs strategies, the database is never touched.
+pub struct WorkerPool<'py> {
+    py: Python<'py>,
+    pool: Bound<'py, PyAny>,
+    evaluate: Bound<'py, PyAny>,
+    snapshot: PathBuf,
+}
+
+impl<'py> WorkerPool<'py> {
+    /// Workers run the strategy of the engine with the given set_parameters arguments
+    /// on the given history data
+    pub fn start(
+        py: Python<'py>,
+        engine: &BacktestingEngine,
+        parameters: &Bound<'py, PyDict>,
+        history_data: &LinkedList<MixData>,
+        target_name: &str,
+        max_workers: Option<usize>,
+    ) -> PyResult<Self> {
+        let strategy_class = engine.strategy_class.as_ref().ok_or_else(|| {
+            pyo3::exceptions::PyValueError::new_err("请先调用add_strategy添加策略")
+        })?;
+        let snapshot = std::env::temp_dir().join(format!(
+            "vnpyrs_history_{}_{}.bin",
+            std::process::id(),
+            SNAPSHOT_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        write_snapshot(&snapshot, history_data).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!(
+                "历史数据快照{}写入失败：{}",
+                snapshot.display(),
+                e
+            ))
+        })?;
+
+        let optimize = py.import("vnpyrs.optimize")?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("initializer", optimize.getattr("init_worker")?)?;
+        kwargs.set_item(
+            "initargs",
+            (
+                snapshot.to_string_lossy().to_string(),
+                parameters,
+                strategy_class.clone_ref(py),
+                target_name,
+            ),
+        )?;
+        let pool = py
+            .import("multiprocessing")?
+            .call_method1("get_context", ("spawn",))?
+            .call_method("Pool", (max_workers,), Some(&kwargs));
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(e) => {
+                let _ = std::fs::remove_file(&snapshot);
+                return Err(e);
+            }
+        };
+        Ok(WorkerPool {
+            py,
+            pool,
+            evaluate: optimize.getattr("evaluate_worker")?,
+            snapshot,
+        })
+    }
+
+    /// Run the settings on the workers, on_result gets every
+    /// (setting, target value, statistics) tuple as soon as it's finished
+    pub fn evaluate(
+        &self,
+        settings: Vec<Bound<'py, PyDict>>,
+        on_result: impl FnMut(Bound<'py, PyAny>) -> PyResult<()>,
+    ) -> PyResult<()> {
+        self.map("imap_unordered", settings, on_result)
+    }
+
+    /// Same as evaluate, but the results come back in the order of the settings
+    pub fn evaluate_ordered(
+        &self,
+        settings: Vec<Bound<'py, PyDict>>,
+        on_result: impl FnMut(Bound<'py, PyAny>) -> PyResult<()>,
+    ) -> PyResult<()> {
+        self.map("imap", settings, on_result)
+    }
+
+    fn map(
+        &self,
+        method: &str,
+        settings: Vec<Bound<'py, PyDict>>,
+        mut on_result: impl FnMut(Bound<'py, PyAny>) -> PyResult<()>,
+    ) -> PyResult<()> {
+        let results = self.pool.call_method1(method, (&self.evaluate, settings))?;
+        for result in results.try_iter()? {
+            on_result(result?)?;
+            self.py.check_signals()?;
+        }
+        Ok(())
+    }
+}
+
+/// Arguments of set_parameters that give an engine the same parameters as this one
+pub fn engine_parameters<'py>(
+    py: Python<'py>,
+    engine: &BacktestingEngine,
+) -> PyResult<Bound<'py, PyDict>> {
+    let parameters = PyDict::new(py);
+    parameters.set_item("vt_symbol", &engine.vt_symbol)?;
+    parameters.set_item("interval", engine.interval.map(|i| i.to_string()))?;
+    parameters.set_item("start", engine.start)?;
+    parameters.set_item("end", engine.end)?;
+    parameters.set_item("rate", engine.rate)?;
+    parameters.set_item("slippage", engine.slippage)?;
+    parameters.set_item("size", engine.size)?;
+    parameters.set_item("pricetick", engine.pricetick)?;
+    parameters.set_item("capital", engine.capital)?;
+    parameters.set_item("mode", engine.mode.to_string())?;
+    parameters.set_item("risk_free", engine.risk_free)?;
+    parameters.set_item("annual_days", engine.annual_days)?;
+    parameters.set_item("half_life", engine.half_life)?;
+    parameters.set_item("record_equity", engine.record_equity)?;
+    parameters.set_item("margin_rate", engine.margin_rate)?;
+    Ok(parameters)
+}
+
+impl Drop for WorkerPool<'_> {
+    fn drop(&mut self) {
+        let _ = self.pool.call_method0("terminate");
+        let _ = self.pool.call_method0("join");
+        let _ = std::fs::remove_file(&self.snapshot);
+    }
+}
+
+/// Finished count and estimated time left of an optimization
+pub struct Progress {
+    start: Instant,
+    pub total: usize,
+    pub finished: usize,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Progress {
+            start: Instant::now(),
+            total,
+            finished: 0,
+        }
+    }
+
+    pub fn elapsed(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    /// Seconds left, assuming the remaining settings take as long as the finished ones
+    pub fn eta(&self) -> f64 {
+        if self.finished == 0 {
+            return f64::NAN;
+        }
+        self.elapsed() / self.finished as f64 * (self.total - self.finished) as f64
+    }
+
+    /// Count one finished setting, true when the progress should be printed,
+    /// which is about every percent
+    pub fn advance(&mut self) -> bool {
+        self.finished += 1;
+        let step = (self.total / 100).max(1);
+        self.finished.is_multiple_of(step) || self.finished == self.total
+    }
+
+    pub fn message(&self) -> String {
+        format!(
+            "优化进度：{}/{} [{:.0}%]，已用时{:.0}秒，预计剩余{:.0}秒",
+            self.finished,
+            self.total,
+            self.finished as f64 / self.total as f64 * 100.0,
+            self.elapsed(),
+            self.eta()
+        )
+    }
+}
+
+/// Target value of a (setting, target value, statistics) result,
+/// negative infinity when it isn't a number
+pub fn target_value(result: &Bound<'_, PyAny>) -> f64 {
+    result
+        .get_item(1)
+        .and_then(|value| value.extract::<f64>())
+        .ok()
+        .filter(|value| !value.is_nan())
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// Best target first, results without a number as their target last
+pub fn sort_results(py: Python<'_>, results: Vec<PyObject>) -> Vec<PyObject> {
+    let mut results: Vec<(f64, PyObject)> = results
+        .into_iter()
+        .map(|result| (target_value(result.bind(py)), result))
+        .collect();
+    results.sort_by(|a, b| b.0.total_cmp(&a.0));
+    results.into_iter().map(|(_, result)| result).collect()
+}
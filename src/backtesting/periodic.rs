@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+
+use super::{
+    statistics::{finite, mean, std},
+    DailyResult,
+};
+
+/// Balance at the end of every day
+pub fn daily_balance<'a>(
+    daily_results: impl IntoIterator<Item = &'a DailyResult>,
+    capital: f64,
+) -> Vec<(NaiveDate, f64)> {
+    let mut balance = capital;
+    daily_results
+        .into_iter()
+        .map(|daily_result| {
+            balance += daily_result.net_pnl;
+            (daily_result.date, balance)
+        })
+        .collect()
+}
+
+/// Return of every month in percent, NaN for months without data, and the return of the whole year
+pub fn monthly_returns(balance: &[(NaiveDate, f64)], capital: f64) -> Vec<(i32, [f64; 12], f64)> {
+    // Balance at the end of each month
+    let mut month_end: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+    for (date, value) in balance {
+        month_end.insert((date.year(), date.month()), *value);
+    }
+
+    let mut table: Vec<(i32, [f64; 12], f64)> = Vec::new();
+    let mut pre_balance = capital;
+    let mut year_start = capital;
+    for ((year, month), value) in month_end {
+        if table.last().is_none_or(|row| row.0 != year) {
+            table.push((year, [f64::NAN; 12], 0.0));
+            year_start = pre_balance;
+        }
+        let row = table.last_mut().unwrap();
+        row.1[month as usize - 1] = (value / pre_balance - 1.0) * 100.0;
+        row.2 = (value / year_start - 1.0) * 100.0;
+        pre_balance = value;
+    }
+    table
+}
+
+/// Return, max drawdown and sharpe ratio of every year
+pub struct YearlyStatistics {
+    pub year: i32,
+    pub trading_days: i64,
+    pub total_return: f64,
+    pub max_ddpercent: f64,
+    pub sharpe_ratio: f64,
+}
+
+/// Log returns, 0 when the balance falls below 0, the same as calculate_statistics
+pub fn log_returns(balance: &[(NaiveDate, f64)], capital: f64) -> Vec<f64> {
+    let mut pre_balance = capital;
+    balance
+        .iter()
+        .map(|(_, value)| {
+            let x = value / pre_balance;
+            pre_balance = *value;
+            if x > 0.0 {
+                x.ln()
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn sharpe_ratio(returns: &[f64], risk_free: f64, annual_days: f64) -> f64 {
+    let daily_risk_free = risk_free / annual_days.sqrt();
+    finite((mean(returns) * 100.0 - daily_risk_free) / (std(returns) * 100.0) * annual_days.sqrt())
+}
+
+/// Largest percent drop from a high within the balances
+fn max_ddpercent(balance: &[(NaiveDate, f64)]) -> f64 {
+    let mut highlevel = f64::MIN;
+    let mut max_ddpercent: f64 = 0.0;
+    for (_, value) in balance {
+        highlevel = highlevel.max(*value);
+        max_ddpercent = max_ddpercent.min((value - highlevel) / highlevel * 100.0);
+    }
+    max_ddpercent
+}
+
+pub fn yearly_statistics(
+    balance: &[(NaiveDate, f64)],
+    capital: f64,
+    risk_free: f64,
+    annual_days: i64,
+) -> Vec<YearlyStatistics> {
+    let returns = log_returns(balance, capital);
+    let mut table = Vec::new();
+    let mut start = 0;
+    while start < balance.len() {
+        let year = balance[start].0.year();
+        let end = start
+            + balance[start..]
+                .iter()
+                .take_while(|(date, _)| date.year() == year)
+                .count();
+        let pre_balance = if start == 0 {
+            capital
+        } else {
+            balance[start - 1].1
+        };
+        // The drawdown counts from the balance the year started with
+        let mut year_balance = vec![(balance[start].0, pre_balance)];
+        year_balance.extend_from_slice(&balance[start..end]);
+        table.push(YearlyStatistics {
+            year,
+            trading_days: (end - start) as i64,
+            total_return: (balance[end - 1].1 / pre_balance - 1.0) * 100.0,
+            max_ddpercent: max_ddpercent(&year_balance),
+            sharpe_ratio: sharpe_ratio(&returns[start..end], risk_free, annual_days as f64),
+        });
+        start = end;
+    }
+    table
+}
+
+/// Sharpe ratio, annualized volatility in percent and max drawdown percent
+/// of the trailing window ending on every day, NaN until the window is full
+pub fn rolling_statistics(
+    balance: &[(NaiveDate, f64)],
+    capital: f64,
+    window: usize,
+    risk_free: f64,
+    annual_days: i64,
+) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let returns = log_returns(balance, capital);
+    let annual_days = annual_days as f64;
+    let mut sharpe = vec![f64::NAN; balance.len()];
+    let mut volatility = vec![f64::NAN; balance.len()];
+    let mut drawdown = vec![f64::NAN; balance.len()];
+    for end in window.max(1)..=balance.len() {
+        let start = end - window.max(1);
+        sharpe[end - 1] = sharpe_ratio(&returns[start..end], risk_free, annual_days);
+        volatility[end - 1] = finite(std(&returns[start..end]) * annual_days.sqrt() * 100.0);
+        drawdown[end - 1] = max_ddpercent(&balance[start..end]);
+    }
+    (sharpe, volatility, drawdown)
+}
@@ -0,0 +1,75 @@
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+
+use super::drawdown::underwater;
+use crate::trader::object::TradeData;
+
+/// Page with inline styles and a small SVG renderer, the data replaces the placeholder
+const TEMPLATE: &str = include_str!("report.html");
+const PLACEHOLDER: &str = "__REPORT_DATA__";
+
+/// Everything shown in the html report
+pub struct Report {
+    pub title: String,
+    pub created: String,
+    /// Lines of BacktestStatistics::report_lines
+    pub statistics: Vec<String>,
+    pub parameters: Vec<(String, String)>,
+    pub setting: Vec<(String, String)>,
+    pub data_range: Vec<(String, String)>,
+    /// Date, balance and net pnl of every day
+    pub daily: Vec<(NaiveDate, f64, f64)>,
+    pub trades: Vec<TradeData>,
+}
+
+/// Split "label：\tvalue" into its two parts, separators are skipped
+fn statistics_rows(lines: &[String]) -> Vec<Value> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let (label, value) = line.split_once(['：', ':', '\t'])?;
+            Some(json!([label.trim(), value.trim()]))
+        })
+        .collect()
+}
+
+fn pairs(pairs: &[(String, String)]) -> Vec<Value> {
+    pairs
+        .iter()
+        .map(|(key, value)| json!([key, value]))
+        .collect()
+}
+
+impl Report {
+    pub fn render(&self) -> String {
+        let balance: Vec<(NaiveDate, f64)> = self
+            .daily
+            .iter()
+            .map(|(date, balance, _)| (*date, *balance))
+            .collect();
+        let data = json!({
+            "title": self.title,
+            "created": self.created,
+            "statistics": statistics_rows(&self.statistics),
+            "parameters": pairs(&self.parameters),
+            "setting": pairs(&self.setting),
+            "data_range": pairs(&self.data_range),
+            "daily": {
+                "date": self.daily.iter().map(|(date, _, _)| date.to_string()).collect::<Vec<_>>(),
+                "balance": balance.iter().map(|(_, balance)| *balance).collect::<Vec<_>>(),
+                "drawdown": underwater(&balance).iter().map(|(_, drawdown, _)| *drawdown).collect::<Vec<_>>(),
+                "net_pnl": self.daily.iter().map(|(_, _, net_pnl)| *net_pnl).collect::<Vec<_>>(),
+            },
+            "trades": self.trades.iter().map(|trade| json!([
+                trade.datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                trade.direction.to_string(),
+                trade.offset.to_string(),
+                trade.price,
+                trade.volume,
+                trade.tradeid.trim(),
+            ])).collect::<Vec<_>>(),
+        });
+        // A "</script>" inside a string would end the data block early
+        TEMPLATE.replace(PLACEHOLDER, &data.to_string().replace("</", "<\\/"))
+    }
+}
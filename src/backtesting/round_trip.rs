@@ -0,0 +1,399 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+use pyo3::{prelude::*, types::PyDict};
+use strum::{Display, EnumString};
+
+use super::statistics::finite;
+use crate::trader::{constant::Direction, object::TradeData};
+
+/// Volumes this small are left over by float arithmetic on fractional fills, they count as none
+pub const VOLUME_EPSILON: f64 = 1e-9;
+
+/// Which open lot a closing fill is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+pub enum MatchingMethod {
+    /// Oldest lot first
+    FIFO,
+    /// Newest lot first
+    LIFO,
+}
+
+/// Price ranges of the replayed data and where fills happened in it,
+/// so that round trips know how long they were held and how far the price went
+#[derive(Default)]
+pub struct TradeTrace {
+    /// High and low price of every bar replayed so far, the last price of ticks
+    highs: Vec<f64>,
+    lows: Vec<f64>,
+    /// vt_tradeid to the number of bars replayed when it was filled
+    pub trade_bars: HashMap<String, usize>,
+}
+
+impl TradeTrace {
+    pub fn record_bar(&mut self, high: f64, low: f64) {
+        self.highs.push(high);
+        self.lows.push(low);
+    }
+
+    pub fn bar_count(&self) -> usize {
+        self.highs.len()
+    }
+
+    /// Highest and lowest price while a position opened on entry_bar and closed on exit_bar was held.
+    /// A fill crosses at the start of its bar, so the exit bar itself is not included.
+    fn price_range(&self, entry_bar: usize, exit_bar: usize, prices: [f64; 2]) -> (f64, f64) {
+        // Bar numbers count from 1
+        let start = entry_bar.saturating_sub(1);
+        let held = start..exit_bar.saturating_sub(1).clamp(start, self.highs.len());
+        let high = self.highs[held.clone()]
+            .iter()
+            .chain(&prices)
+            .fold(f64::MIN, |a, b| a.max(*b));
+        let low = self.lows[held]
+            .iter()
+            .chain(&prices)
+            .fold(f64::MAX, |a, b| a.min(*b));
+        (high, low)
+    }
+}
+
+/// A position opened and closed again. Partial fills give one round trip per matched volume.
+#[pyclass(get_all)]
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    /// LONG for bought then sold, SHORT for sold then bought
+    pub direction: Direction,
+    pub volume: f64,
+    pub entry_time: DateTime<Tz>,
+    pub entry_price: f64,
+    pub entry_tradeid: String,
+    pub exit_time: DateTime<Tz>,
+    pub exit_price: f64,
+    pub exit_tradeid: String,
+    pub pnl: f64,
+    pub commission: f64,
+    pub slippage: f64,
+    /// pnl after commission and slippage
+    pub net_pnl: f64,
+    pub holding_period: TimeDelta,
+    pub bars_held: i64,
+    /// Maximum adverse excursion, the worst unrealized pnl while held (0 or negative)
+    pub mae: f64,
+    /// Maximum favorable excursion, the best unrealized pnl while held (0 or positive)
+    pub mfe: f64,
+}
+
+#[pymethods]
+impl RoundTrip {
+    fn __repr__(&self) -> String {
+        format!(
+            "RoundTrip(direction={}, volume={}, entry_time={}, entry_price={}, exit_time={}, exit_price={}, net_pnl={:.2}, mae={:.2}, mfe={:.2})",
+            self.direction,
+            self.volume,
+            self.entry_time,
+            self.entry_price,
+            self.exit_time,
+            self.exit_price,
+            self.net_pnl,
+            self.mae,
+            self.mfe
+        )
+    }
+}
+
+/// Aggregates over the round trips of a backtest
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default)]
+pub struct RoundTripStatistics {
+    pub total_trips: i64,
+    pub winning_trips: i64,
+    pub losing_trips: i64,
+    pub win_rate: f64,
+    pub gross_profit: f64,
+    pub gross_loss: f64,
+    pub profit_factor: f64,
+    pub average_win: f64,
+    pub average_loss: f64,
+    /// Average net pnl of a round trip
+    pub expectancy: f64,
+    pub max_win_streak: i64,
+    pub max_loss_streak: i64,
+    pub average_bars_held: f64,
+    pub average_holding_period: TimeDelta,
+}
+
+#[pymethods]
+impl RoundTripStatistics {
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("total_trips", self.total_trips)?;
+        dict.set_item("winning_trips", self.winning_trips)?;
+        dict.set_item("losing_trips", self.losing_trips)?;
+        dict.set_item("win_rate", self.win_rate)?;
+        dict.set_item("gross_profit", self.gross_profit)?;
+        dict.set_item("gross_loss", self.gross_loss)?;
+        dict.set_item("profit_factor", self.profit_factor)?;
+        dict.set_item("average_win", self.average_win)?;
+        dict.set_item("average_loss", self.average_loss)?;
+        dict.set_item("expectancy", self.expectancy)?;
+        dict.set_item("max_win_streak", self.max_win_streak)?;
+        dict.set_item("max_loss_streak", self.max_loss_streak)?;
+        dict.set_item("average_bars_held", self.average_bars_held)?;
+        dict.set_item("average_holding_period", self.average_holding_period)?;
+        Ok(dict.unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RoundTripStatistics(total_trips={}, win_rate={:.4}, profit_factor={:.4}, expectancy={:.2})",
+            self.total_trips, self.win_rate, self.profit_factor, self.expectancy
+        )
+    }
+}
+
+struct OpenLot<'a> {
+    trade: &'a TradeData,
+    bar: usize,
+    volume: f64,
+}
+
+/// Costs of a volume filled at a price, computed the same way as DailyResult
+fn costs(volume: f64, price: f64, size: f64, rate: f64, slippage: f64) -> (f64, f64) {
+    (volume * size * price * rate, volume * size * slippage)
+}
+
+/// Pair fills into round trips. `trades` must be in fill order.
+pub fn match_round_trips(
+    trades: &[TradeData],
+    trace: &TradeTrace,
+    method: MatchingMethod,
+    size: f64,
+    rate: f64,
+    slippage: f64,
+) -> Vec<RoundTrip> {
+    let mut lots: VecDeque<OpenLot> = VecDeque::new();
+    let mut trips = Vec::new();
+    for trade in trades {
+        let bar = trace
+            .trade_bars
+            .get(&trade.vt_tradeid())
+            .copied()
+            .unwrap_or_default();
+        let mut remaining = trade.volume;
+        while remaining > VOLUME_EPSILON {
+            // Open lots all have the same direction, a fill in the other direction closes them
+            let closing = lots
+                .front()
+                .is_some_and(|lot| lot.trade.direction != trade.direction);
+            if !closing {
+                lots.push_back(OpenLot {
+                    trade,
+                    bar,
+                    volume: remaining,
+                });
+                break;
+            }
+            let lot = match method {
+                MatchingMethod::FIFO => lots.front_mut().unwrap(),
+                MatchingMethod::LIFO => lots.back_mut().unwrap(),
+            };
+            let volume = lot.volume.min(remaining);
+            let entry = lot.trade;
+            let sign = if entry.direction == Direction::LONG {
+                1.0
+            } else {
+                -1.0
+            };
+            let pnl = (trade.price - entry.price) * volume * size * sign;
+            let (high, low) = trace.price_range(lot.bar, bar, [entry.price, trade.price]);
+            let (best, worst) = if sign > 0.0 { (high, low) } else { (low, high) };
+            let (entry_commission, entry_slippage) =
+                costs(volume, entry.price, size, rate, slippage);
+            let (exit_commission, exit_slippage) = costs(volume, trade.price, size, rate, slippage);
+            trips.push(RoundTrip {
+                direction: entry.direction,
+                volume,
+                entry_time: entry.datetime,
+                entry_price: entry.price,
+                entry_tradeid: entry.tradeid.trim().to_string(),
+                exit_time: trade.datetime,
+                exit_price: trade.price,
+                exit_tradeid: trade.tradeid.trim().to_string(),
+                pnl,
+                commission: entry_commission + exit_commission,
+                slippage: entry_slippage + exit_slippage,
+                net_pnl: pnl - entry_commission - exit_commission - entry_slippage - exit_slippage,
+                holding_period: trade.datetime - entry.datetime,
+                bars_held: bar as i64 - lot.bar as i64,
+                mae: (worst - entry.price) * volume * size * sign,
+                mfe: (best - entry.price) * volume * size * sign,
+            });
+
+            lot.volume -= volume;
+            remaining -= volume;
+            if lot.volume <= VOLUME_EPSILON {
+                match method {
+                    MatchingMethod::FIFO => lots.pop_front(),
+                    MatchingMethod::LIFO => lots.pop_back(),
+                };
+            }
+        }
+    }
+    trips
+}
+
+pub fn round_trip_statistics(trips: &[RoundTrip]) -> RoundTripStatistics {
+    if trips.is_empty() {
+        return RoundTripStatistics::default();
+    }
+    let total = trips.len() as f64;
+    let wins: Vec<f64> = trips
+        .iter()
+        .map(|t| t.net_pnl)
+        .filter(|pnl| *pnl > 0.0)
+        .collect();
+    let losses: Vec<f64> = trips
+        .iter()
+        .map(|t| t.net_pnl)
+        .filter(|pnl| *pnl < 0.0)
+        .collect();
+    let gross_profit: f64 = wins.iter().sum();
+    let gross_loss: f64 = losses.iter().sum();
+
+    // Streaks follow the order trips are closed in, break-even trips end both
+    let (mut win_streak, mut loss_streak) = (0, 0);
+    let (mut max_win_streak, mut max_loss_streak) = (0, 0);
+    for trip in trips {
+        if trip.net_pnl > 0.0 {
+            win_streak += 1;
+            loss_streak = 0;
+        } else if trip.net_pnl < 0.0 {
+            loss_streak += 1;
+            win_streak = 0;
+        } else {
+            win_streak = 0;
+            loss_streak = 0;
+        }
+        max_win_streak = max_win_streak.max(win_streak);
+        max_loss_streak = max_loss_streak.max(loss_streak);
+    }
+
+    let total_holding: TimeDelta = trips.iter().map(|t| t.holding_period).sum();
+    RoundTripStatistics {
+        total_trips: trips.len() as i64,
+        winning_trips: wins.len() as i64,
+        losing_trips: losses.len() as i64,
+        win_rate: wins.len() as f64 / total,
+        gross_profit,
+        gross_loss,
+        profit_factor: finite(gross_profit / -gross_loss),
+        average_win: finite(gross_profit / wins.len() as f64),
+        average_loss: finite(gross_loss / losses.len() as f64),
+        expectancy: trips.iter().map(|t| t.net_pnl).sum::<f64>() / total,
+        max_win_streak,
+        max_loss_streak,
+        average_bars_held: trips.iter().map(|t| t.bars_held as f64).sum::<f64>() / total,
+        average_holding_period: total_holding / trips.len() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::trader::constant::Offset_;
+
+    fn trade(id: usize, direction: Direction, price: f64, volume: f64) -> TradeData {
+        TradeData {
+            gateway_name: "BACKTESTING",
+            symbol: "rb".to_string(),
+            exchange: "SHFE".to_string(),
+            orderid: id.to_string(),
+            tradeid: id.to_string(),
+            direction,
+            offset: Offset_::NONE,
+            price,
+            volume,
+            datetime: Tz::Asia__Shanghai
+                .with_ymd_and_hms(2024, 1, 2, 9, id as u32, 0)
+                .unwrap(),
+        }
+    }
+
+    /// (entry tradeid, exit tradeid, volume, pnl) of every round trip
+    fn trips(trades: &[TradeData], method: MatchingMethod) -> Vec<(String, String, f64, f64)> {
+        match_round_trips(trades, &TradeTrace::default(), method, 1.0, 0.0, 0.0)
+            .into_iter()
+            .map(|trip| (trip.entry_tradeid, trip.exit_tradeid, trip.volume, trip.pnl))
+            .collect()
+    }
+
+    fn trip(entry: usize, exit: usize, volume: f64, pnl: f64) -> (String, String, f64, f64) {
+        (entry.to_string(), exit.to_string(), volume, pnl)
+    }
+
+    #[test]
+    fn partial_fills() {
+        let trades = [
+            trade(1, Direction::LONG, 100.0, 2.0),
+            trade(2, Direction::LONG, 102.0, 1.0),
+            trade(3, Direction::SHORT, 105.0, 1.0),
+            trade(4, Direction::SHORT, 101.0, 2.0),
+        ];
+        assert_eq!(
+            trips(&trades, MatchingMethod::FIFO),
+            vec![
+                trip(1, 3, 1.0, 5.0),
+                trip(1, 4, 1.0, 1.0),
+                trip(2, 4, 1.0, -1.0)
+            ]
+        );
+        assert_eq!(
+            trips(&trades, MatchingMethod::LIFO),
+            vec![trip(2, 3, 1.0, 3.0), trip(1, 4, 2.0, 2.0)]
+        );
+    }
+
+    #[test]
+    fn reversing_fill() {
+        let trades = [
+            trade(1, Direction::LONG, 100.0, 1.0),
+            trade(2, Direction::SHORT, 110.0, 3.0),
+            trade(3, Direction::LONG, 105.0, 2.0),
+        ];
+        for method in [MatchingMethod::FIFO, MatchingMethod::LIFO] {
+            assert_eq!(
+                trips(&trades, method),
+                vec![trip(1, 2, 1.0, 10.0), trip(2, 3, 2.0, 10.0)]
+            );
+        }
+    }
+
+    #[test]
+    fn fractional_fills() {
+        // 0.1 + 0.2 is not exactly 0.3, no dust lot may be left open
+        let trades = [
+            trade(1, Direction::LONG, 100.0, 0.1),
+            trade(2, Direction::LONG, 100.0, 0.2),
+            trade(3, Direction::SHORT, 101.0, 0.3),
+            trade(4, Direction::SHORT, 102.0, 0.5),
+            trade(5, Direction::LONG, 101.0, 0.5),
+        ];
+        for (method, first, second) in [
+            (MatchingMethod::FIFO, "1", "2"),
+            (MatchingMethod::LIFO, "2", "1"),
+        ] {
+            let trips = trips(&trades, method);
+            let pairs: Vec<(&str, &str)> = trips
+                .iter()
+                .map(|(entry, exit, _, _)| (entry.as_str(), exit.as_str()))
+                .collect();
+            assert_eq!(pairs, vec![(first, "3"), (second, "3"), ("4", "5")]);
+            assert!((trips[2].2 - 0.5).abs() < VOLUME_EPSILON);
+            assert!((trips[2].3 - 0.5).abs() < VOLUME_EPSILON);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+/*! Parameter spaces beyond lists of values, and samplers drawing a budget of settings from them. */
+
+use std::{collections::HashSet, str::FromStr};
+
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use strum::{Display, EnumString};
+
+/// How the settings are drawn from the parameter space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+pub enum Sampler {
+    /// Every point independently and uniformly
+    RANDOM,
+    /// Latin hypercube, every parameter's range is cut into as many strata as the budget, each hit once
+    LHS,
+    /// Low discrepancy sequence, randomized by a digital shift drawn from the seed
+    SOBOL,
+}
+
+/// Numeric parameter between start and end, both included
+#[pyclass(get_all)]
+#[derive(Debug, Clone)]
+pub struct ParameterRange {
+    pub start: f64,
+    pub end: f64,
+    /// Values are spread evenly over the logarithm of the range
+    pub log: bool,
+    /// Values are rounded to integers
+    pub integer: bool,
+    /// Number of values the grid has, None leaves the range continuous
+    pub count: Option<usize>,
+}
+
+#[pymethods]
+impl ParameterRange {
+    #[new]
+    #[pyo3(signature = (start, end, log=false, integer=false, count=None))]
+    pub fn new(
+        start: f64,
+        end: f64,
+        log: bool,
+        integer: bool,
+        count: Option<usize>,
+    ) -> PyResult<Self> {
+        if start.partial_cmp(&end) != Some(std::cmp::Ordering::Less) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "参数优化起始点必须小于终止点",
+            ));
+        }
+        if log && start <= 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "对数范围的起始点必须大于0",
+            ));
+        }
+        if integer && start.ceil() > end.floor() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "整数范围内没有整数",
+            ));
+        }
+        if count.is_some_and(|count| count < 2) {
+            return Err(pyo3::exceptions::PyValueError::new_err("count至少为2"));
+        }
+        Ok(ParameterRange {
+            start,
+            end,
+            log,
+            integer,
+            count,
+        })
+    }
+
+    /// Values tried by the grid and the genetic algorithm, None when the range is continuous.
+    /// Integer ranges without a count have every integer in the range.
+    pub fn grid(&self, py: Python<'_>) -> PyResult<Option<Vec<PyObject>>> {
+        let mut values: Vec<f64> = match self.count {
+            Some(count) => (0..count).map(|i| self.grid_value(i, count)).collect(),
+            None if self.integer => (self.start.ceil() as i64..=self.end.floor() as i64)
+                .map(|value| value as f64)
+                .collect(),
+            None => return Ok(None),
+        };
+        values.dedup();
+        values
+            .into_iter()
+            .map(|value| self.to_object(py, value))
+            .collect::<PyResult<Vec<_>>>()
+            .map(Some)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ParameterRange(start={}, end={}, log={}, integer={}, count={})",
+            self.start,
+            self.end,
+            self.log,
+            self.integer,
+            self.count
+                .map_or("None".to_string(), |count| count.to_string())
+        )
+    }
+}
+
+impl ParameterRange {
+    /// Point of the range at the fraction u of its length, or of its logarithm's
+    fn scale(&self, u: f64) -> f64 {
+        if self.log {
+            (self.start.ln() + u * (self.end.ln() - self.start.ln())).exp()
+        } else {
+            self.start + u * (self.end - self.start)
+        }
+    }
+
+    fn grid_value(&self, i: usize, count: usize) -> f64 {
+        let value = self.scale(i as f64 / (count - 1) as f64);
+        if self.integer {
+            value.round().clamp(self.start.ceil(), self.end.floor())
+        } else {
+            value
+        }
+    }
+
+    fn to_object(&self, py: Python<'_>, value: f64) -> PyResult<PyObject> {
+        Ok(if self.integer {
+            (value as i64).into_pyobject(py)?.into_any().unbind()
+        } else {
+            value.into_pyobject(py)?.into_any().unbind()
+        })
+    }
+
+    /// Value at u in [0, 1), grid values are equally likely when there is a count
+    fn sample(&self, py: Python<'_>, u: f64) -> PyResult<PyObject> {
+        let (low, high) = (self.start.ceil(), self.end.floor());
+        let value = match self.count {
+            Some(count) => self.grid_value(pick(u, count), count),
+            None if self.integer && !self.log => low + pick(u, (high - low) as usize + 1) as f64,
+            None if self.integer => self.scale(u).round().clamp(low, high),
+            None => self.scale(u),
+        };
+        self.to_object(py, value)
+    }
+}
+
+fn pick(u: f64, len: usize) -> usize {
+    ((u * len as f64) as usize).min(len - 1)
+}
+
+/// Degree, coefficients and initial direction numbers of the dimensions after the first,
+/// from the new-joe-kuo-6.21201 table of Joe and Kuo
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+const SOBOL_BITS: usize = 32;
+
+fn sobol_directions(dimension: usize) -> [u32; SOBOL_BITS] {
+    let mut v = [0u32; SOBOL_BITS];
+    if dimension == 0 {
+        for (k, v) in v.iter_mut().enumerate() {
+            *v = 1 << (SOBOL_BITS - 1 - k);
+        }
+        return v;
+    }
+    let (s, a, m) = SOBOL_DIRECTIONS[dimension - 1];
+    let s = s as usize;
+    for k in 0..SOBOL_BITS {
+        v[k] = if k < s {
+            m[k] << (SOBOL_BITS - 1 - k)
+        } else {
+            let mut value = v[k - s] ^ (v[k - s] >> s);
+            for j in 1..s {
+                if (a >> (s - 1 - j)) & 1 == 1 {
+                    value ^= v[k - j];
+                }
+            }
+            value
+        };
+    }
+    v
+}
+
+/// What a parameter takes its values from
+enum Space<'py> {
+    Values(Bound<'py, PyList>),
+    Range(ParameterRange),
+}
+
+/// Points in the unit cube, one coordinate for every dimension
+fn unit_points(
+    sampler: Sampler,
+    dimensions: usize,
+    budget: usize,
+    rng: &mut StdRng,
+) -> Result<Vec<Vec<f64>>, String> {
+    let mut points = vec![vec![0.0; dimensions]; budget];
+    match sampler {
+        Sampler::RANDOM => {
+            for point in points.iter_mut() {
+                point.iter_mut().for_each(|u| *u = rng.gen());
+            }
+        }
+        Sampler::LHS => {
+            for d in 0..dimensions {
+                let mut strata: Vec<usize> = (0..budget).collect();
+                strata.shuffle(rng);
+                for (point, stratum) in points.iter_mut().zip(strata) {
+                    point[d] = (stratum as f64 + rng.gen::<f64>()) / budget as f64;
+                }
+            }
+        }
+        Sampler::SOBOL => {
+            if dimensions > SOBOL_DIRECTIONS.len() + 1 {
+                return Err(format!(
+                    "Sobol采样最多支持{}个参数",
+                    SOBOL_DIRECTIONS.len() + 1
+                ));
+            }
+            for d in 0..dimensions {
+                let v = sobol_directions(d);
+                let shift: u32 = rng.gen();
+                let mut x = 0u32;
+                for (i, point) in points.iter_mut().enumerate() {
+                    if i > 0 {
+                        // Gray code order, one direction number changes from a point to the next
+                        x ^= v[(!(i - 1)).trailing_zeros() as usize];
+                    }
+                    point[d] = (x ^ shift) as f64 / (1u64 << SOBOL_BITS) as f64;
+                }
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// Up to budget settings drawn from params, which maps every parameter to a list
+/// of values or a ParameterRange. Repeated settings are only kept once.
+#[pyfunction]
+#[pyo3(signature = (params, sampler="RANDOM", budget=100, seed=0))]
+pub fn sample_settings<'py>(
+    py: Python<'py>,
+    params: &Bound<'py, PyDict>,
+    sampler: &str,
+    budget: usize,
+    seed: u64,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let sampler = Sampler::from_str(sampler).map_err(|_| {
+        pyo3::exceptions::PyValueError::new_err("sampler字段只能是RANDOM,LHS,SOBOL中的一个")
+    })?;
+    let mut dimensions = Vec::new();
+    for (name, space) in params.iter() {
+        let space = match space.extract::<ParameterRange>() {
+            Ok(range) => Space::Range(range),
+            Err(_) => {
+                let values = space.downcast_into::<PyList>()?;
+                if values.is_empty() {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "参数{}没有可选的取值",
+                        name
+                    )));
+                }
+                Space::Values(values)
+            }
+        };
+        dimensions.push((name, space));
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let points = unit_points(sampler, dimensions.len(), budget, &mut rng)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    let mut seen = HashSet::new();
+    let mut settings = Vec::new();
+    for point in points {
+        let setting = PyDict::new(py);
+        for ((name, space), u) in dimensions.iter().zip(point) {
+            let value = match space {
+                Space::Range(range) => range.sample(py, u)?,
+                Space::Values(values) => values.get_item(pick(u, values.len()))?.unbind(),
+            };
+            setting.set_item(name, value)?;
+        }
+        if seen.insert(setting.repr()?.to_string()) {
+            settings.push(setting);
+        }
+    }
+    Ok(settings)
+}
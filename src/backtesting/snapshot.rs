@@ -0,0 +1,339 @@
+/*! History data saved once by the optimizer and read back by every worker process. */
+
+use std::{collections::LinkedList, error::Error, path::Path, str::FromStr};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::trader::{
+    constant::Interval,
+    object::{BarData, MixData, TickData},
+};
+
+const MAGIC: &[u8; 8] = b"VNRSHIST";
+/// Version 1 stored datetimes in milliseconds, version 2 in microseconds
+const SNAPSHOT_VERSION: u32 = 2;
+
+type SnapshotResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Fields shared by every item, stored once before the items
+#[derive(Serialize, Deserialize)]
+struct Header {
+    tick: bool,
+    count: usize,
+    symbol: String,
+    exchange: String,
+    interval: String,
+    timezone: String,
+}
+
+fn tick_values(tick: &TickData) -> [f64; 31] {
+    [
+        tick.volume,
+        tick.turnover,
+        tick.open_interest,
+        tick.last_price,
+        tick.last_volume,
+        tick.limit_up,
+        tick.limit_down,
+        tick.open_price,
+        tick.high_price,
+        tick.low_price,
+        tick.pre_close,
+        tick.bid_price_1,
+        tick.bid_price_2,
+        tick.bid_price_3,
+        tick.bid_price_4,
+        tick.bid_price_5,
+        tick.ask_price_1,
+        tick.ask_price_2,
+        tick.ask_price_3,
+        tick.ask_price_4,
+        tick.ask_price_5,
+        tick.bid_volume_1,
+        tick.bid_volume_2,
+        tick.bid_volume_3,
+        tick.bid_volume_4,
+        tick.bid_volume_5,
+        tick.ask_volume_1,
+        tick.ask_volume_2,
+        tick.ask_volume_3,
+        tick.ask_volume_4,
+        tick.ask_volume_5,
+    ]
+}
+
+/// Items are fixed size little endian records, ticks also carry their name.
+/// Datetimes are stored as microseconds since the epoch
+pub fn write_snapshot(path: &Path, history_data: &LinkedList<MixData>) -> SnapshotResult<()> {
+    let (tick, symbol, exchange, interval, timezone) = match history_data.front() {
+        Some(MixData::BarData(bar)) => (
+            false,
+            &bar.symbol,
+            &bar.exchange,
+            bar.interval,
+            bar.datetime.timezone(),
+        ),
+        Some(MixData::TickData(tick)) => (
+            true,
+            &tick.symbol,
+            &tick.exchange,
+            Interval::TICK,
+            tick.datetime.timezone(),
+        ),
+        None => return Err("没有可以保存的历史数据".into()),
+    };
+    let header = serde_json::to_vec(&Header {
+        tick,
+        count: history_data.len(),
+        symbol: symbol.clone(),
+        exchange: exchange.clone(),
+        interval: interval.to_string(),
+        timezone: timezone.name().to_string(),
+    })?;
+
+    let mut buffer: Vec<u8> = Vec::with_capacity(history_data.len() * if tick { 280 } else { 64 });
+    buffer.extend_from_slice(MAGIC);
+    buffer.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    buffer.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&header);
+    for item in history_data {
+        match item {
+            MixData::BarData(bar) => {
+                buffer.extend_from_slice(&bar.datetime.timestamp_micros().to_le_bytes());
+                for value in [
+                    bar.volume,
+                    bar.turnover,
+                    bar.open_interest,
+                    bar.open_price,
+                    bar.high_price,
+                    bar.low_price,
+                    bar.close_price,
+                ] {
+                    buffer.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            MixData::TickData(tick) => {
+                buffer.extend_from_slice(&tick.datetime.timestamp_micros().to_le_bytes());
+                buffer
+                    .extend_from_slice(&tick.localtime.and_utc().timestamp_micros().to_le_bytes());
+                buffer.extend_from_slice(&(tick.name.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(tick.name.as_bytes());
+                for value in tick_values(tick) {
+                    buffer.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    // Written under another name first, so a worker never sees half a file
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, buffer)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+    millis: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> SnapshotResult<&'a [u8]> {
+        let bytes = self
+            .buffer
+            .get(self.position..self.position + len)
+            .ok_or("历史数据快照不完整")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> SnapshotResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> SnapshotResult<i64> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn f64(&mut self) -> SnapshotResult<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn timestamp(&mut self) -> SnapshotResult<DateTime<Utc>> {
+        let value = self.i64()?;
+        let datetime = if self.millis {
+            DateTime::from_timestamp_millis(value)
+        } else {
+            DateTime::from_timestamp_micros(value)
+        };
+        Ok(datetime.ok_or("时间戳超出范围")?)
+    }
+
+    fn datetime(&mut self, tz: Tz) -> SnapshotResult<DateTime<Tz>> {
+        Ok(self.timestamp()?.with_timezone(&tz))
+    }
+}
+
+/// The whole file is read into memory and decoded, so every worker process
+/// holds its own private copy of the history data rather than sharing pages
+pub fn read_snapshot(path: &Path) -> SnapshotResult<LinkedList<MixData>> {
+    let buffer = std::fs::read(path)?;
+    let mut reader = Reader {
+        buffer: &buffer,
+        position: 0,
+        millis: false,
+    };
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err("不是历史数据快照文件".into());
+    }
+    let version = reader.u32()?;
+    if version > SNAPSHOT_VERSION {
+        return Err(format!("不支持的历史数据快照版本{}", version).into());
+    }
+    reader.millis = version == 1;
+    let header_len = reader.u32()? as usize;
+    let header: Header = serde_json::from_slice(reader.bytes(header_len)?)?;
+    let tz = Tz::from_str(&header.timezone)?;
+    let interval = Interval::from_str(&header.interval)?;
+
+    let mut history_data = LinkedList::new();
+    for _ in 0..header.count {
+        let datetime = reader.datetime(tz)?;
+        if header.tick {
+            let localtime = reader.timestamp()?.naive_utc();
+            let name_len = reader.u32()? as usize;
+            let name = String::from_utf8(reader.bytes(name_len)?.to_vec())?;
+            let mut values = [0.0; 31];
+            for value in values.iter_mut() {
+                *value = reader.f64()?;
+            }
+            history_data.push_back(MixData::TickData(tick_from_values(
+                &header, datetime, localtime, name, values,
+            )));
+        } else {
+            history_data.push_back(MixData::BarData(BarData {
+                gateway_name: "DB",
+                symbol: header.symbol.clone(),
+                exchange: header.exchange.clone(),
+                datetime,
+                interval,
+                volume: reader.f64()?,
+                turnover: reader.f64()?,
+                open_interest: reader.f64()?,
+                open_price: reader.f64()?,
+                high_price: reader.f64()?,
+                low_price: reader.f64()?,
+                close_price: reader.f64()?,
+            }));
+        }
+    }
+    Ok(history_data)
+}
+
+fn tick_from_values(
+    header: &Header,
+    datetime: DateTime<Tz>,
+    localtime: NaiveDateTime,
+    name: String,
+    values: [f64; 31],
+) -> TickData {
+    let [volume, turnover, open_interest, last_price, last_volume, limit_up, limit_down, open_price, high_price, low_price, pre_close, bid_price_1, bid_price_2, bid_price_3, bid_price_4, bid_price_5, ask_price_1, ask_price_2, ask_price_3, ask_price_4, ask_price_5, bid_volume_1, bid_volume_2, bid_volume_3, bid_volume_4, bid_volume_5, ask_volume_1, ask_volume_2, ask_volume_3, ask_volume_4, ask_volume_5] =
+        values;
+    TickData {
+        gateway_name: "DB",
+        symbol: header.symbol.clone(),
+        exchange: header.exchange.clone(),
+        datetime,
+        name,
+        volume,
+        turnover,
+        open_interest,
+        last_price,
+        last_volume,
+        limit_up,
+        limit_down,
+        open_price,
+        high_price,
+        low_price,
+        pre_close,
+        bid_price_1,
+        bid_price_2,
+        bid_price_3,
+        bid_price_4,
+        bid_price_5,
+        ask_price_1,
+        ask_price_2,
+        ask_price_3,
+        ask_price_4,
+        ask_price_5,
+        bid_volume_1,
+        bid_volume_2,
+        bid_volume_3,
+        bid_volume_4,
+        bid_volume_5,
+        ask_volume_1,
+        ask_volume_2,
+        ask_volume_3,
+        ask_volume_4,
+        ask_volume_5,
+        localtime,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn sub_millisecond_datetimes_round_trip() {
+        let start = Tz::Asia__Shanghai
+            .with_ymd_and_hms(2024, 1, 2, 9, 0, 0)
+            .unwrap();
+        let history_data: LinkedList<MixData> = (0..3)
+            .map(|i| {
+                MixData::BarData(BarData {
+                    gateway_name: "DB",
+                    symbol: "rb".to_string(),
+                    exchange: "SHFE".to_string(),
+                    datetime: start + TimeDelta::microseconds(i * 250),
+                    interval: Interval::MINUTE,
+                    volume: 1.0,
+                    turnover: 0.0,
+                    open_interest: 0.0,
+                    open_price: 100.0,
+                    high_price: 101.0,
+                    low_price: 99.0,
+                    close_price: 100.5,
+                })
+            })
+            .collect();
+
+        let path =
+            std::env::temp_dir().join(format!("vnpyrs_snapshot_test_{}", std::process::id()));
+        write_snapshot(&path, &history_data).unwrap();
+        let loaded = read_snapshot(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let datetimes: Vec<_> = loaded
+            .unwrap()
+            .iter()
+            .map(|item| match item {
+                MixData::BarData(bar) => bar.datetime,
+                MixData::TickData(tick) => tick.datetime,
+            })
+            .collect();
+        let expected: Vec<_> = history_data
+            .iter()
+            .map(|item| match item {
+                MixData::BarData(bar) => bar.datetime,
+                MixData::TickData(tick) => tick.datetime,
+            })
+            .collect();
+        assert_eq!(datetimes, expected);
+    }
+}
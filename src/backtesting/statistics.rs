@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use pyo3::{exceptions::PyKeyError, prelude::*, types::PyDict, IntoPyObjectExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    benchmark::BenchmarkStatistics,
+    confidence::ConfidenceStatistics,
+    periodic::{daily_balance, yearly_statistics},
+    DailyResult,
+};
+
+/// Result of calculate_statistics. It can be used like the dict returned by vnpy,
+/// `statistics["sharpe_ratio"]` works and `to_dict()` gives a plain dict.
+#[pyclass(get_all)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BacktestStatistics {
+    /// None when there is no data or the balance fell to 0
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub total_days: i64,
+    pub profit_days: i64,
+    pub loss_days: i64,
+    pub capital: f64,
+    pub end_balance: f64,
+    pub max_drawdown: f64,
+    pub max_ddpercent: f64,
+    pub max_drawdown_duration: i64,
+    pub total_net_pnl: f64,
+    pub daily_net_pnl: f64,
+    pub total_commission: f64,
+    pub daily_commission: f64,
+    pub total_slippage: f64,
+    pub daily_slippage: f64,
+    pub total_turnover: f64,
+    pub daily_turnover: f64,
+    pub total_trade_count: i64,
+    pub daily_trade_count: f64,
+    pub total_return: f64,
+    pub annual_return: f64,
+    pub daily_return: f64,
+    pub return_std: f64,
+    pub sharpe_ratio: f64,
+    pub ewm_sharpe: f64,
+    pub return_drawdown_ratio: f64,
+    /// Drawdown of the equity marked at every bar or tick, None unless the equity is recorded
+    pub intraday_max_drawdown: Option<f64>,
+    pub intraday_max_ddpercent: Option<f64>,
+    /// Performance relative to the benchmark, None unless a benchmark is set
+    pub benchmark: Option<BenchmarkStatistics>,
+    /// Confidence intervals and deflated sharpe ratio, None unless set_confidence is called
+    pub confidence: Option<ConfidenceStatistics>,
+    /// Return of every calendar year in percent
+    pub yearly_returns: BTreeMap<i32, f64>,
+}
+
+impl BacktestStatistics {
+    /// Keys and values in the order of vnpy's statistics dict, empty dates are ""
+    pub(crate) fn items(&self, py: Python<'_>) -> PyResult<Vec<(&'static str, PyObject)>> {
+        let date = |date: Option<NaiveDate>| match date {
+            Some(date) => date.into_py_any(py),
+            None => "".into_py_any(py),
+        };
+        let mut items = vec![
+            ("start_date", date(self.start_date)?),
+            ("end_date", date(self.end_date)?),
+            ("total_days", self.total_days.into_py_any(py)?),
+            ("profit_days", self.profit_days.into_py_any(py)?),
+            ("loss_days", self.loss_days.into_py_any(py)?),
+            ("capital", self.capital.into_py_any(py)?),
+            ("end_balance", self.end_balance.into_py_any(py)?),
+            ("max_drawdown", self.max_drawdown.into_py_any(py)?),
+            ("max_ddpercent", self.max_ddpercent.into_py_any(py)?),
+            (
+                "max_drawdown_duration",
+                self.max_drawdown_duration.into_py_any(py)?,
+            ),
+            ("total_net_pnl", self.total_net_pnl.into_py_any(py)?),
+            ("daily_net_pnl", self.daily_net_pnl.into_py_any(py)?),
+            ("total_commission", self.total_commission.into_py_any(py)?),
+            ("daily_commission", self.daily_commission.into_py_any(py)?),
+            ("total_slippage", self.total_slippage.into_py_any(py)?),
+            ("daily_slippage", self.daily_slippage.into_py_any(py)?),
+            ("total_turnover", self.total_turnover.into_py_any(py)?),
+            ("daily_turnover", self.daily_turnover.into_py_any(py)?),
+            ("total_trade_count", self.total_trade_count.into_py_any(py)?),
+            ("daily_trade_count", self.daily_trade_count.into_py_any(py)?),
+            ("total_return", self.total_return.into_py_any(py)?),
+            ("annual_return", self.annual_return.into_py_any(py)?),
+            ("daily_return", self.daily_return.into_py_any(py)?),
+            ("return_std", self.return_std.into_py_any(py)?),
+            ("sharpe_ratio", self.sharpe_ratio.into_py_any(py)?),
+            ("ewm_sharpe", self.ewm_sharpe.into_py_any(py)?),
+            (
+                "return_drawdown_ratio",
+                self.return_drawdown_ratio.into_py_any(py)?,
+            ),
+        ];
+        if let (Some(drawdown), Some(ddpercent)) =
+            (self.intraday_max_drawdown, self.intraday_max_ddpercent)
+        {
+            items.push(("intraday_max_drawdown", drawdown.into_py_any(py)?));
+            items.push(("intraday_max_ddpercent", ddpercent.into_py_any(py)?));
+        }
+        if let Some(benchmark) = self.benchmark.as_ref() {
+            items.extend(benchmark.items(py)?);
+        }
+        if let Some(confidence) = self.confidence.as_ref() {
+            items.extend(confidence.items(py)?);
+        }
+        Ok(items)
+    }
+
+    /// Lines printed by the engine, same as vnpy
+    pub fn report_lines(&self) -> Vec<String> {
+        let date = |date: Option<NaiveDate>| date.map(|d| d.to_string()).unwrap_or_default();
+        let mut lines = vec![
+            "-".repeat(30),
+            format!("首个交易日：\t{}", date(self.start_date)),
+            format!("最后交易日：\t{}", date(self.end_date)),
+            format!("总交易日：\t{}", self.total_days),
+            format!("盈利交易日：\t{}", self.profit_days),
+            format!("亏损交易日：\t{}", self.loss_days),
+            format!("起始资金：\t{}", format_number(self.capital)),
+            format!("结束资金：\t{}", format_number(self.end_balance)),
+            format!("总收益率：\t{}%", format_number(self.total_return)),
+            format!("年化收益：\t{}%", format_number(self.annual_return)),
+            format!("最大回撤: \t{}", format_number(self.max_drawdown)),
+            format!("百分比最大回撤: {}%", format_number(self.max_ddpercent)),
+            format!("最大回撤天数: \t{}", self.max_drawdown_duration),
+            format!("总盈亏：\t{}", format_number(self.total_net_pnl)),
+            format!("总手续费：\t{}", format_number(self.total_commission)),
+            format!("总滑点：\t{}", format_number(self.total_slippage)),
+            format!("总成交金额：\t{}", format_number(self.total_turnover)),
+            format!("总成交笔数：\t{}", self.total_trade_count),
+            format!("日均盈亏：\t{}", format_number(self.daily_net_pnl)),
+            format!("日均手续费：\t{}", format_number(self.daily_commission)),
+            format!("日均滑点：\t{}", format_number(self.daily_slippage)),
+            format!("日均成交金额：\t{}", format_number(self.daily_turnover)),
+            format!("日均成交笔数：\t{:?}", self.daily_trade_count),
+            format!("日均收益率：\t{}%", format_number(self.daily_return)),
+            format!("收益标准差：\t{}%", format_number(self.return_std)),
+            format!("Sharpe Ratio：\t{}", format_number(self.sharpe_ratio)),
+            format!("EWM Sharpe：\t{}", format_number(self.ewm_sharpe)),
+            format!(
+                "收益回撤比：\t{}",
+                format_number(self.return_drawdown_ratio)
+            ),
+        ];
+        if let (Some(drawdown), Some(ddpercent)) =
+            (self.intraday_max_drawdown, self.intraday_max_ddpercent)
+        {
+            lines.push(format!("日内最大回撤: \t{}", format_number(drawdown)));
+            lines.push(format!("日内百分比最大回撤: {}%", format_number(ddpercent)));
+        }
+        for (year, total_return) in &self.yearly_returns {
+            lines.push(format!(
+                "{}年收益率：\t{}%",
+                year,
+                format_number(*total_return)
+            ));
+        }
+        if let Some(benchmark) = self.benchmark.as_ref() {
+            lines.extend(benchmark.report_lines());
+        }
+        if let Some(confidence) = self.confidence.as_ref() {
+            lines.extend(confidence.report_lines());
+        }
+        lines
+    }
+}
+
+#[pymethods]
+impl BacktestStatistics {
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        for (key, value) in self.items(py)? {
+            dict.set_item(key, value)?;
+        }
+        Ok(dict.unbind())
+    }
+
+    pub fn keys(&self, py: Python<'_>) -> PyResult<Vec<&'static str>> {
+        Ok(self.items(py)?.into_iter().map(|(key, _)| key).collect())
+    }
+
+    pub fn values(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        Ok(self
+            .items(py)?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    #[pyo3(name = "items")]
+    pub fn py_items(&self, py: Python<'_>) -> PyResult<Vec<(&'static str, PyObject)>> {
+        self.items(py)
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    pub fn get(&self, py: Python<'_>, key: &str, default: Option<PyObject>) -> PyResult<PyObject> {
+        Ok(self
+            .items(py)?
+            .into_iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .unwrap_or_else(|| default.unwrap_or_else(|| py.None())))
+    }
+
+    pub fn __getitem__(&self, py: Python<'_>, key: &str) -> PyResult<PyObject> {
+        self.items(py)?
+            .into_iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| PyKeyError::new_err(key.to_string()))
+    }
+
+    pub fn __contains__(&self, py: Python<'_>, key: &str) -> PyResult<bool> {
+        Ok(self.keys(py)?.contains(&key))
+    }
+
+    pub fn __len__(&self, py: Python<'_>) -> PyResult<usize> {
+        Ok(self.items(py)?.len())
+    }
+
+    pub fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.keys(py)?
+            .into_pyobject(py)?
+            .try_iter()?
+            .into_py_any(py)
+    }
+
+    pub fn __repr__(&self) -> String {
+        format!(
+            "BacktestStatistics(start_date={}, end_date={}, total_return={:.2}, sharpe_ratio={:.2}, max_ddpercent={:.2})",
+            self.start_date.map(|d| d.to_string()).unwrap_or_default(),
+            self.end_date.map(|d| d.to_string()).unwrap_or_default(),
+            self.total_return, self.sharpe_ratio, self.max_ddpercent
+        )
+    }
+}
+
+/// Python's "{:,.2f}"
+pub(crate) fn format_number(value: f64) -> String {
+    let text = format!("{:.2}", value.abs());
+    let (integer, fraction) = text.split_once('.').unwrap_or((&text, "00"));
+    let mut grouped = String::new();
+    for (i, c) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    let sign = if value < 0.0 && text != "0.00" {
+        "-"
+    } else {
+        ""
+    };
+    format!("{}{}.{}", sign, grouped, fraction)
+}
+
+/// Infinite and NaN values are reported as 0, like np.nan_to_num in vnpy
+pub(crate) fn finite(value: f64) -> f64 {
+    if value.is_finite() {
+        value
+    } else {
+        0.0
+    }
+}
+
+pub(crate) fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample standard deviation, NaN for less than 2 values
+pub(crate) fn std(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return f64::NAN;
+    }
+    let mean = mean(values);
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    var.sqrt()
+}
+
+/// Last value of pandas' `ewm(halflife=half_life).mean()` and `.std()` (adjust=True, bias=False)
+fn ewm_mean_std(values: &[f64], half_life: f64) -> (f64, f64) {
+    let alpha = 1.0 - (0.5f64.ln() / half_life).exp();
+    let decay = 1.0 - alpha;
+    let mut sum_weight = 0.0;
+    let mut sum_weight2 = 0.0;
+    let mut weighted_sum = 0.0;
+    let mut weight = 1.0;
+    for value in values.iter().rev() {
+        sum_weight += weight;
+        sum_weight2 += weight * weight;
+        weighted_sum += weight * value;
+        weight *= decay;
+    }
+    let mean = weighted_sum / sum_weight;
+
+    let mut weighted_var = 0.0;
+    let mut weight = 1.0;
+    for value in values.iter().rev() {
+        weighted_var += weight * (value - mean).powi(2);
+        weight *= decay;
+    }
+    let denominator = sum_weight * sum_weight - sum_weight2;
+    let std = if denominator > 0.0 {
+        (weighted_var / sum_weight * sum_weight * sum_weight / denominator).sqrt()
+    } else {
+        f64::NAN
+    };
+    (mean, std)
+}
+
+/// Same metrics as vnpy's calculate_statistics, computed from the daily results
+pub fn calculate_statistics<'a>(
+    daily_results: impl IntoIterator<Item = &'a DailyResult>,
+    capital: f64,
+    risk_free: f64,
+    annual_days: i64,
+    half_life: i64,
+) -> (BacktestStatistics, bool) {
+    let daily_results: Vec<&DailyResult> = daily_results.into_iter().collect();
+    let mut statistics = BacktestStatistics {
+        capital,
+        ..Default::default()
+    };
+    if daily_results.is_empty() {
+        return (statistics, true);
+    }
+
+    let mut balance = Vec::with_capacity(daily_results.len());
+    let mut returns = Vec::with_capacity(daily_results.len());
+    let mut drawdown = Vec::with_capacity(daily_results.len());
+    let mut ddpercent = Vec::with_capacity(daily_results.len());
+    let mut pre_balance = capital;
+    let mut highlevel = f64::MIN;
+    for daily_result in daily_results.iter() {
+        let current = pre_balance + daily_result.net_pnl;
+        // When balance falls below 0, daily return is 0
+        let x = current / pre_balance;
+        returns.push(if x > 0.0 { x.ln() } else { 0.0 });
+        highlevel = highlevel.max(current);
+        drawdown.push(current - highlevel);
+        ddpercent.push((current - highlevel) / highlevel * 100.0);
+        balance.push(current);
+        pre_balance = current;
+    }
+    if balance.iter().any(|&b| b <= 0.0) {
+        return (statistics, false);
+    }
+
+    let total_days = daily_results.len() as i64;
+    let days = total_days as f64;
+    statistics.start_date = Some(daily_results[0].date);
+    statistics.end_date = Some(daily_results[daily_results.len() - 1].date);
+    statistics.total_days = total_days;
+    statistics.profit_days = daily_results.iter().filter(|d| d.net_pnl > 0.0).count() as i64;
+    statistics.loss_days = daily_results.iter().filter(|d| d.net_pnl < 0.0).count() as i64;
+
+    statistics.end_balance = balance[balance.len() - 1];
+    // First minimum, and the first maximum of balance before it, like idxmin/idxmax
+    let mut end_index = 0;
+    for (i, value) in drawdown.iter().enumerate() {
+        if *value < drawdown[end_index] {
+            end_index = i;
+        }
+    }
+    let mut start_index = 0;
+    for i in 0..=end_index {
+        if balance[i] > balance[start_index] {
+            start_index = i;
+        }
+    }
+    statistics.max_drawdown = drawdown[end_index];
+    statistics.max_ddpercent = ddpercent.iter().cloned().fold(f64::INFINITY, f64::min);
+    statistics.max_drawdown_duration =
+        (daily_results[end_index].date - daily_results[start_index].date).num_days();
+
+    statistics.total_net_pnl = daily_results.iter().map(|d| d.net_pnl).sum();
+    statistics.daily_net_pnl = statistics.total_net_pnl / days;
+    statistics.total_commission = daily_results.iter().map(|d| d.commission).sum();
+    statistics.daily_commission = statistics.total_commission / days;
+    statistics.total_slippage = daily_results.iter().map(|d| d.slippage).sum();
+    statistics.daily_slippage = statistics.total_slippage / days;
+    statistics.total_turnover = daily_results.iter().map(|d| d.turnover).sum();
+    statistics.daily_turnover = statistics.total_turnover / days;
+    statistics.total_trade_count = daily_results.iter().map(|d| d.trade_count).sum();
+    statistics.daily_trade_count = statistics.total_trade_count as f64 / days;
+
+    statistics.total_return = (statistics.end_balance / capital - 1.0) * 100.0;
+    statistics.annual_return = statistics.total_return / days * annual_days as f64;
+    statistics.daily_return = mean(&returns) * 100.0;
+    statistics.return_std = finite(std(&returns) * 100.0);
+
+    if statistics.return_std != 0.0 {
+        let daily_risk_free = risk_free / (annual_days as f64).sqrt();
+        statistics.sharpe_ratio = (statistics.daily_return - daily_risk_free)
+            / statistics.return_std
+            * (annual_days as f64).sqrt();
+
+        let (ewm_mean, ewm_std) = ewm_mean_std(&returns, half_life as f64);
+        statistics.ewm_sharpe = finite(
+            (ewm_mean * 100.0 - daily_risk_free) / (ewm_std * 100.0) * (annual_days as f64).sqrt(),
+        );
+    }
+
+    if statistics.max_ddpercent != 0.0 {
+        statistics.return_drawdown_ratio = -statistics.total_return / statistics.max_ddpercent;
+    }
+
+    statistics.annual_return = finite(statistics.annual_return);
+    statistics.sharpe_ratio = finite(statistics.sharpe_ratio);
+    statistics.return_drawdown_ratio = finite(statistics.return_drawdown_ratio);
+
+    let balance = daily_balance(daily_results.iter().copied(), capital);
+    statistics.yearly_returns = yearly_statistics(&balance, capital, risk_free, annual_days)
+        .into_iter()
+        .map(|year| (year.year, year.total_return))
+        .collect();
+    (statistics, true)
+}
@@ -0,0 +1,440 @@
+/*! Quality checks on history data before running a backtest. */
+
+use std::collections::{HashSet, LinkedList};
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, Weekday};
+use chrono_tz::Tz;
+use pyo3::prelude::*;
+use strum::Display;
+
+use crate::trader::{
+    constant::Interval,
+    object::{BarData, MixData},
+};
+
+#[pyclass(eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum DataIssueKind {
+    #[strum(serialize = "重复时间")]
+    DUPLICATE,
+    #[strum(serialize = "时间倒序")]
+    REVERSED,
+    #[strum(serialize = "价格为0")]
+    ZEROPRICE,
+    #[strum(serialize = "OHLC错误")]
+    OHLC,
+    #[strum(serialize = "收益率异常")]
+    OUTLIER,
+    #[strum(serialize = "成交量异常")]
+    VOLUME,
+    #[strum(serialize = "数据缺失")]
+    GAP,
+}
+
+#[pymethods]
+impl DataIssueKind {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[pyclass(get_all)]
+#[derive(Debug, Clone)]
+pub struct DataIssue {
+    pub kind: DataIssueKind,
+    /// Position of the item in the loaded history data
+    pub index: usize,
+    pub datetime: DateTime<Tz>,
+    pub message: String,
+}
+
+#[pymethods]
+impl DataIssue {
+    fn __repr__(&self) -> String {
+        format!(
+            "DataIssue({}, {}, {}, {})",
+            self.kind, self.index, self.datetime, self.message
+        )
+    }
+}
+
+#[pyclass(get_all)]
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub total: usize,
+    pub duplicates: usize,
+    pub reversed: usize,
+    pub zero_prices: usize,
+    pub ohlc_errors: usize,
+    pub outlier_returns: usize,
+    pub volume_anomalies: usize,
+    pub gaps: usize,
+    pub missing_bars: usize,
+    pub issues: Vec<DataIssue>,
+
+    pub deduped: usize,
+    pub dropped: usize,
+    pub filled: usize,
+}
+
+#[pymethods]
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ValidationReport(total={}, duplicates={}, reversed={}, zero_prices={}, ohlc_errors={}, outlier_returns={}, volume_anomalies={}, gaps={}, missing_bars={})",
+            self.total,
+            self.duplicates,
+            self.reversed,
+            self.zero_prices,
+            self.ohlc_errors,
+            self.outlier_returns,
+            self.volume_anomalies,
+            self.gaps,
+            self.missing_bars
+        )
+    }
+}
+
+impl ValidationReport {
+    fn add(&mut self, kind: DataIssueKind, index: usize, datetime: DateTime<Tz>, message: String) {
+        match kind {
+            DataIssueKind::DUPLICATE => self.duplicates += 1,
+            DataIssueKind::REVERSED => self.reversed += 1,
+            DataIssueKind::ZEROPRICE => self.zero_prices += 1,
+            DataIssueKind::OHLC => self.ohlc_errors += 1,
+            DataIssueKind::OUTLIER => self.outlier_returns += 1,
+            DataIssueKind::VOLUME => self.volume_anomalies += 1,
+            DataIssueKind::GAP => self.gaps += 1,
+        }
+        self.issues.push(DataIssue {
+            kind,
+            index,
+            datetime,
+            message,
+        });
+    }
+}
+
+pub struct ValidationSetting {
+    /// Trading sessions as (start, end) of bar time, end is exclusive.
+    /// A session with start > end crosses midnight, one with start == end lasts the whole day.
+    pub sessions: Vec<(NaiveTime, NaiveTime)>,
+    /// Days of the week on which the sessions start
+    pub weekdays: Vec<Weekday>,
+    /// Dates on which no session starts, such as exchange holidays
+    pub holidays: HashSet<NaiveDate>,
+    /// Returns further than this many standard deviations from the mean are outliers
+    pub return_sigma: f64,
+    /// Bar volumes further than this many standard deviations above the mean are anomalies
+    pub volume_sigma: f64,
+}
+
+fn item_datetime(item: &MixData) -> DateTime<Tz> {
+    match item {
+        MixData::BarData(bar) => bar.datetime,
+        MixData::TickData(tick) => tick.datetime,
+    }
+}
+
+fn item_price(item: &MixData) -> f64 {
+    match item {
+        MixData::BarData(bar) => bar.close_price,
+        MixData::TickData(tick) => tick.last_price,
+    }
+}
+
+/// Check prices of a single bar/tick, return the issue found if any.
+fn check_prices(item: &MixData) -> Option<(DataIssueKind, String)> {
+    match item {
+        MixData::BarData(bar) => {
+            let prices = [
+                bar.open_price,
+                bar.high_price,
+                bar.low_price,
+                bar.close_price,
+            ];
+            if prices
+                .iter()
+                .any(|price| !price.is_finite() || *price <= 0.0)
+            {
+                return Some((
+                    DataIssueKind::ZEROPRICE,
+                    format!(
+                        "开{} 高{} 低{} 收{}",
+                        bar.open_price, bar.high_price, bar.low_price, bar.close_price
+                    ),
+                ));
+            }
+            if bar.high_price < bar.low_price
+                || bar.high_price < bar.open_price.max(bar.close_price)
+                || bar.low_price > bar.open_price.min(bar.close_price)
+            {
+                return Some((
+                    DataIssueKind::OHLC,
+                    format!(
+                        "开{} 高{} 低{} 收{}",
+                        bar.open_price, bar.high_price, bar.low_price, bar.close_price
+                    ),
+                ));
+            }
+            None
+        }
+        MixData::TickData(tick) => {
+            if !tick.last_price.is_finite() || tick.last_price <= 0.0 {
+                return Some((
+                    DataIssueKind::ZEROPRICE,
+                    format!("最新价{}", tick.last_price),
+                ));
+            }
+            if tick.bid_price_1 > 0.0
+                && tick.ask_price_1 > 0.0
+                && tick.bid_price_1 > tick.ask_price_1
+            {
+                return Some((
+                    DataIssueKind::OHLC,
+                    format!("买一价{}高于卖一价{}", tick.bid_price_1, tick.ask_price_1),
+                ));
+            }
+            None
+        }
+    }
+}
+
+fn mean_std(values: &[f64]) -> (f64, f64) {
+    if values.len() < 2 {
+        return (0.0, 0.0);
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    (mean, var.sqrt())
+}
+
+/// The part of a session after midnight belongs to the day the session started on,
+/// so a night session opened on Friday runs into Saturday but none opens on Saturday.
+fn in_session(dt: &DateTime<Tz>, setting: &ValidationSetting) -> bool {
+    let (date, time) = (dt.date_naive(), dt.time());
+    let open = |date: NaiveDate| {
+        setting.weekdays.contains(&date.weekday()) && !setting.holidays.contains(&date)
+    };
+    setting.sessions.iter().any(|(start, end)| {
+        if start < end {
+            time >= *start && time < *end && open(date)
+        } else if time >= *start {
+            open(date)
+        } else {
+            time < *end && open(date - TimeDelta::days(1))
+        }
+    })
+}
+
+/// Minutes inside trading sessions strictly between two bars
+fn missing_minutes(
+    prev: &DateTime<Tz>,
+    cur: &DateTime<Tz>,
+    setting: &ValidationSetting,
+) -> Vec<DateTime<Tz>> {
+    let mut missing = Vec::new();
+    let mut dt = *prev + TimeDelta::minutes(1);
+    while dt < *cur {
+        if in_session(&dt, setting) {
+            missing.push(dt);
+        }
+        dt += TimeDelta::minutes(1);
+    }
+    missing
+}
+
+pub fn validate(
+    data: &LinkedList<MixData>,
+    interval: Option<Interval>,
+    setting: &ValidationSetting,
+) -> ValidationReport {
+    let mut report = ValidationReport {
+        total: data.len(),
+        ..Default::default()
+    };
+
+    let datetimes: Vec<DateTime<Tz>> = data.iter().map(item_datetime).collect();
+    let mut returns: Vec<(usize, f64)> = Vec::new();
+    let mut volumes: Vec<(usize, f64)> = Vec::new();
+    let mut prev: Option<&MixData> = None;
+    let mut prev_price: Option<f64> = None;
+
+    for (i, item) in data.iter().enumerate() {
+        let dt = datetimes[i];
+
+        if let Some(prev) = prev {
+            let prev_dt = item_datetime(prev);
+            if dt == prev_dt {
+                report.add(
+                    DataIssueKind::DUPLICATE,
+                    i,
+                    dt,
+                    "与前一条数据时间相同".to_string(),
+                );
+            } else if dt < prev_dt {
+                report.add(
+                    DataIssueKind::REVERSED,
+                    i,
+                    dt,
+                    format!("早于前一条数据的时间{}", prev_dt),
+                );
+            } else if interval == Some(Interval::MINUTE) && !setting.sessions.is_empty() {
+                let missing = missing_minutes(&prev_dt, &dt, setting);
+                if !missing.is_empty() {
+                    report.missing_bars += missing.len();
+                    report.add(
+                        DataIssueKind::GAP,
+                        i,
+                        missing[0],
+                        format!("{}之后缺失{}根K线", prev_dt, missing.len()),
+                    );
+                }
+            }
+        }
+
+        match check_prices(item) {
+            Some((kind, message)) => report.add(kind, i, dt, message),
+            None => {
+                let price = item_price(item);
+                if let Some(prev_price) = prev_price {
+                    returns.push((i, (price / prev_price).ln()));
+                }
+                prev_price = Some(price);
+            }
+        }
+
+        match item {
+            MixData::BarData(bar) => {
+                if bar.volume < 0.0 {
+                    report.add(
+                        DataIssueKind::VOLUME,
+                        i,
+                        dt,
+                        format!("成交量{}小于0", bar.volume),
+                    );
+                } else {
+                    volumes.push((i, bar.volume));
+                }
+            }
+            MixData::TickData(tick) => {
+                if tick.volume < 0.0 || tick.last_volume < 0.0 {
+                    report.add(
+                        DataIssueKind::VOLUME,
+                        i,
+                        dt,
+                        format!("成交量{}，最新成交量{}", tick.volume, tick.last_volume),
+                    );
+                }
+            }
+        }
+
+        prev = Some(item);
+    }
+
+    // Outliers are judged against the whole series
+    let values: Vec<f64> = returns.iter().map(|(_, r)| *r).collect();
+    let (mean, std) = mean_std(&values);
+    if std > 0.0 {
+        for (i, r) in returns {
+            if (r - mean).abs() > setting.return_sigma * std {
+                report.add(
+                    DataIssueKind::OUTLIER,
+                    i,
+                    datetimes[i],
+                    format!(
+                        "收益率{:.4}%，超过{}倍标准差",
+                        r * 100.0,
+                        setting.return_sigma
+                    ),
+                );
+            }
+        }
+    }
+
+    let values: Vec<f64> = volumes.iter().map(|(_, v)| *v).collect();
+    let (mean, std) = mean_std(&values);
+    if std > 0.0 {
+        for (i, v) in volumes {
+            if v - mean > setting.volume_sigma * std {
+                report.add(
+                    DataIssueKind::VOLUME,
+                    i,
+                    datetimes[i],
+                    format!("成交量{}，超过{}倍标准差", v, setting.volume_sigma),
+                );
+            }
+        }
+    }
+
+    report.issues.sort_by_key(|issue| issue.index);
+    report
+}
+
+/// Apply the selected fixes and record what was changed into the report.
+pub fn fix(
+    data: LinkedList<MixData>,
+    interval: Option<Interval>,
+    setting: &ValidationSetting,
+    report: &mut ValidationReport,
+    dedupe: bool,
+    drop_invalid: bool,
+    forward_fill: bool,
+) -> LinkedList<MixData> {
+    let mut items: Vec<MixData> = data.into_iter().collect();
+
+    // Sort by time and keep the last one of each timestamp
+    if dedupe {
+        let len = items.len();
+        items.sort_by_key(item_datetime);
+        let mut deduped: Vec<MixData> = Vec::with_capacity(len);
+        for item in items {
+            match deduped.last_mut() {
+                Some(last) if item_datetime(last) == item_datetime(&item) => *last = item,
+                _ => deduped.push(item),
+            }
+        }
+        report.deduped = len - deduped.len();
+        items = deduped;
+    }
+
+    if drop_invalid {
+        let len = items.len();
+        items.retain(|item| check_prices(item).is_none());
+        report.dropped = len - items.len();
+    }
+
+    // Fill missing session minutes with flat bars at the previous close,
+    // gaps longer than a day (e.g. holidays) are left as they are
+    if forward_fill && interval == Some(Interval::MINUTE) && !setting.sessions.is_empty() {
+        let mut filled: Vec<MixData> = Vec::with_capacity(items.len());
+        for item in items {
+            if let (Some(MixData::BarData(prev)), MixData::BarData(cur)) = (filled.last(), &item) {
+                if cur.datetime > prev.datetime && cur.datetime - prev.datetime < TimeDelta::days(1)
+                {
+                    let prev = prev.clone();
+                    for dt in missing_minutes(&prev.datetime, &cur.datetime, setting) {
+                        filled.push(MixData::BarData(BarData {
+                            datetime: dt,
+                            volume: 0.0,
+                            turnover: 0.0,
+                            open_price: prev.close_price,
+                            high_price: prev.close_price,
+                            low_price: prev.close_price,
+                            ..prev.clone()
+                        }));
+                        report.filled += 1;
+                    }
+                }
+            }
+            filled.push(item);
+        }
+        items = filled;
+    }
+
+    items.into_iter().collect()
+}
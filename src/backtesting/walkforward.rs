@@ -0,0 +1,495 @@
+/*! Walk-forward analysis: the strategy is optimized on every in-sample window and traded with the best setting on the window that follows. */
+
+use std::{collections::LinkedList, ops::Range, str::FromStr};
+
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+use pyo3::{
+    prelude::*,
+    types::{PyCFunction, PyDict, PyTuple},
+};
+use strum::{Display, EnumString};
+
+use super::{
+    daily_dataframe,
+    optimization::{engine_parameters, target_value, Progress, WorkerPool},
+    statistics::{calculate_statistics, BacktestStatistics},
+    to_dataframe, BacktestingEngine, DailyResult, GLOBAL_HISTORY_DATA,
+};
+use crate::trader::object::MixData;
+
+/// What the in-sample and out-of-sample lengths are counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+pub enum WindowUnit {
+    /// Calendar days, windows start and end at a date boundary
+    DAYS,
+    /// Bars or ticks of the history data
+    BARS,
+}
+
+fn datetime(item: &MixData) -> DateTime<Tz> {
+    match item {
+        MixData::BarData(bar) => bar.datetime,
+        MixData::TickData(tick) => tick.datetime,
+    }
+}
+
+/// Index ranges of the history data making up every window. Anchored windows
+/// all start at the beginning, rolling ones keep the in-sample length.
+pub fn split_windows(
+    datetimes: &[DateTime<Tz>],
+    in_sample: usize,
+    out_sample: usize,
+    unit: WindowUnit,
+    anchored: bool,
+) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut windows = Vec::new();
+    let Some(first) = datetimes.first().map(|dt| dt.date_naive()) else {
+        return windows;
+    };
+    // Index of the first item at or after the given offset from the beginning
+    let boundary = |offset: usize| match unit {
+        WindowUnit::BARS => offset.min(datetimes.len()),
+        WindowUnit::DAYS => {
+            let date = first + TimeDelta::days(offset as i64);
+            datetimes.partition_point(|dt| dt.date_naive() < date)
+        }
+    };
+    for k in 0.. {
+        let out_start = in_sample + k * out_sample;
+        let out_range = boundary(out_start)..boundary(out_start + out_sample);
+        if out_range.start >= datetimes.len() {
+            break;
+        }
+        let in_start = if anchored { 0 } else { out_start - in_sample };
+        let in_range = boundary(in_start)..out_range.start;
+        // A gap in the data may leave a window without any item
+        if !in_range.is_empty() && !out_range.is_empty() {
+            windows.push((in_range, out_range));
+        }
+    }
+    windows
+}
+
+/// Backtest of one setting in this process, on the given history data only
+fn run_window(
+    py: Python<'_>,
+    engine: &BacktestingEngine,
+    parameters: &Bound<'_, PyDict>,
+    history_data: LinkedList<MixData>,
+    setting: &Bound<'_, PyAny>,
+) -> PyResult<(BacktestStatistics, Vec<DailyResult>)> {
+    let window = Bound::new(py, BacktestingEngine::__new__(None)?)?;
+    {
+        let mut window = window.borrow_mut();
+        window.database = engine.database.clone();
+        window.database_config = engine.database_config.clone();
+        window.rs_pyfunc_output = Some(
+            PyCFunction::new_closure(py, None, None, |_: &Bound<'_, PyTuple>, _| {})?
+                .into_any()
+                .unbind(),
+        );
+    }
+    window.call_method("set_parameters", (), Some(parameters))?;
+    window.call_method1(
+        "add_strategy",
+        (engine.strategy_class.as_ref().unwrap().bind(py), setting),
+    )?;
+
+    // The window replaces the loaded history data while it runs
+    let history_data = std::mem::replace(&mut *GLOBAL_HISTORY_DATA.lock().unwrap(), history_data);
+    let result = window.call_method0("run_backtesting");
+    *GLOBAL_HISTORY_DATA.lock().unwrap() = history_data;
+    result?;
+
+    let mut window = window.borrow_mut();
+    let statistics = window.rs_calculate_statistics(py, false);
+    let daily_results = window
+        .daily_results
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    Ok((statistics, daily_results))
+}
+
+/// Value of the named statistic, NaN when it isn't a number
+fn statistic(py: Python<'_>, statistics: &BacktestStatistics, name: &str) -> PyResult<f64> {
+    let value = statistics.to_dict(py)?.bind(py).get_item(name)?;
+    Ok(value.and_then(|v| v.extract().ok()).unwrap_or(f64::NAN))
+}
+
+/// Out-of-sample over in-sample annual return, NaN unless the in-sample one is positive
+fn efficiency(in_sample: f64, out_sample: f64) -> f64 {
+    if in_sample > 0.0 {
+        out_sample / in_sample
+    } else {
+        f64::NAN
+    }
+}
+
+/// Daily results of the out-of-sample windows one after another. A day split
+/// between two windows is merged, every window starts without a position.
+fn stitch(windows: Vec<Vec<DailyResult>>) -> Vec<DailyResult> {
+    let mut stitched: Vec<DailyResult> = Vec::new();
+    for daily_result in windows.into_iter().flatten() {
+        match stitched.last_mut() {
+            Some(last) if last.date == daily_result.date => {
+                last.close_price = daily_result.close_price;
+                last.trades.extend(daily_result.trades);
+                last.trade_count += daily_result.trade_count;
+                last.end_pos = daily_result.end_pos;
+                last.turnover += daily_result.turnover;
+                last.commission += daily_result.commission;
+                last.slippage += daily_result.slippage;
+                last.trading_pnl += daily_result.trading_pnl;
+                last.holding_pnl += daily_result.holding_pnl;
+                last.total_pnl += daily_result.total_pnl;
+                last.net_pnl += daily_result.net_pnl;
+            }
+            _ => stitched.push(daily_result),
+        }
+    }
+    stitched
+}
+
+/// One in-sample optimization and the out-of-sample run of its best setting
+#[pyclass(get_all)]
+pub struct WalkForwardWindow {
+    pub index: usize,
+    pub in_sample_start: DateTime<Tz>,
+    pub in_sample_end: DateTime<Tz>,
+    pub out_sample_start: DateTime<Tz>,
+    pub out_sample_end: DateTime<Tz>,
+    /// Best setting of the in-sample optimization
+    pub setting: Py<PyDict>,
+    pub in_sample_target: f64,
+    pub out_sample_target: f64,
+    pub in_sample_statistics: BacktestStatistics,
+    pub out_sample_statistics: BacktestStatistics,
+    /// Out-of-sample over in-sample annual return
+    pub efficiency: f64,
+}
+
+#[pymethods]
+impl WalkForwardWindow {
+    fn __repr__(&self) -> String {
+        format!(
+            "WalkForwardWindow(index={}, in_sample={}~{}, out_sample={}~{}, setting={}, efficiency={})",
+            self.index,
+            self.in_sample_start,
+            self.in_sample_end,
+            self.out_sample_start,
+            self.out_sample_end,
+            self.setting,
+            self.efficiency
+        )
+    }
+}
+
+/// Windows of a walk-forward analysis and their out-of-sample runs joined into one
+#[pyclass]
+pub struct WalkForwardResult {
+    windows: Vec<Py<WalkForwardWindow>>,
+    /// Names of the optimized parameters
+    parameters: Vec<String>,
+    daily_results: Vec<DailyResult>,
+    capital: f64,
+    risk_free: f64,
+    annual_days: i64,
+    half_life: i64,
+    /// Annual return of the joined out-of-sample runs over the mean in-sample annual return
+    #[pyo3(get)]
+    efficiency: f64,
+}
+
+/// Mean, sample standard deviation, coefficient of variation, number of changes from
+/// one window to the next, most common value and its share of the windows
+type Stability = (f64, f64, f64, usize, PyObject, f64);
+
+fn stability(py: Python<'_>, values: &[Bound<'_, PyAny>]) -> PyResult<Stability> {
+    let numbers: Option<Vec<f64>> = values.iter().map(|v| v.extract().ok()).collect();
+    let (mean, std) = match numbers {
+        Some(numbers) if !numbers.is_empty() => {
+            let n = numbers.len() as f64;
+            let mean = numbers.iter().sum::<f64>() / n;
+            let std = if numbers.len() > 1 {
+                (numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+            } else {
+                f64::NAN
+            };
+            (mean, std)
+        }
+        _ => (f64::NAN, f64::NAN),
+    };
+
+    let mut changes = 0;
+    for pair in values.windows(2) {
+        if !pair[0].eq(&pair[1])? {
+            changes += 1;
+        }
+    }
+    let mut mode = (py.None(), 0);
+    for value in values {
+        let mut count = 0;
+        for other in values {
+            if value.eq(other)? {
+                count += 1;
+            }
+        }
+        if count > mode.1 {
+            mode = (value.clone().unbind(), count);
+        }
+    }
+    Ok((
+        mean,
+        std,
+        std / mean.abs(),
+        changes,
+        mode.0,
+        mode.1 as f64 / values.len() as f64,
+    ))
+}
+
+#[pymethods]
+impl WalkForwardResult {
+    #[getter]
+    pub fn windows(&self, py: Python<'_>) -> Vec<Py<WalkForwardWindow>> {
+        self.windows.iter().map(|w| w.clone_ref(py)).collect()
+    }
+
+    /// Daily results of the out-of-sample runs, one after another
+    pub fn get_all_daily_results(&self) -> Vec<DailyResult> {
+        self.daily_results.clone()
+    }
+
+    pub fn calculate_result(&self, py: Python<'_>) -> PyResult<PyObject> {
+        daily_dataframe(py, &self.daily_results)
+    }
+
+    /// Statistics of the out-of-sample equity curve
+    #[pyo3(signature = (output=true))]
+    pub fn calculate_statistics(&self, output: bool) -> BacktestStatistics {
+        let (statistics, _) = calculate_statistics(
+            &self.daily_results,
+            self.capital,
+            self.risk_free,
+            self.annual_days,
+            self.half_life,
+        );
+        if output {
+            for line in statistics.report_lines() {
+                println!("{}", line);
+            }
+        }
+        statistics
+    }
+
+    /// How much every optimized parameter moves across the windows, indexed by parameter
+    pub fn get_parameter_stability(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let mut rows = Vec::new();
+        for name in self.parameters.iter() {
+            let values = self
+                .windows
+                .iter()
+                .map(|w| w.borrow(py).setting.bind(py).as_any().get_item(name))
+                .collect::<PyResult<Vec<_>>>()?;
+            rows.push(stability(py, &values)?);
+        }
+        let results = PyDict::new(py);
+        results.set_item("parameter", self.parameters.clone())?;
+        results.set_item("mean", rows.iter().map(|r| r.0).collect::<Vec<_>>())?;
+        results.set_item("std", rows.iter().map(|r| r.1).collect::<Vec<_>>())?;
+        results.set_item("cv", rows.iter().map(|r| r.2).collect::<Vec<_>>())?;
+        results.set_item("changes", rows.iter().map(|r| r.3).collect::<Vec<_>>())?;
+        results.set_item(
+            "mode",
+            rows.iter().map(|r| r.4.clone_ref(py)).collect::<Vec<_>>(),
+        )?;
+        results.set_item("mode_ratio", rows.iter().map(|r| r.5).collect::<Vec<_>>())?;
+        to_dataframe(py, results, "parameter")
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "WalkForwardResult(windows={}, days={}, efficiency={})",
+            self.windows.len(),
+            self.daily_results.len(),
+            self.efficiency
+        )
+    }
+}
+
+/// Optimize every in-sample window on the worker processes and run its best
+/// setting on the out-of-sample window. The history data of the whole range
+/// must have been loaded.
+#[allow(clippy::too_many_arguments)]
+pub fn run_walk_forward(
+    py: Python<'_>,
+    engine: &BacktestingEngine,
+    settings: Vec<Bound<'_, PyDict>>,
+    target_name: &str,
+    in_sample: usize,
+    out_sample: usize,
+    unit: &str,
+    anchored: bool,
+    max_workers: Option<usize>,
+    callback: Option<PyObject>,
+) -> PyResult<WalkForwardResult> {
+    let unit = WindowUnit::from_str(unit)
+        .map_err(|_| pyo3::exceptions::PyValueError::new_err("unit字段只能是DAYS,BARS中的一个"))?;
+    if settings.is_empty() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "优化参数组合为空，请检查",
+        ));
+    }
+    if in_sample == 0 || out_sample == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "样本内和样本外长度必须大于0",
+        ));
+    }
+    let history_data: Vec<MixData> = GLOBAL_HISTORY_DATA
+        .lock()
+        .unwrap()
+        .iter()
+        .cloned()
+        .collect();
+    let datetimes: Vec<DateTime<Tz>> = history_data.iter().map(datetime).collect();
+    let ranges = split_windows(&datetimes, in_sample, out_sample, unit, anchored);
+    if ranges.is_empty() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "历史数据不足以划分出一个样本内和样本外窗口",
+        ));
+    }
+    engine.output(
+        py,
+        &format!(
+            "开始执行滚动优化，窗口数量：{}，参数优化空间：{}",
+            ranges.len(),
+            settings.len()
+        ),
+    );
+
+    let parameters = engine_parameters(py, engine)?;
+    let window_parameters = |range: &Range<usize>| -> PyResult<Bound<'_, PyDict>> {
+        let window = parameters.copy()?;
+        window.set_item("start", datetimes[range.start].naive_local())?;
+        window.set_item("end", datetimes[range.end - 1].naive_local())?;
+        Ok(window)
+    };
+    let slice = |range: &Range<usize>| -> LinkedList<MixData> {
+        history_data[range.clone()].iter().cloned().collect()
+    };
+
+    let mut progress = Progress::new(ranges.len());
+    let mut windows = Vec::new();
+    let mut out_sample_daily = Vec::new();
+    for (index, (in_range, out_range)) in ranges.iter().enumerate() {
+        let in_parameters = window_parameters(in_range)?;
+        let in_history = slice(in_range);
+        let mut best: Option<(f64, Bound<'_, PyAny>)> = None;
+        {
+            let pool = WorkerPool::start(
+                py,
+                engine,
+                &in_parameters,
+                &in_history,
+                target_name,
+                max_workers,
+            )?;
+            pool.evaluate(settings.clone(), |result| {
+                let target = target_value(&result);
+                if best.as_ref().is_none_or(|(value, _)| target > *value) {
+                    best = Some((target, result));
+                }
+                Ok(())
+            })?;
+        }
+        let setting = best.unwrap().1.get_item(0)?;
+
+        let (in_statistics, _) = run_window(py, engine, &in_parameters, in_history, &setting)?;
+        let (out_statistics, daily_results) = run_window(
+            py,
+            engine,
+            &window_parameters(out_range)?,
+            slice(out_range),
+            &setting,
+        )?;
+        out_sample_daily.push(daily_results);
+
+        let in_sample_target = statistic(py, &in_statistics, target_name)?;
+        let out_sample_target = statistic(py, &out_statistics, target_name)?;
+        let window = Py::new(
+            py,
+            WalkForwardWindow {
+                index,
+                in_sample_start: datetimes[in_range.start],
+                in_sample_end: datetimes[in_range.end - 1],
+                out_sample_start: datetimes[out_range.start],
+                out_sample_end: datetimes[out_range.end - 1],
+                setting: setting.downcast_into::<PyDict>()?.unbind(),
+                in_sample_target,
+                out_sample_target,
+                efficiency: efficiency(in_statistics.annual_return, out_statistics.annual_return),
+                in_sample_statistics: in_statistics,
+                out_sample_statistics: out_statistics,
+            },
+        )?;
+        progress.advance();
+        engine.output(
+            py,
+            &format!(
+                "第{}个窗口：样本内{}至{}，样本外{}至{}，最优参数：{}，样本内目标：{}，样本外目标：{}",
+                index + 1,
+                datetimes[in_range.start].date_naive(),
+                datetimes[in_range.end - 1].date_naive(),
+                datetimes[out_range.start].date_naive(),
+                datetimes[out_range.end - 1].date_naive(),
+                window.borrow(py).setting,
+                in_sample_target,
+                out_sample_target
+            ),
+        );
+        if let Some(callback) = callback.as_ref() {
+            callback.call1(
+                py,
+                (&window, progress.finished, progress.total, progress.eta()),
+            )?;
+        }
+        windows.push(window);
+    }
+
+    let daily_results = stitch(out_sample_daily);
+    let (statistics, _) = calculate_statistics(
+        &daily_results,
+        engine.capital,
+        engine.risk_free,
+        engine.annual_days,
+        engine.half_life,
+    );
+    let in_sample_return = windows
+        .iter()
+        .map(|w| w.borrow(py).in_sample_statistics.annual_return)
+        .sum::<f64>()
+        / windows.len() as f64;
+    let parameters = settings
+        .first()
+        .map(|setting| setting.keys().extract())
+        .transpose()?
+        .unwrap_or_default();
+    engine.output(
+        py,
+        &format!("滚动优化完成，耗时{:.0}秒", progress.elapsed()),
+    );
+    Ok(WalkForwardResult {
+        windows,
+        parameters,
+        daily_results,
+        capital: engine.capital,
+        risk_free: engine.risk_free,
+        annual_days: engine.annual_days,
+        half_life: engine.half_life,
+        efficiency: efficiency(in_sample_return, statistics.annual_return),
+    })
+}
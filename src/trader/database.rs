@@ -1,90 +1,362 @@
-use std::sync::{Arc, LazyLock};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
 
 use chrono_tz::Tz;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use pyo3::{
+    create_exception,
+    exceptions::{PyConnectionError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::{PyDict, PyTuple},
+};
 
 use super::{
     database_impl::{
-        BaseDatabase, MongodbDatabase, MysqlDatabase, PostgreSQLDatabase, SqliteDatabase, DBMAP,
+        with_retry, BaseDatabase, DatabaseError, MemoryDatabase, MongodbDatabase, MysqlDatabase,
+        ParquetDatabase, PostgreSQLDatabase, SqliteDatabase, DBMAP,
     },
-    setting::SETTINGS,
+    object::{BarData, TickData},
+    schema::{DatetimeFormat, SchemaMapping},
+    setting::{try_settings, SettingError},
     utility::get_file_path,
 };
 
-pub static DB_TZ: LazyLock<Tz> = LazyLock::new(|| {
-    SETTINGS
+create_exception!(
+    vnpyrs.trader.database,
+    DatabaseConnectionError,
+    PyConnectionError,
+    "数据库连接失败"
+);
+create_exception!(
+    vnpyrs.trader.database,
+    ConfigurationError,
+    PyValueError,
+    "配置错误"
+);
+create_exception!(
+    vnpyrs.trader.database,
+    DataError,
+    PyRuntimeError,
+    "数据读取失败"
+);
+
+impl From<SettingError> for PyErr {
+    fn from(e: SettingError) -> Self {
+        ConfigurationError::new_err(e.to_string())
+    }
+}
+
+impl From<DatabaseError> for PyErr {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::Connection { .. } => DatabaseConnectionError::new_err(e.to_string()),
+            DatabaseError::Config(_) => ConfigurationError::new_err(e.to_string()),
+            DatabaseError::Data { .. } => DataError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// Timezone of datetimes in the database, from the current settings
+pub fn db_tz() -> Result<Tz, SettingError> {
+    try_settings()?
         .database_timezone
         .parse()
-        .expect("配置文件中database.timezone错误")
-});
+        .map_err(|e: chrono_tz::ParseError| SettingError::invalid("database.timezone", e))
+}
 
-pub fn get_database() -> Arc<dyn BaseDatabase> {
-    // Read database related global setting
-    match SETTINGS.database_name.as_str() {
-        "sqlite" => {
-            if DBMAP.lock().unwrap().sqlite.is_none() {
-                DBMAP.lock().unwrap().sqlite = Some(Arc::new(
-                    SqliteDatabase::connect(
-                        &get_file_path("database.db")
-                            .into_os_string()
-                            .into_string()
-                            .unwrap(),
-                    )
-                    .expect("Sqlite数据库打开失败"),
-                ));
-            }
-            return DBMAP.lock().unwrap().sqlite.as_ref().unwrap().clone();
+const DATABASE_NAMES: [&str; 5] = ["sqlite", "mysql", "postgresql", "mongodb", "parquet"];
+
+/// Where and how to read history data. Engines with the same config share one connection.
+#[pyclass(module = "vnpyrs.trader.database", get_all, eq, hash, frozen)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DatabaseConfig {
+    pub name: String,
+    /// File path for sqlite and parquet (relative to .vntrader), database name for others
+    pub database: String,
+    /// Overrides host, port, user and password for mysql, postgresql and mongodb when not empty
+    pub url: String,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub timezone: String,
+    /// Table or collection names, empty for the vnpy names
+    pub bar_table: String,
+    pub tick_table: String,
+    /// Field name to column name, "" for fields the table doesn't have
+    pub columns: BTreeMap<String, String>,
+    /// naive, utc or epoch_ms, empty for the vnpy format of the backend
+    pub datetime_format: String,
+    /// Timezones of naive datetimes by exchange and by vt_symbol, Asia/Shanghai if not set.
+    /// Loaded data is converted into `timezone`.
+    pub exchange_timezones: BTreeMap<String, String>,
+    pub symbol_timezones: BTreeMap<String, String>,
+}
+
+impl DatabaseConfig {
+    /// Config read from vt_setting.json
+    pub fn from_settings() -> Result<DatabaseConfig, SettingError> {
+        let setting = try_settings()?;
+        let database = if setting.database_name == "sqlite" {
+            // Same as vnpy_sqlite, the file name is fixed
+            "database.db".to_string()
+        } else {
+            setting.database_database.clone()
+        };
+        Ok(DatabaseConfig {
+            name: setting.database_name.clone(),
+            database,
+            url: "".to_string(),
+            host: setting.database_host.clone(),
+            port: setting.database_port,
+            user: setting.database_user.clone(),
+            password: setting.database_password.clone(),
+            timezone: setting.database_timezone.clone(),
+            bar_table: setting.database_bar_table.clone(),
+            tick_table: setting.database_tick_table.clone(),
+            columns: setting.database_columns.clone(),
+            datetime_format: setting.database_datetime_format.clone(),
+            exchange_timezones: setting.database_exchange_timezones.clone(),
+            symbol_timezones: setting.database_symbol_timezones.clone(),
+        })
+    }
+
+    pub fn tz(&self) -> Result<Tz, SettingError> {
+        self.timezone
+            .parse()
+            .map_err(|e: chrono_tz::ParseError| SettingError::invalid("database.timezone", e))
+    }
+
+    /// Schema of the database, with the defaults of the backend for what isn't set
+    pub fn schema(
+        &self,
+        default_tables: (&str, &str),
+        default_format: DatetimeFormat,
+    ) -> Result<SchemaMapping, SettingError> {
+        SchemaMapping::new(self, default_tables, default_format)
+    }
+
+    fn path(&self) -> String {
+        // Relative paths are resolved against the .vntrader folder
+        let mut path = PathBuf::from(&self.database);
+        if path.is_relative() {
+            path = get_file_path(&self.database);
         }
-        "mysql" => {
-            if DBMAP.lock().unwrap().mysql.is_none() {
-                DBMAP.lock().unwrap().mysql = Some(Arc::new(
-                    MysqlDatabase::connect(&format!(
-                        "mysql://{}:{}@{}:{}/{}",
-                        SETTINGS.database_user,
-                        SETTINGS.database_password,
-                        SETTINGS.database_host,
-                        SETTINGS.database_port,
-                        SETTINGS.database_database
-                    ))
-                    .expect("Mysql数据库打开失败"),
-                ));
-            }
-            return DBMAP.lock().unwrap().mysql.as_ref().unwrap().clone();
+        path.to_string_lossy().to_string()
+    }
+
+    fn server_url(&self, scheme: &str) -> String {
+        if !self.url.is_empty() {
+            return self.url.clone();
         }
-        "postgresql" => {
-            if DBMAP.lock().unwrap().postgresql.is_none() {
-                DBMAP.lock().unwrap().postgresql = Some(Arc::new(
-                    PostgreSQLDatabase::connect(&format!(
-                        "postgres://{}:{}@{}:{}/{}",
-                        SETTINGS.database_user,
-                        SETTINGS.database_password,
-                        SETTINGS.database_host,
-                        SETTINGS.database_port,
-                        SETTINGS.database_database
-                    ))
-                    .expect("PostgreSQL数据库打开失败"),
-                ));
-            }
-            return DBMAP.lock().unwrap().postgresql.as_ref().unwrap().clone();
+        // Characters such as @ : / # in the credentials would break the url
+        let credentials = if self.user.is_empty() {
+            "".to_string()
+        } else {
+            format!(
+                "{}:{}@",
+                utf8_percent_encode(&self.user, NON_ALPHANUMERIC),
+                utf8_percent_encode(&self.password, NON_ALPHANUMERIC)
+            )
+        };
+        if scheme == "mongodb" {
+            format!("mongodb://{}{}:{}", credentials, self.host, self.port)
+        } else {
+            format!(
+                "{}://{}{}:{}/{}",
+                scheme, credentials, self.host, self.port, self.database
+            )
         }
-        "mongodb" => {
-            if DBMAP.lock().unwrap().mongodb.is_none() {
-                DBMAP.lock().unwrap().mongodb = Some(Arc::new(
-                    MongodbDatabase::connect(
-                        &format!(
-                            "mongodb://{}:{}",
-                            SETTINGS.database_host, SETTINGS.database_port
-                        ),
-                        &SETTINGS.database_user,
-                        &SETTINGS.database_password,
-                        &SETTINGS.database_database,
-                    )
-                    .expect("MongoDB数据库打开失败"),
-                ));
+    }
+
+    fn connect(&self) -> Result<Arc<dyn BaseDatabase>, DatabaseError> {
+        Ok(match self.name.as_str() {
+            "sqlite" => Arc::new(SqliteDatabase::connect(&self.path(), self)?),
+            "mysql" => Arc::new(MysqlDatabase::connect(&self.server_url("mysql"), self)?),
+            "postgresql" => Arc::new(PostgreSQLDatabase::connect(
+                &self.server_url("postgres"),
+                self,
+            )?),
+            "mongodb" => Arc::new(MongodbDatabase::connect(&self.server_url("mongodb"), self)?),
+            "parquet" => Arc::new(ParquetDatabase::connect(&self.path(), self.tz()?)?),
+            _ => {
+                return Err(SettingError::invalid(
+                    "database.name",
+                    format!("不支持的数据库{}", self.name),
+                )
+                .into())
             }
-            return DBMAP.lock().unwrap().mongodb.as_ref().unwrap().clone();
-        }
-        _ => {
-            unreachable!("unsupported Database")
+        })
+    }
+}
+
+#[pymethods]
+impl DatabaseConfig {
+    #[new]
+    #[pyo3(signature = (name="sqlite".to_string(),database="database.db".to_string(),url="".to_string(),host="".to_string(),port=0,user="".to_string(),password="".to_string(),timezone=None,bar_table="".to_string(),tick_table="".to_string(),columns=BTreeMap::new(),datetime_format="".to_string(),exchange_timezones=BTreeMap::new(),symbol_timezones=BTreeMap::new()))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn __new__(
+        name: String,
+        database: String,
+        url: String,
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+        timezone: Option<String>,
+        bar_table: String,
+        tick_table: String,
+        columns: BTreeMap<String, String>,
+        datetime_format: String,
+        exchange_timezones: BTreeMap<String, String>,
+        symbol_timezones: BTreeMap<String, String>,
+    ) -> PyResult<Self> {
+        if !DATABASE_NAMES.contains(&name.as_str()) {
+            return Err(
+                SettingError::invalid("database.name", format!("不支持的数据库{}", name)).into(),
+            );
         }
+        let timezone = match timezone {
+            Some(timezone) => timezone,
+            None => try_settings()?.database_timezone.clone(),
+        };
+        let config = DatabaseConfig {
+            name,
+            database,
+            url,
+            host,
+            port,
+            user,
+            password,
+            timezone,
+            bar_table,
+            tick_table,
+            columns,
+            datetime_format,
+            exchange_timezones,
+            symbol_timezones,
+        };
+        config.tz()?;
+        config.schema(("", ""), DatetimeFormat::NAIVE)?;
+        Ok(config)
+    }
+
+    /// The config in vt_setting.json, used by engines without their own config
+    #[staticmethod]
+    #[pyo3(name = "from_settings")]
+    pub fn py_from_settings() -> PyResult<Self> {
+        Ok(DatabaseConfig::from_settings()?)
+    }
+
+    pub fn __getnewargs_ex__(&self, py: Python<'_>) -> PyResult<(Py<PyTuple>, Py<PyDict>)> {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("name", &self.name)?;
+        kwargs.set_item("database", &self.database)?;
+        kwargs.set_item("url", &self.url)?;
+        kwargs.set_item("host", &self.host)?;
+        kwargs.set_item("port", self.port)?;
+        kwargs.set_item("user", &self.user)?;
+        kwargs.set_item("password", &self.password)?;
+        kwargs.set_item("timezone", &self.timezone)?;
+        kwargs.set_item("bar_table", &self.bar_table)?;
+        kwargs.set_item("tick_table", &self.tick_table)?;
+        kwargs.set_item("columns", &self.columns)?;
+        kwargs.set_item("datetime_format", &self.datetime_format)?;
+        kwargs.set_item("exchange_timezones", &self.exchange_timezones)?;
+        kwargs.set_item("symbol_timezones", &self.symbol_timezones)?;
+        Ok((PyTuple::empty(py).unbind(), kwargs.unbind()))
+    }
+
+    pub fn __repr__(&self) -> String {
+        // Password is never shown
+        format!(
+            "DatabaseConfig(name={}, database={}, url={}, host={}, port={}, user={}, timezone={}, bar_table={}, tick_table={}, columns={:?}, datetime_format={}, exchange_timezones={:?}, symbol_timezones={:?})",
+            self.name,
+            self.database,
+            self.url,
+            self.host,
+            self.port,
+            self.user,
+            self.timezone,
+            self.bar_table,
+            self.tick_table,
+            self.columns,
+            self.datetime_format,
+            self.exchange_timezones,
+            self.symbol_timezones
+        )
     }
 }
+
+/// Get the database of the config, connecting at first use
+pub fn get_database_by_config(
+    config: &DatabaseConfig,
+) -> Result<Arc<dyn BaseDatabase>, DatabaseError> {
+    if let Some(database) = DBMAP.lock().unwrap().get(config) {
+        return Ok(database.clone());
+    }
+    // Connect without the lock, a slow database shouldn't block the others. When two
+    // threads race the first connection stored wins and the other one is dropped
+    let database = with_retry(|| config.connect())?;
+    Ok(DBMAP
+        .lock()
+        .unwrap()
+        .entry(config.clone())
+        .or_insert(database)
+        .clone())
+}
+
+pub fn get_database() -> Result<Arc<dyn BaseDatabase>, DatabaseError> {
+    // Read database related global setting
+    get_database_by_config(&DatabaseConfig::from_settings()?)
+}
+
+fn database_of(config: Option<DatabaseConfig>) -> Result<Arc<dyn BaseDatabase>, DatabaseError> {
+    match config {
+        Some(config) => get_database_by_config(&config),
+        None => get_database(),
+    }
+}
+
+/// Save a list of BarData (vnpyrs or vnpy) into the database of the config,
+/// or of vt_setting.json when there is none. Only parquet databases can be written.
+#[pyfunction]
+#[pyo3(signature = (bars, config=None))]
+pub fn save_bar_data(bars: Bound<'_, PyAny>, config: Option<DatabaseConfig>) -> PyResult<()> {
+    let mut list = Vec::new();
+    for bar in bars.try_iter()? {
+        list.push(BarData::from_pyobject(&bar?)?);
+    }
+    database_of(config)?.save_bar_data(list)?;
+    Ok(())
+}
+
+/// Save a list of TickData (vnpyrs or vnpy) into the database of the config,
+/// or of vt_setting.json when there is none. Only parquet databases can be written.
+#[pyfunction]
+#[pyo3(signature = (ticks, config=None))]
+pub fn save_tick_data(ticks: Bound<'_, PyAny>, config: Option<DatabaseConfig>) -> PyResult<()> {
+    let mut list = Vec::new();
+    for tick in ticks.try_iter()? {
+        list.push(TickData::from_pyobject(&tick?)?);
+    }
+    database_of(config)?.save_tick_data(list)?;
+    Ok(())
+}
+
+#[pymodule]
+pub fn database(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<MemoryDatabase>()?;
+    m.add_class::<DatabaseConfig>()?;
+    m.add_function(wrap_pyfunction!(save_bar_data, m)?)?;
+    m.add_function(wrap_pyfunction!(save_tick_data, m)?)?;
+    m.add(
+        "DatabaseConnectionError",
+        m.py().get_type::<DatabaseConnectionError>(),
+    )?;
+    m.add(
+        "ConfigurationError",
+        m.py().get_type::<ConfigurationError>(),
+    )?;
+    m.add("DataError", m.py().get_type::<DataError>())?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use chrono_tz::Tz;
 use sqlx::mysql::MySqlPoolOptions;
 use sqlx::postgres::PgPool;
@@ -6,38 +6,189 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::MySqlPool;
 use sqlx::Row;
 use sqlx::SqlitePool;
-use std::collections::LinkedList;
+use std::collections::{HashMap, LinkedList};
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::LazyLock;
 use std::sync::Mutex;
 
-use super::database::DB_TZ;
+use super::database::DatabaseConfig;
 use super::object::MixData;
 use super::object::TickData;
+use super::schema::{
+    DatetimeFormat, SchemaMapping, BAR_KEY_FIELDS, BAR_VALUE_COLUMNS, TICK_KEY_FIELDS,
+    TICK_VALUE_COLUMNS,
+};
+use super::setting::{try_settings, SettingError};
+use super::utility::localize;
 use super::{constant::Interval, object::BarData};
 
-pub static DBMAP: Mutex<GlobalDBMap> = Mutex::new(GlobalDBMap::new());
-static SH_TZ: Tz = Tz::Asia__Shanghai;
+/// Connections are cached per config, so engines sharing a config share one connection pool
+pub static DBMAP: LazyLock<Mutex<HashMap<DatabaseConfig, Arc<dyn BaseDatabase>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
-pub struct GlobalDBMap {
-    pub sqlite: Option<Arc<SqliteDatabase>>,
-    pub mysql: Option<Arc<MysqlDatabase>>,
-    pub postgresql: Option<Arc<PostgreSQLDatabase>>,
-    pub mongodb: Option<Arc<MongodbDatabase>>,
+#[derive(Debug, Clone)]
+pub enum DatabaseError {
+    /// The database can't be reached, worth retrying
+    Connection {
+        backend: &'static str,
+        message: String,
+    },
+    /// Settings can't be used to open the database
+    Config(SettingError),
+    /// Query failed, or data in the database can't be decoded
+    Data {
+        backend: &'static str,
+        message: String,
+    },
 }
 
-impl GlobalDBMap {
-    pub const fn new() -> Self {
-        GlobalDBMap {
-            sqlite: None,
-            mysql: None,
-            postgresql: None,
-            mongodb: None,
+impl DatabaseError {
+    pub fn connection(backend: &'static str, message: impl fmt::Display) -> Self {
+        DatabaseError::Connection {
+            backend,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn data(backend: &'static str, message: impl fmt::Display) -> Self {
+        DatabaseError::Data {
+            backend,
+            message: message.to_string(),
+        }
+    }
+
+    fn sqlx(backend: &'static str, e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => DatabaseError::connection(backend, e),
+            sqlx::Error::Configuration(_) => {
+                DatabaseError::Config(SettingError::invalid("database.host", &e))
+            }
+            _ => DatabaseError::data(backend, e),
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DatabaseError::Connection { .. })
+    }
+
+    /// Prefix the message with what was being done when the error happened
+    pub fn context(self, context: &str) -> Self {
+        match self {
+            DatabaseError::Connection { backend, message } => DatabaseError::Connection {
+                backend,
+                message: format!("{}：{}", context, message),
+            },
+            DatabaseError::Data { backend, message } => DatabaseError::Data {
+                backend,
+                message: format!("{}：{}", context, message),
+            },
+            DatabaseError::Config(e) => DatabaseError::Config(e),
         }
     }
 }
 
-pub trait BaseDatabase {
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Connection { backend, message } => {
+                write!(f, "{}数据库连接失败：{}", backend, message)
+            }
+            DatabaseError::Config(e) => e.fmt(f),
+            DatabaseError::Data { backend, message } => {
+                write!(f, "{}数据库读取失败：{}", backend, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<SettingError> for DatabaseError {
+    fn from(e: SettingError) -> Self {
+        DatabaseError::Config(e)
+    }
+}
+
+impl From<mongodb::error::Error> for DatabaseError {
+    fn from(e: mongodb::error::Error) -> Self {
+        match e.kind.as_ref() {
+            mongodb::error::ErrorKind::Io(_)
+            | mongodb::error::ErrorKind::DnsResolve { .. }
+            | mongodb::error::ErrorKind::ConnectionPoolCleared { .. }
+            | mongodb::error::ErrorKind::ServerSelection { .. } => {
+                DatabaseError::connection("MongoDB", e)
+            }
+            mongodb::error::ErrorKind::InvalidArgument { .. }
+            | mongodb::error::ErrorKind::InvalidTlsConfig { .. } => {
+                DatabaseError::Config(SettingError::invalid("database.host", &e))
+            }
+            _ => DatabaseError::data("MongoDB", e),
+        }
+    }
+}
+
+/// Run f, retrying transient failures database.retry times.
+/// The wait starts at database.retry_interval seconds and doubles every time.
+pub fn with_retry<T>(mut f: impl FnMut() -> Result<T, DatabaseError>) -> Result<T, DatabaseError> {
+    let setting = try_settings()?;
+    let mut interval = setting.database_retry_interval;
+    let mut retry = 0;
+    loop {
+        match f() {
+            Err(e) if e.is_transient() && retry < setting.database_retry => {
+                std::thread::sleep(std::time::Duration::from_secs_f64(interval));
+                interval *= 2.0;
+                retry += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Loads of different chunks may block_on the same runtime from several threads at once,
+/// so the runtime must have its own workers to drive the shared connection pool
+fn database_runtime(backend: &'static str) -> Result<tokio::runtime::Runtime, DatabaseError> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(usize::max(try_settings()?.database_concurrency as usize, 1))
+        .enable_all()
+        .build()
+        .map_err(|e| DatabaseError::connection(backend, e))
+}
+
+/// Row::try_get with the error converted, so that columns can be read with `?`
+trait RowExt: Row {
+    fn decode<'r, T>(&'r self, index: usize) -> Result<T, DatabaseError>
+    where
+        usize: sqlx::ColumnIndex<Self>,
+        T: sqlx::Decode<'r, Self::Database> + sqlx::Type<Self::Database>,
+    {
+        self.try_get(index)
+            .map_err(|e| DatabaseError::sqlx(<Self::Database as sqlx::Database>::NAME, e))
+    }
+}
+
+impl<R: Row> RowExt for R {}
+
+fn parse_interval(backend: &'static str, interval: &str) -> Result<Interval, DatabaseError> {
+    Interval::from_str(interval).map_err(|_| {
+        DatabaseError::data(
+            backend,
+            format!(
+                "数据库中interval字段只能是1m,1h,d,w,tick中的一个，实际为{}",
+                interval
+            ),
+        )
+    })
+}
+
+pub trait BaseDatabase: Send + Sync {
     fn load_bar_data(
         &self,
         symbol: &str,
@@ -45,28 +196,416 @@ pub trait BaseDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData>;
+    ) -> Result<LinkedList<MixData>, DatabaseError>;
     fn load_tick_data(
         &self,
         symbol: &str,
         exchange: &str,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData>;
+    ) -> Result<LinkedList<MixData>, DatabaseError>;
+    /// Save bars, replacing those already stored at the same datetime
+    fn save_bar_data(&self, _bars: Vec<BarData>) -> Result<(), DatabaseError> {
+        Err(read_only())
+    }
+    /// Save ticks, replacing those already stored at the same datetime
+    fn save_tick_data(&self, _ticks: Vec<TickData>) -> Result<(), DatabaseError> {
+        Err(read_only())
+    }
+}
+
+/// Error of the backends which are only read from
+fn read_only() -> DatabaseError {
+    DatabaseError::Config(SettingError::invalid(
+        "database.name",
+        "只有parquet数据库支持写入数据",
+    ))
+}
+
+/// Query parameters, bound in order
+enum SqlParam {
+    Text(String),
+    Datetime(NaiveDateTime),
+    Millis(i64),
+}
+
+/// How datetimes of one contract are stored
+#[derive(Clone, Copy)]
+struct DatetimeStorage {
+    format: DatetimeFormat,
+    /// Timezone of naive datetimes
+    tz: Tz,
+}
+
+impl DatetimeStorage {
+    fn new(schema: &SchemaMapping, symbol: &str, exchange: &str) -> Self {
+        DatetimeStorage {
+            format: schema.datetime_format,
+            tz: schema.storage_tz(symbol, exchange),
+        }
+    }
+
+    fn param(self, datetime: DateTime<Tz>) -> SqlParam {
+        match self.format {
+            DatetimeFormat::NAIVE => {
+                SqlParam::Datetime(datetime.with_timezone(&self.tz).naive_local())
+            }
+            DatetimeFormat::UTC => SqlParam::Datetime(datetime.naive_utc()),
+            DatetimeFormat::EPOCHMS => SqlParam::Millis(datetime.timestamp_millis()),
+        }
+    }
+
+    /// Naive datetimes read from the database, for the naive format they are in the storage timezone
+    fn naive_to_utc(self, datetime: NaiveDateTime) -> DateTime<Utc> {
+        match self.format {
+            DatetimeFormat::NAIVE => localize(self.tz, datetime).with_timezone(&Utc),
+            _ => datetime.and_utc(),
+        }
+    }
+}
+
+fn bind_params<'q, DB: sqlx::Database>(
+    mut query: sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>,
+    params: Vec<SqlParam>,
+) -> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>
+where
+    String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    NaiveDateTime: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+{
+    for param in params {
+        query = match param {
+            SqlParam::Text(text) => query.bind(text),
+            SqlParam::Datetime(datetime) => query.bind(datetime),
+            SqlParam::Millis(millis) => query.bind(millis),
+        };
+    }
+    query
+}
+
+#[derive(Clone, Copy)]
+enum SqlDialect {
+    Sqlite,
+    Mysql,
+    Postgres,
+}
+
+impl SqlDialect {
+    /// Every part of a dotted name is quoted, so that `schema.table` works
+    fn quote(self, name: &str) -> String {
+        let quote = match self {
+            SqlDialect::Mysql => '`',
+            _ => '"',
+        };
+        name.split('.')
+            .map(|part| format!("{0}{1}{0}", quote, part))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn placeholder(self, n: usize) -> String {
+        match self {
+            SqlDialect::Postgres => format!("${}", n),
+            _ => "?".to_string(),
+        }
+    }
+
+    /// SELECT the columns of `fields` that exist, filtered by `filters` and the datetime range.
+    /// Returns the sql, its parameters, and the position in the result of every field.
+    #[allow(clippy::too_many_arguments)]
+    fn select(
+        self,
+        schema: &SchemaMapping,
+        table: &str,
+        fields: impl Iterator<Item = &'static str>,
+        filters: &[(&str, &str)],
+        storage: DatetimeStorage,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+    ) -> (String, Vec<SqlParam>, Vec<Option<usize>>) {
+        let mut columns = Vec::new();
+        let positions = fields
+            .map(|field| {
+                schema.column(field).map(|column| {
+                    columns.push(self.quote(column));
+                    columns.len() - 1
+                })
+            })
+            .collect();
+
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for (field, value) in filters {
+            if let Some(column) = schema.column(field) {
+                params.push(SqlParam::Text(value.to_string()));
+                conditions.push(format!(
+                    "{}={}",
+                    self.quote(column),
+                    self.placeholder(params.len())
+                ));
+            }
+        }
+        let datetime = self.quote(schema.column("datetime").unwrap());
+        params.push(storage.param(start));
+        conditions.push(format!("{}>={}", datetime, self.placeholder(params.len())));
+        params.push(storage.param(end));
+        conditions.push(format!("{}<={}", datetime, self.placeholder(params.len())));
+
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {} ORDER BY {}",
+            columns.join(","),
+            self.quote(table),
+            conditions.join(" AND "),
+            datetime
+        );
+        (sql, params, positions)
+    }
+}
+
+/// Values of any sql backend, whatever type the column was created with. NULL is read as empty.
+trait SqlRowExt: RowExt {
+    fn text(&self, index: usize) -> Result<String, DatabaseError>;
+    fn number(&self, index: usize) -> Result<f64, DatabaseError>;
+    fn naive_datetime(&self, index: usize) -> Result<Option<NaiveDateTime>, DatabaseError>;
+    fn datetime(
+        &self,
+        index: usize,
+        storage: DatetimeStorage,
+    ) -> Result<DateTime<Utc>, DatabaseError>;
+}
+
+impl<R> SqlRowExt for R
+where
+    R: Row,
+    usize: sqlx::ColumnIndex<R>,
+    for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> f64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> f32: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> NaiveDateTime: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> DateTime<Utc>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    fn text(&self, index: usize) -> Result<String, DatabaseError> {
+        Ok(self.decode::<Option<String>>(index)?.unwrap_or_default())
+    }
+
+    fn number(&self, index: usize) -> Result<f64, DatabaseError> {
+        if let Ok(value) = self.decode::<Option<f64>>(index) {
+            return Ok(value.unwrap_or_default());
+        }
+        // REAL of postgresql, parsed from text so that 0.1 stays 0.1
+        if let Ok(value) = self.decode::<Option<f32>>(index) {
+            return Ok(value.map_or(0.0, |value| value.to_string().parse().unwrap()));
+        }
+        Ok(self.decode::<Option<i64>>(index)?.unwrap_or_default() as f64)
+    }
+
+    fn naive_datetime(&self, index: usize) -> Result<Option<NaiveDateTime>, DatabaseError> {
+        self.decode(index)
+    }
+
+    fn datetime(
+        &self,
+        index: usize,
+        storage: DatetimeStorage,
+    ) -> Result<DateTime<Utc>, DatabaseError> {
+        if storage.format == DatetimeFormat::EPOCHMS {
+            let millis = self.number(index)? as i64;
+            return DateTime::from_timestamp_millis(millis).ok_or_else(|| {
+                DatabaseError::data(
+                    <R::Database as sqlx::Database>::NAME,
+                    format!("时间戳{}超出范围", millis),
+                )
+            });
+        }
+        match self.decode::<NaiveDateTime>(index) {
+            Ok(datetime) => Ok(storage.naive_to_utc(datetime)),
+            // Columns with timezone
+            Err(e) => self.decode::<DateTime<Utc>>(index).map_err(|_| e),
+        }
+    }
+}
+
+fn bar_from_values(
+    symbol: String,
+    exchange: String,
+    datetime: DateTime<Tz>,
+    interval: Interval,
+    values: &[f64],
+) -> BarData {
+    BarData {
+        symbol,
+        exchange,
+        datetime,
+        interval,
+        volume: values[0],
+        turnover: values[1],
+        open_interest: values[2],
+        open_price: values[3],
+        high_price: values[4],
+        low_price: values[5],
+        close_price: values[6],
+        gateway_name: "DB",
+    }
+}
+
+fn tick_from_values(
+    symbol: String,
+    exchange: String,
+    datetime: DateTime<Tz>,
+    name: String,
+    values: &[f64],
+    localtime: NaiveDateTime,
+) -> TickData {
+    TickData {
+        symbol,
+        exchange,
+        datetime,
+        name,
+        volume: values[0],
+        turnover: values[1],
+        open_interest: values[2],
+        last_price: values[3],
+        last_volume: values[4],
+        limit_up: values[5],
+        limit_down: values[6],
+        open_price: values[7],
+        high_price: values[8],
+        low_price: values[9],
+        pre_close: values[10],
+        bid_price_1: values[11],
+        bid_price_2: values[12],
+        bid_price_3: values[13],
+        bid_price_4: values[14],
+        bid_price_5: values[15],
+        ask_price_1: values[16],
+        ask_price_2: values[17],
+        ask_price_3: values[18],
+        ask_price_4: values[19],
+        ask_price_5: values[20],
+        bid_volume_1: values[21],
+        bid_volume_2: values[22],
+        bid_volume_3: values[23],
+        bid_volume_4: values[24],
+        bid_volume_5: values[25],
+        ask_volume_1: values[26],
+        ask_volume_2: values[27],
+        ask_volume_3: values[28],
+        ask_volume_4: values[29],
+        ask_volume_5: values[30],
+        localtime,
+        gateway_name: "DB",
+    }
+}
+
+/// Columns missing from the table take the queried value, or 0 for numbers
+fn bars_from_rows<R: SqlRowExt>(
+    rows: &[R],
+    positions: &[Option<usize>],
+    storage: DatetimeStorage,
+    symbol: &str,
+    exchange: &str,
+    interval: Interval,
+    tz: Tz,
+) -> Result<LinkedList<MixData>, DatabaseError> {
+    let backend = <R::Database as sqlx::Database>::NAME;
+    let mut bars = LinkedList::new();
+    for row in rows {
+        let text = |i: usize, default: &str| match positions[i] {
+            Some(index) => row.text(index),
+            None => Ok(default.to_string()),
+        };
+        let values = positions[BAR_KEY_FIELDS.len()..]
+            .iter()
+            .map(|position| position.map_or(Ok(0.0), |index| row.number(index)))
+            .collect::<Result<Vec<f64>, DatabaseError>>()?;
+        let interval = match positions[3] {
+            Some(index) => parse_interval(backend, &row.text(index)?)?,
+            None => interval,
+        };
+        bars.push_back(MixData::BarData(bar_from_values(
+            text(0, symbol)?,
+            text(1, exchange)?,
+            row.datetime(positions[2].unwrap(), storage)?
+                .with_timezone(&tz),
+            interval,
+            &values,
+        )));
+    }
+    Ok(bars)
+}
+
+/// Without a localtime column, localtime is the datetime in the display timezone
+fn ticks_from_rows<R: SqlRowExt>(
+    rows: &[R],
+    positions: &[Option<usize>],
+    storage: DatetimeStorage,
+    symbol: &str,
+    exchange: &str,
+    tz: Tz,
+) -> Result<LinkedList<MixData>, DatabaseError> {
+    let localtime_position = positions[positions.len() - 1];
+    let mut ticks = LinkedList::new();
+    for row in rows {
+        let text = |i: usize, default: &str| match positions[i] {
+            Some(index) => row.text(index),
+            None => Ok(default.to_string()),
+        };
+        let values = positions[TICK_KEY_FIELDS.len()..positions.len() - 1]
+            .iter()
+            .map(|position| position.map_or(Ok(0.0), |index| row.number(index)))
+            .collect::<Result<Vec<f64>, DatabaseError>>()?;
+        let datetime = row
+            .datetime(positions[2].unwrap(), storage)?
+            .with_timezone(&tz);
+        let localtime = match localtime_position {
+            Some(index) if storage.format == DatetimeFormat::NAIVE => row.naive_datetime(index)?,
+            Some(index) => Some(
+                row.datetime(index, storage)?
+                    .with_timezone(&tz)
+                    .naive_local(),
+            ),
+            None => None,
+        }
+        .unwrap_or(datetime.naive_local());
+        ticks.push_back(MixData::TickData(tick_from_values(
+            text(0, symbol)?,
+            text(1, exchange)?,
+            datetime,
+            text(3, "")?,
+            &values,
+            localtime,
+        )));
+    }
+    Ok(ticks)
+}
+
+/// Schema of vnpy_sqlite, vnpy_mysql and vnpy_postgresql unless configured
+fn sql_schema(config: &DatabaseConfig) -> Result<SchemaMapping, SettingError> {
+    config.schema(("dbbardata", "dbtickdata"), DatetimeFormat::NAIVE)
 }
 
 pub struct SqliteDatabase {
+    tz: Tz,
+    schema: SchemaMapping,
     pool: SqlitePool,
     rt: tokio::runtime::Runtime,
 }
 
 impl SqliteDatabase {
-    pub fn connect(url: &str) -> Result<SqliteDatabase, Box<dyn std::error::Error>> {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        let pool = rt.block_on(SqlitePool::connect(url))?;
-        Ok(SqliteDatabase { pool, rt })
+    pub fn connect(url: &str, config: &DatabaseConfig) -> Result<SqliteDatabase, DatabaseError> {
+        let tz = config.tz()?;
+        let schema = sql_schema(config)?;
+        let rt = database_runtime("SQLite")?;
+        let pool = rt
+            .block_on(SqlitePool::connect(url))
+            .map_err(|e| DatabaseError::sqlx("SQLite", e))?;
+        Ok(SqliteDatabase {
+            tz,
+            schema,
+            pool,
+            rt,
+        })
     }
 }
 
@@ -78,37 +617,29 @@ impl BaseDatabase for SqliteDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
         let interval_str = interval.to_string();
-
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,interval,volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=? AND exchange=? AND interval=? AND datetime>=? AND datetime<=? ORDER BY datetime")
-                    .bind(symbol).bind(exchange).bind(interval_str).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut bars = LinkedList::new();
-        for db_bar in s.iter() {
-            bars.push_back(MixData::BarData(BarData {
-                symbol: db_bar.get::<String, usize>(0),
-                exchange: db_bar.get::<String, usize>(1),
-                datetime: db_bar
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                interval: Interval::from_str(db_bar.get::<&str, usize>(3))
-                    .expect("数据库中interval字段只能是1m,1h,d,w,tick中的一个"),
-                volume: db_bar.get::<f64, usize>(4),
-                turnover: db_bar.get::<f64, usize>(5),
-                open_interest: db_bar.get::<f64, usize>(6),
-                open_price: db_bar.get::<f64, usize>(7),
-                high_price: db_bar.get::<f64, usize>(8),
-                low_price: db_bar.get::<f64, usize>(9),
-                close_price: db_bar.get::<f64, usize>(10),
-                gateway_name: "DB",
-            }));
-        }
-        bars
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Sqlite.select(
+            &self.schema,
+            &self.schema.bar_table,
+            SchemaMapping::bar_fields(),
+            &[
+                ("symbol", symbol),
+                ("exchange", exchange),
+                ("interval", &interval_str),
+            ],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("SQLite", e))?;
+        bars_from_rows(
+            &rows, &positions, storage, symbol, exchange, interval, self.tz,
+        )
     }
 
     fn load_tick_data(
@@ -117,74 +648,50 @@ impl BaseDatabase for SqliteDatabase {
         exchange: &str,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,name,volume,turnover,open_interest,last_price,last_volume,limit_up,limit_down,open_price,high_price,low_price,pre_close,bid_price_1,bid_price_2,bid_price_3,bid_price_4,bid_price_5,ask_price_1,ask_price_2,ask_price_3,ask_price_4,ask_price_5,bid_volume_1,bid_volume_2,bid_volume_3,bid_volume_4,bid_volume_5,ask_volume_1,ask_volume_2,ask_volume_3,ask_volume_4,ask_volume_5,localtime FROM dbbardata WHERE symbol=? AND exchange=? AND datetime>=? AND datetime<=? ORDER BY datetime")
-                    .bind(symbol).bind(exchange).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut ticks = LinkedList::new();
-        for db_tick in s.iter() {
-            ticks.push_back(MixData::TickData(TickData {
-                symbol: db_tick.get::<String, usize>(0),
-                exchange: db_tick.get::<String, usize>(1),
-                datetime: db_tick
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                name: db_tick.get::<String, usize>(3),
-                volume: db_tick.get::<f64, usize>(4),
-                turnover: db_tick.get::<f64, usize>(5),
-                open_interest: db_tick.get::<f64, usize>(6),
-                last_price: db_tick.get::<f64, usize>(7),
-                last_volume: db_tick.get::<f64, usize>(8),
-                limit_up: db_tick.get::<f64, usize>(9),
-                limit_down: db_tick.get::<f64, usize>(10),
-                open_price: db_tick.get::<f64, usize>(11),
-                high_price: db_tick.get::<f64, usize>(12),
-                low_price: db_tick.get::<f64, usize>(13),
-                pre_close: db_tick.get::<f64, usize>(14),
-                bid_price_1: db_tick.get::<f64, usize>(15),
-                bid_price_2: db_tick.get::<f64, usize>(16),
-                bid_price_3: db_tick.get::<f64, usize>(17),
-                bid_price_4: db_tick.get::<f64, usize>(18),
-                bid_price_5: db_tick.get::<f64, usize>(19),
-                ask_price_1: db_tick.get::<f64, usize>(20),
-                ask_price_2: db_tick.get::<f64, usize>(21),
-                ask_price_3: db_tick.get::<f64, usize>(22),
-                ask_price_4: db_tick.get::<f64, usize>(23),
-                ask_price_5: db_tick.get::<f64, usize>(24),
-                bid_volume_1: db_tick.get::<f64, usize>(25),
-                bid_volume_2: db_tick.get::<f64, usize>(26),
-                bid_volume_3: db_tick.get::<f64, usize>(27),
-                bid_volume_4: db_tick.get::<f64, usize>(28),
-                bid_volume_5: db_tick.get::<f64, usize>(29),
-                ask_volume_1: db_tick.get::<f64, usize>(30),
-                ask_volume_2: db_tick.get::<f64, usize>(31),
-                ask_volume_3: db_tick.get::<f64, usize>(32),
-                ask_volume_4: db_tick.get::<f64, usize>(33),
-                ask_volume_5: db_tick.get::<f64, usize>(34),
-                localtime: db_tick.get::<NaiveDateTime, usize>(35),
-                gateway_name: "DB",
-            }));
-        }
-        ticks
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Sqlite.select(
+            &self.schema,
+            &self.schema.tick_table,
+            SchemaMapping::tick_fields(),
+            &[("symbol", symbol), ("exchange", exchange)],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("SQLite", e))?;
+        ticks_from_rows(&rows, &positions, storage, symbol, exchange, self.tz)
     }
 }
 
 pub struct MysqlDatabase {
+    tz: Tz,
+    schema: SchemaMapping,
     pool: MySqlPool,
     rt: tokio::runtime::Runtime,
 }
 
 impl MysqlDatabase {
-    pub fn connect(url: &str) -> Result<MysqlDatabase, Box<dyn std::error::Error>> {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        let pool = rt.block_on(MySqlPoolOptions::new().max_connections(5).connect(url))?;
-        Ok(MysqlDatabase { pool, rt })
+    pub fn connect(url: &str, config: &DatabaseConfig) -> Result<MysqlDatabase, DatabaseError> {
+        let tz = config.tz()?;
+        let schema = sql_schema(config)?;
+        let rt = database_runtime("MySQL")?;
+        let pool = rt
+            .block_on(
+                MySqlPoolOptions::new()
+                    .max_connections(try_settings()?.database_concurrency as u32)
+                    .connect(url),
+            )
+            .map_err(|e| DatabaseError::sqlx("MySQL", e))?;
+        Ok(MysqlDatabase {
+            tz,
+            schema,
+            pool,
+            rt,
+        })
     }
 }
 
@@ -196,37 +703,29 @@ impl BaseDatabase for MysqlDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
         let interval_str = interval.to_string();
-
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,`interval`,volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=? AND exchange=? AND `interval`=? AND datetime>=? AND datetime<=? ORDER BY datetime")
-                    .bind(symbol).bind(exchange).bind(interval_str).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut bars = LinkedList::new();
-        for db_bar in s.iter() {
-            bars.push_back(MixData::BarData(BarData {
-                symbol: db_bar.get::<String, usize>(0),
-                exchange: db_bar.get::<String, usize>(1),
-                datetime: db_bar
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                interval: Interval::from_str(db_bar.get::<&str, usize>(3))
-                    .expect("数据库中interval字段只能是1m,1h,d,w,tick中的一个"),
-                volume: db_bar.get::<f64, usize>(4),
-                turnover: db_bar.get::<f64, usize>(5),
-                open_interest: db_bar.get::<f64, usize>(6),
-                open_price: db_bar.get::<f64, usize>(7),
-                high_price: db_bar.get::<f64, usize>(8),
-                low_price: db_bar.get::<f64, usize>(9),
-                close_price: db_bar.get::<f64, usize>(10),
-                gateway_name: "DB",
-            }));
-        }
-        bars
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Mysql.select(
+            &self.schema,
+            &self.schema.bar_table,
+            SchemaMapping::bar_fields(),
+            &[
+                ("symbol", symbol),
+                ("exchange", exchange),
+                ("interval", &interval_str),
+            ],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("MySQL", e))?;
+        bars_from_rows(
+            &rows, &positions, storage, symbol, exchange, interval, self.tz,
+        )
     }
 
     fn load_tick_data(
@@ -235,74 +734,53 @@ impl BaseDatabase for MysqlDatabase {
         exchange: &str,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,name,volume,turnover,open_interest,last_price,last_volume,limit_up,limit_down,open_price,high_price,low_price,pre_close,bid_price_1,bid_price_2,bid_price_3,bid_price_4,bid_price_5,ask_price_1,ask_price_2,ask_price_3,ask_price_4,ask_price_5,bid_volume_1,bid_volume_2,bid_volume_3,bid_volume_4,bid_volume_5,ask_volume_1,ask_volume_2,ask_volume_3,ask_volume_4,ask_volume_5,localtime FROM dbbardata WHERE symbol=? AND exchange=? AND datetime>=? AND datetime<=? ORDER BY datetime")
-                    .bind(symbol).bind(exchange).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut ticks = LinkedList::new();
-        for db_tick in s.iter() {
-            ticks.push_back(MixData::TickData(TickData {
-                symbol: db_tick.get::<String, usize>(0),
-                exchange: db_tick.get::<String, usize>(1),
-                datetime: db_tick
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                name: db_tick.get::<String, usize>(3),
-                volume: db_tick.get::<f64, usize>(4),
-                turnover: db_tick.get::<f64, usize>(5),
-                open_interest: db_tick.get::<f64, usize>(6),
-                last_price: db_tick.get::<f64, usize>(7),
-                last_volume: db_tick.get::<f64, usize>(8),
-                limit_up: db_tick.get::<f64, usize>(9),
-                limit_down: db_tick.get::<f64, usize>(10),
-                open_price: db_tick.get::<f64, usize>(11),
-                high_price: db_tick.get::<f64, usize>(12),
-                low_price: db_tick.get::<f64, usize>(13),
-                pre_close: db_tick.get::<f64, usize>(14),
-                bid_price_1: db_tick.get::<f64, usize>(15),
-                bid_price_2: db_tick.get::<f64, usize>(16),
-                bid_price_3: db_tick.get::<f64, usize>(17),
-                bid_price_4: db_tick.get::<f64, usize>(18),
-                bid_price_5: db_tick.get::<f64, usize>(19),
-                ask_price_1: db_tick.get::<f64, usize>(20),
-                ask_price_2: db_tick.get::<f64, usize>(21),
-                ask_price_3: db_tick.get::<f64, usize>(22),
-                ask_price_4: db_tick.get::<f64, usize>(23),
-                ask_price_5: db_tick.get::<f64, usize>(24),
-                bid_volume_1: db_tick.get::<f64, usize>(25),
-                bid_volume_2: db_tick.get::<f64, usize>(26),
-                bid_volume_3: db_tick.get::<f64, usize>(27),
-                bid_volume_4: db_tick.get::<f64, usize>(28),
-                bid_volume_5: db_tick.get::<f64, usize>(29),
-                ask_volume_1: db_tick.get::<f64, usize>(30),
-                ask_volume_2: db_tick.get::<f64, usize>(31),
-                ask_volume_3: db_tick.get::<f64, usize>(32),
-                ask_volume_4: db_tick.get::<f64, usize>(33),
-                ask_volume_5: db_tick.get::<f64, usize>(34),
-                localtime: db_tick.get::<NaiveDateTime, usize>(35),
-                gateway_name: "DB",
-            }));
-        }
-        ticks
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Mysql.select(
+            &self.schema,
+            &self.schema.tick_table,
+            SchemaMapping::tick_fields(),
+            &[("symbol", symbol), ("exchange", exchange)],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("MySQL", e))?;
+        ticks_from_rows(&rows, &positions, storage, symbol, exchange, self.tz)
     }
 }
 
 pub struct PostgreSQLDatabase {
+    tz: Tz,
+    schema: SchemaMapping,
     pool: PgPool,
     rt: tokio::runtime::Runtime,
 }
 
 impl PostgreSQLDatabase {
-    pub fn connect(url: &str) -> Result<PostgreSQLDatabase, Box<dyn std::error::Error>> {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        let pool = rt.block_on(PgPoolOptions::new().max_connections(5).connect(url))?;
-        Ok(PostgreSQLDatabase { pool, rt })
+    pub fn connect(
+        url: &str,
+        config: &DatabaseConfig,
+    ) -> Result<PostgreSQLDatabase, DatabaseError> {
+        let tz = config.tz()?;
+        let schema = sql_schema(config)?;
+        let rt = database_runtime("PostgreSQL")?;
+        let pool = rt
+            .block_on(
+                PgPoolOptions::new()
+                    .max_connections(try_settings()?.database_concurrency as u32)
+                    .connect(url),
+            )
+            .map_err(|e| DatabaseError::sqlx("PostgreSQL", e))?;
+        Ok(PostgreSQLDatabase {
+            tz,
+            schema,
+            pool,
+            rt,
+        })
     }
 }
 
@@ -314,37 +792,29 @@ impl BaseDatabase for PostgreSQLDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
         let interval_str = interval.to_string();
-
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,\"interval\",volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=$1 AND exchange=$2 AND \"interval\"=$3 AND datetime>=$4 AND datetime<=$5 ORDER BY datetime")
-            .bind(symbol).bind(exchange).bind(interval_str).bind(start).bind(end)
-            .fetch_all(&self.pool)).unwrap();
-        let mut bars = LinkedList::new();
-        for db_bar in s.iter() {
-            bars.push_back(MixData::BarData(BarData {
-                symbol: db_bar.get::<String, usize>(0),
-                exchange: db_bar.get::<String, usize>(1),
-                datetime: db_bar
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                interval: Interval::from_str(db_bar.get::<&str, usize>(3))
-                    .expect("数据库中interval字段只能是1m,1h,d,w,tick中的一个"),
-                volume: db_bar.get::<f32, usize>(4).to_string().parse().unwrap(),
-                turnover: db_bar.get::<f32, usize>(5).to_string().parse().unwrap(),
-                open_interest: db_bar.get::<f32, usize>(6).to_string().parse().unwrap(),
-                open_price: db_bar.get::<f32, usize>(7).to_string().parse().unwrap(),
-                high_price: db_bar.get::<f32, usize>(8).to_string().parse().unwrap(),
-                low_price: db_bar.get::<f32, usize>(9).to_string().parse().unwrap(),
-                close_price: db_bar.get::<f32, usize>(10).to_string().parse().unwrap(),
-                gateway_name: "DB",
-            }));
-        }
-        bars
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Postgres.select(
+            &self.schema,
+            &self.schema.bar_table,
+            SchemaMapping::bar_fields(),
+            &[
+                ("symbol", symbol),
+                ("exchange", exchange),
+                ("interval", &interval_str),
+            ],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("PostgreSQL", e))?;
+        bars_from_rows(
+            &rows, &positions, storage, symbol, exchange, interval, self.tz,
+        )
     }
 
     fn load_tick_data(
@@ -353,67 +823,32 @@ impl BaseDatabase for PostgreSQLDatabase {
         exchange: &str,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let tz = DB_TZ.clone();
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,name,volume,turnover,open_interest,last_price,last_volume,limit_up,limit_down,open_price,high_price,low_price,pre_close,bid_price_1,bid_price_2,bid_price_3,bid_price_4,bid_price_5,ask_price_1,ask_price_2,ask_price_3,ask_price_4,ask_price_5,bid_volume_1,bid_volume_2,bid_volume_3,bid_volume_4,bid_volume_5,ask_volume_1,ask_volume_2,ask_volume_3,ask_volume_4,ask_volume_5,localtime FROM dbbardata WHERE symbol=$1 AND exchange=$2 AND datetime>=$3 AND datetime<=$4 ORDER BY datetime")
-                    .bind(symbol).bind(exchange).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut ticks = LinkedList::new();
-        for db_tick in s.iter() {
-            ticks.push_back(MixData::TickData(TickData {
-                symbol: db_tick.get::<String, usize>(0),
-                exchange: db_tick.get::<String, usize>(1),
-                datetime: db_tick
-                    .get::<NaiveDateTime, usize>(2)
-                    .and_local_timezone(SH_TZ)
-                    .unwrap()
-                    .with_timezone(&tz),
-                name: db_tick.get::<String, usize>(3).to_string().parse().unwrap(),
-                volume: db_tick.get::<f32, usize>(4).to_string().parse().unwrap(),
-                turnover: db_tick.get::<f32, usize>(5).to_string().parse().unwrap(),
-                open_interest: db_tick.get::<f32, usize>(6).to_string().parse().unwrap(),
-                last_price: db_tick.get::<f32, usize>(7).to_string().parse().unwrap(),
-                last_volume: db_tick.get::<f32, usize>(8).to_string().parse().unwrap(),
-                limit_up: db_tick.get::<f32, usize>(9).to_string().parse().unwrap(),
-                limit_down: db_tick.get::<f32, usize>(10).to_string().parse().unwrap(),
-                open_price: db_tick.get::<f32, usize>(11).to_string().parse().unwrap(),
-                high_price: db_tick.get::<f32, usize>(12).to_string().parse().unwrap(),
-                low_price: db_tick.get::<f32, usize>(13).to_string().parse().unwrap(),
-                pre_close: db_tick.get::<f32, usize>(14).to_string().parse().unwrap(),
-                bid_price_1: db_tick.get::<f32, usize>(15).to_string().parse().unwrap(),
-                bid_price_2: db_tick.get::<f32, usize>(16).to_string().parse().unwrap(),
-                bid_price_3: db_tick.get::<f32, usize>(17).to_string().parse().unwrap(),
-                bid_price_4: db_tick.get::<f32, usize>(18).to_string().parse().unwrap(),
-                bid_price_5: db_tick.get::<f32, usize>(19).to_string().parse().unwrap(),
-                ask_price_1: db_tick.get::<f32, usize>(20).to_string().parse().unwrap(),
-                ask_price_2: db_tick.get::<f32, usize>(21).to_string().parse().unwrap(),
-                ask_price_3: db_tick.get::<f32, usize>(22).to_string().parse().unwrap(),
-                ask_price_4: db_tick.get::<f32, usize>(23).to_string().parse().unwrap(),
-                ask_price_5: db_tick.get::<f32, usize>(24).to_string().parse().unwrap(),
-                bid_volume_1: db_tick.get::<f32, usize>(25).to_string().parse().unwrap(),
-                bid_volume_2: db_tick.get::<f32, usize>(26).to_string().parse().unwrap(),
-                bid_volume_3: db_tick.get::<f32, usize>(27).to_string().parse().unwrap(),
-                bid_volume_4: db_tick.get::<f32, usize>(28).to_string().parse().unwrap(),
-                bid_volume_5: db_tick.get::<f32, usize>(29).to_string().parse().unwrap(),
-                ask_volume_1: db_tick.get::<f32, usize>(30).to_string().parse().unwrap(),
-                ask_volume_2: db_tick.get::<f32, usize>(31).to_string().parse().unwrap(),
-                ask_volume_3: db_tick.get::<f32, usize>(32).to_string().parse().unwrap(),
-                ask_volume_4: db_tick.get::<f32, usize>(33).to_string().parse().unwrap(),
-                ask_volume_5: db_tick.get::<f32, usize>(34).to_string().parse().unwrap(),
-                localtime: db_tick.get::<NaiveDateTime, usize>(35),
-                gateway_name: "DB",
-            }));
-        }
-        ticks
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let (sql, params, positions) = SqlDialect::Postgres.select(
+            &self.schema,
+            &self.schema.tick_table,
+            SchemaMapping::tick_fields(),
+            &[("symbol", symbol), ("exchange", exchange)],
+            storage,
+            localize(self.tz, start),
+            localize(self.tz, end),
+        );
+        let rows = self
+            .rt
+            .block_on(bind_params(sqlx::query(&sql), params).fetch_all(&self.pool))
+            .map_err(|e| DatabaseError::sqlx("PostgreSQL", e))?;
+        ticks_from_rows(&rows, &positions, storage, symbol, exchange, self.tz)
     }
 }
 
 use mongodb::{
-    bson::{doc, Document},
+    bson::{doc, Bson, Document, RawBsonRef, RawDocument},
     Client, Collection,
 };
 pub struct MongodbDatabase {
+    tz: Tz,
+    schema: SchemaMapping,
     _client: Client,
     coll_bar_data: Collection<Document>,
     coll_tick_data: Collection<Document>,
@@ -421,29 +856,127 @@ pub struct MongodbDatabase {
 }
 
 impl MongodbDatabase {
-    pub fn connect(
-        url: &str,
-        _username: &str,
-        _password: &str,
-        database: &str,
-    ) -> Result<MongodbDatabase, Box<dyn std::error::Error>> {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
+    pub fn connect(url: &str, config: &DatabaseConfig) -> Result<MongodbDatabase, DatabaseError> {
+        let tz = config.tz()?;
+        // vnpy_mongodb writes naive local time as BSON dates, which are read as UTC here
+        let schema = config.schema(("bar_data", "tick_data"), DatetimeFormat::UTC)?;
+        let rt = database_runtime("MongoDB")?;
         let (_client, coll_bar_data, coll_tick_data) = rt.block_on(async {
-            let client = Client::with_uri_str(url).await.expect("Mongodb URL error");
-            let db = client.database(database);
-            let coll_bar_data = db.collection("bar_data");
-            let coll_tick_data = db.collection("tick_data");
-            (client, coll_bar_data, coll_tick_data)
-        });
+            let client = Client::with_uri_str(url).await?;
+            let db = client.database(&config.database);
+            let coll_bar_data = db.collection(&schema.bar_table);
+            let coll_tick_data = db.collection(&schema.tick_table);
+            Ok::<_, DatabaseError>((client, coll_bar_data, coll_tick_data))
+        })?;
         Ok(MongodbDatabase {
+            tz,
+            schema,
             _client,
             coll_bar_data,
             coll_tick_data,
             rt,
         })
     }
+
+    /// Filter on the fields that exist and the datetime range
+    fn filter(
+        &self,
+        filters: &[(&str, &str)],
+        storage: DatetimeStorage,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Document {
+        let mut filter = Document::new();
+        for (field, value) in filters {
+            if let Some(key) = self.schema.column(field) {
+                filter.insert(key, *value);
+            }
+        }
+        let bound = |datetime: NaiveDateTime| -> Bson {
+            match storage.param(localize(self.tz, datetime)) {
+                SqlParam::Datetime(datetime) => {
+                    bson::DateTime::from_millis(datetime.and_utc().timestamp_millis()).into()
+                }
+                SqlParam::Millis(millis) => millis.into(),
+                SqlParam::Text(text) => text.into(),
+            }
+        };
+        filter.insert(
+            self.schema.column("datetime").unwrap(),
+            doc! { "$gte": bound(start), "$lte": bound(end) },
+        );
+        filter
+    }
+
+    fn datetime_key(&self) -> &str {
+        self.schema.column("datetime").unwrap()
+    }
+
+    fn text(
+        &self,
+        document: &RawDocument,
+        field: &str,
+        default: &str,
+    ) -> Result<String, DatabaseError> {
+        match self.schema.column(field) {
+            Some(key) => bson_str(document, key),
+            None => Ok(default.to_string()),
+        }
+    }
+
+    fn values(&self, document: &RawDocument, fields: &[&str]) -> Result<Vec<f64>, DatabaseError> {
+        fields
+            .iter()
+            .map(|field| match self.schema.column(field) {
+                Some(key) => bson_f64(document, key),
+                None => Ok(0.0),
+            })
+            .collect()
+    }
+
+    fn datetime(
+        &self,
+        document: &RawDocument,
+        key: &str,
+        storage: DatetimeStorage,
+    ) -> Result<DateTime<Utc>, DatabaseError> {
+        let value = bson_raw(document, key)?;
+        let datetime = match storage.format {
+            DatetimeFormat::EPOCHMS => value
+                .as_i64()
+                .or(value.as_i32().map(i64::from))
+                .and_then(DateTime::from_timestamp_millis),
+            DatetimeFormat::UTC => value.as_datetime().map(|datetime| datetime.to_chrono()),
+            DatetimeFormat::NAIVE => value
+                .as_datetime()
+                .map(|datetime| storage.naive_to_utc(datetime.to_chrono().naive_utc())),
+        };
+        datetime.ok_or_else(|| DatabaseError::data("MongoDB", format!("{}字段不是时间", key)))
+    }
+}
+
+fn bson_raw<'a>(document: &'a RawDocument, key: &str) -> Result<RawBsonRef<'a>, DatabaseError> {
+    document
+        .get(key)
+        .map_err(|e| DatabaseError::data("MongoDB", e))?
+        .ok_or_else(|| DatabaseError::data("MongoDB", format!("数据缺少{}字段", key)))
+}
+
+fn bson_str(document: &RawDocument, key: &str) -> Result<String, DatabaseError> {
+    bson_raw(document, key)?
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| DatabaseError::data("MongoDB", format!("{}字段不是字符串", key)))
+}
+
+/// Numbers stored with other types are read as 0
+fn bson_f64(document: &RawDocument, key: &str) -> Result<f64, DatabaseError> {
+    let value = bson_raw(document, key)?;
+    Ok(value
+        .as_f64()
+        .or(value.as_i64().map(|value| value as f64))
+        .or(value.as_i32().map(f64::from))
+        .unwrap_or(0.0))
 }
 
 impl BaseDatabase for MongodbDatabase {
@@ -454,44 +987,43 @@ impl BaseDatabase for MongodbDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let start_ = bson::DateTime::from_millis(
-            start.and_local_timezone(SH_TZ).unwrap().timestamp_millis(),
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let interval_str = interval.to_string();
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let filter = self.filter(
+            &[
+                ("symbol", symbol),
+                ("exchange", exchange),
+                ("interval", &interval_str),
+            ],
+            storage,
+            start,
+            end,
         );
-        let end_ =
-            bson::DateTime::from_millis(end.and_local_timezone(SH_TZ).unwrap().timestamp_millis());
-        let tz = DB_TZ.clone();
+        let tz = self.tz;
         self.rt.block_on(async {
             let mut bars: LinkedList<MixData> = LinkedList::new();
             let mut cursor = self
                 .coll_bar_data
-                .find(doc! {"symbol":symbol,"exchange":exchange,"interval":interval.to_string(),"datetime": doc! { "$gte": start_,"$lte":end_ }})
-                .sort(doc! {"datetime":1})
-                .await
-                .unwrap();
-            while cursor.advance().await.unwrap() {
+                .find(filter)
+                .sort(doc! {self.datetime_key():1})
+                .await?;
+            while cursor.advance().await? {
                 let current = cursor.current();
-                bars.push_back(MixData::BarData(BarData{
-                    symbol: current.get_str("symbol").unwrap().to_string(),
-                    exchange: current.get_str("exchange").unwrap().to_string(),
-                    datetime: current
-                    .get_datetime("datetime")
-                    .unwrap()
-                    .to_chrono()
-                    .with_timezone(&tz),
-                    interval: Interval::from_str(current.get_str("interval").unwrap())
-                        .expect("数据库中interval字段只能是1m,1h,d,w,tick中的一个"),
-                    volume: current.get("volume").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    turnover: current.get("turnover").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    open_interest: current.get("open_interest").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    open_price: current.get("open_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    high_price: current.get("high_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    low_price: current.get("low_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    close_price: current.get("close_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    gateway_name: "DB",
-                }));
+                let interval = match self.schema.column("interval") {
+                    Some(key) => parse_interval("MongoDB", &bson_str(current, key)?)?,
+                    None => interval,
+                };
+                bars.push_back(MixData::BarData(bar_from_values(
+                    self.text(current, "symbol", symbol)?,
+                    self.text(current, "exchange", exchange)?,
+                    self.datetime(current, self.datetime_key(), storage)?
+                        .with_timezone(&tz),
+                    interval,
+                    &self.values(current, &BAR_VALUE_COLUMNS)?,
+                )));
             }
-            bars
+            Ok(bars)
         })
     }
 
@@ -501,71 +1033,955 @@ impl BaseDatabase for MongodbDatabase {
         exchange: &str,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> LinkedList<MixData> {
-        let start_ = bson::DateTime::from_millis(
-            start.and_local_timezone(SH_TZ).unwrap().timestamp_millis(),
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let storage = DatetimeStorage::new(&self.schema, symbol, exchange);
+        let filter = self.filter(
+            &[("symbol", symbol), ("exchange", exchange)],
+            storage,
+            start,
+            end,
         );
-        let end_ =
-            bson::DateTime::from_millis(end.and_local_timezone(SH_TZ).unwrap().timestamp_millis());
-        let tz = DB_TZ.clone();
+        let tz = self.tz;
         self.rt.block_on(async {
             let mut ticks: LinkedList<MixData> = LinkedList::new();
             let mut cursor = self
                 .coll_tick_data
-                .find(doc! {"symbol":symbol,"exchange":exchange,"datetime": doc! { "$gte": start_,"$lte":end_ }})
-                .sort(doc! {"datetime":1})
-                .await
-                .unwrap();
-            while cursor.advance().await.unwrap() {
+                .find(filter)
+                .sort(doc! {self.datetime_key():1})
+                .await?;
+            while cursor.advance().await? {
                 let current = cursor.current();
-                ticks.push_back(MixData::TickData(TickData{
-                    symbol: current.get_str("symbol").unwrap().to_string(),
-                    exchange: current.get_str("exchange").unwrap().to_string(),
-                    datetime: current
-                    .get_datetime("datetime")
-                    .unwrap()
-                    .to_chrono()
-                    .with_timezone(&tz),
-                    name: current.get_str("name").unwrap().to_string(),
-                    volume: current.get("volume").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    turnover: current.get("turnover").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    open_interest: current.get("open_interest").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    last_price: current.get("last_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    last_volume: current.get("last_volume").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    limit_up: current.get("limit_up").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    limit_down: current.get("limit_down").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    open_price: current.get("open_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    high_price: current.get("high_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    low_price: current.get("low_price").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    pre_close: current.get("pre_close").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_price_1: current.get("bid_price_1").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_price_2: current.get("bid_price_2").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_price_3: current.get("bid_price_3").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_price_4: current.get("bid_price_4").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_price_5: current.get("bid_price_5").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_price_1: current.get("ask_price_1").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_price_2: current.get("ask_price_2").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_price_3: current.get("ask_price_3").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_price_4: current.get("ask_price_4").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_price_5: current.get("ask_price_5").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_volume_1: current.get("bid_volume_1").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_volume_2: current.get("bid_volume_2").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_volume_3: current.get("bid_volume_3").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_volume_4: current.get("bid_volume_4").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    bid_volume_5: current.get("bid_volume_5").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_volume_1: current.get("ask_volume_1").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_volume_2: current.get("ask_volume_2").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_volume_3: current.get("ask_volume_3").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_volume_4: current.get("ask_volume_4").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    ask_volume_5: current.get("ask_volume_5").unwrap().unwrap().as_f64().or(Some(0.0)).unwrap(),
-                    localtime: current
-                    .get_datetime("localtime")
-                    .unwrap_or(bson::DateTime::from_millis(0))
-                    .to_chrono().naive_local(),
-                    gateway_name: "DB",
-                    }));
+                let datetime = self
+                    .datetime(current, self.datetime_key(), storage)?
+                    .with_timezone(&tz);
+                let localtime = match self.schema.column("localtime") {
+                    Some(key) => current
+                        .get_datetime(key)
+                        .unwrap_or(bson::DateTime::from_millis(0))
+                        .to_chrono()
+                        .naive_local(),
+                    None => datetime.naive_local(),
+                };
+                ticks.push_back(MixData::TickData(tick_from_values(
+                    self.text(current, "symbol", symbol)?,
+                    self.text(current, "exchange", exchange)?,
+                    datetime,
+                    self.text(current, "name", "")?,
+                    &self.values(current, &TICK_VALUE_COLUMNS)?,
+                    localtime,
+                )));
             }
-            ticks
+            Ok(ticks)
+        })
+    }
+}
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, TimestampMillisecondType},
+    ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::Datelike;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::Compression,
+    file::{properties::WriterProperties, statistics::Statistics},
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const PARQUET_ROW_GROUP_SIZE: usize = 10_000;
+
+/// Database made of parquet files, laid out as
+/// `{root}/{symbol}/{exchange}/{interval}/{year}.parquet`.
+/// Tick data is stored under the `tick` interval.
+pub struct ParquetDatabase {
+    root: PathBuf,
+    tz: Tz,
+}
+
+impl ParquetDatabase {
+    pub fn connect(root: &str, tz: Tz) -> Result<ParquetDatabase, DatabaseError> {
+        std::fs::create_dir_all(root)
+            .map_err(|e| DatabaseError::connection("Parquet", format!("{}：{}", root, e)))?;
+        Ok(ParquetDatabase {
+            root: PathBuf::from(root),
+            tz,
         })
     }
+
+    fn partition_dir(&self, symbol: &str, exchange: &str, interval: Interval) -> PathBuf {
+        self.root
+            .join(symbol)
+            .join(exchange)
+            .join(interval.to_string())
+    }
+
+    fn year_files(
+        &self,
+        symbol: &str,
+        exchange: &str,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Vec<PathBuf> {
+        let dir = self.partition_dir(symbol, exchange, interval);
+        // Files are split by the UTC year, which may differ from the local one near New Year
+        (start.year() - 1..=end.year() + 1)
+            .map(|year| dir.join(format!("{}.parquet", year)))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    fn write_bar_data(&self, bars: &[BarData]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut partitions: HashMap<(String, String, Interval, i32), Vec<&BarData>> =
+            HashMap::new();
+        for bar in bars {
+            let year = bar.datetime.with_timezone(&Utc).year();
+            partitions
+                .entry((bar.symbol.clone(), bar.exchange.clone(), bar.interval, year))
+                .or_default()
+                .push(bar);
+        }
+
+        for ((symbol, exchange, interval, year), new_bars) in partitions {
+            let dir = self.partition_dir(&symbol, &exchange, interval);
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{}.parquet", year));
+
+            // Merge with the bars already on disk, new bars overwrite old ones
+            let mut merged: BTreeMap<i64, BarData> = BTreeMap::new();
+            if path.exists() {
+                for batch in read_batches(&path, None)? {
+                    for bar in bars_from_batch(&batch, &symbol, &exchange, interval, self.tz)? {
+                        merged.insert(bar.datetime.timestamp_millis(), bar);
+                    }
+                }
+            }
+            for bar in new_bars {
+                merged.insert(bar.datetime.timestamp_millis(), bar.clone());
+            }
+
+            let merged: Vec<BarData> = merged.into_values().collect();
+            let datetime: ArrayRef = Arc::new(
+                TimestampMillisecondArray::from(
+                    merged
+                        .iter()
+                        .map(|bar| bar.datetime.timestamp_millis())
+                        .collect::<Vec<i64>>(),
+                )
+                .with_timezone("UTC"),
+            );
+            let mut columns = vec![datetime];
+            let getters: [fn(&BarData) -> f64; 7] = [
+                |bar| bar.volume,
+                |bar| bar.turnover,
+                |bar| bar.open_interest,
+                |bar| bar.open_price,
+                |bar| bar.high_price,
+                |bar| bar.low_price,
+                |bar| bar.close_price,
+            ];
+            for getter in getters {
+                columns.push(Arc::new(Float64Array::from(
+                    merged.iter().map(getter).collect::<Vec<f64>>(),
+                )));
+            }
+            let batch = RecordBatch::try_new(bar_schema(), columns)?;
+            write_batch(&path, &batch)?;
+        }
+        Ok(())
+    }
+
+    fn write_tick_data(&self, ticks: &[TickData]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut partitions: HashMap<(String, String, i32), Vec<&TickData>> = HashMap::new();
+        for tick in ticks {
+            let year = tick.datetime.with_timezone(&Utc).year();
+            partitions
+                .entry((tick.symbol.clone(), tick.exchange.clone(), year))
+                .or_default()
+                .push(tick);
+        }
+
+        for ((symbol, exchange, year), new_ticks) in partitions {
+            let dir = self.partition_dir(&symbol, &exchange, Interval::TICK);
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{}.parquet", year));
+
+            // Merge with the ticks already on disk, new ticks overwrite old ones
+            let mut merged: BTreeMap<i64, TickData> = BTreeMap::new();
+            if path.exists() {
+                for batch in read_batches(&path, None)? {
+                    for tick in ticks_from_batch(&batch, &symbol, &exchange, self.tz)? {
+                        merged.insert(tick.datetime.timestamp_millis(), tick);
+                    }
+                }
+            }
+            for tick in new_ticks {
+                merged.insert(tick.datetime.timestamp_millis(), tick.clone());
+            }
+
+            let merged: Vec<TickData> = merged.into_values().collect();
+            let datetime: ArrayRef = Arc::new(
+                TimestampMillisecondArray::from(
+                    merged
+                        .iter()
+                        .map(|tick| tick.datetime.timestamp_millis())
+                        .collect::<Vec<i64>>(),
+                )
+                .with_timezone("UTC"),
+            );
+            let name: ArrayRef = Arc::new(StringArray::from(
+                merged
+                    .iter()
+                    .map(|tick| tick.name.as_str())
+                    .collect::<Vec<&str>>(),
+            ));
+            let mut columns = vec![datetime, name];
+            let getters: [fn(&TickData) -> f64; 31] = [
+                |tick| tick.volume,
+                |tick| tick.turnover,
+                |tick| tick.open_interest,
+                |tick| tick.last_price,
+                |tick| tick.last_volume,
+                |tick| tick.limit_up,
+                |tick| tick.limit_down,
+                |tick| tick.open_price,
+                |tick| tick.high_price,
+                |tick| tick.low_price,
+                |tick| tick.pre_close,
+                |tick| tick.bid_price_1,
+                |tick| tick.bid_price_2,
+                |tick| tick.bid_price_3,
+                |tick| tick.bid_price_4,
+                |tick| tick.bid_price_5,
+                |tick| tick.ask_price_1,
+                |tick| tick.ask_price_2,
+                |tick| tick.ask_price_3,
+                |tick| tick.ask_price_4,
+                |tick| tick.ask_price_5,
+                |tick| tick.bid_volume_1,
+                |tick| tick.bid_volume_2,
+                |tick| tick.bid_volume_3,
+                |tick| tick.bid_volume_4,
+                |tick| tick.bid_volume_5,
+                |tick| tick.ask_volume_1,
+                |tick| tick.ask_volume_2,
+                |tick| tick.ask_volume_3,
+                |tick| tick.ask_volume_4,
+                |tick| tick.ask_volume_5,
+            ];
+            for getter in getters {
+                columns.push(Arc::new(Float64Array::from(
+                    merged.iter().map(getter).collect::<Vec<f64>>(),
+                )));
+            }
+            columns.push(Arc::new(TimestampMillisecondArray::from(
+                merged
+                    .iter()
+                    .map(|tick| tick.localtime.and_utc().timestamp_millis())
+                    .collect::<Vec<i64>>(),
+            )));
+            let batch = RecordBatch::try_new(tick_schema(), columns)?;
+            write_batch(&path, &batch)?;
+        }
+        Ok(())
+    }
+}
+
+impl BaseDatabase for ParquetDatabase {
+    fn load_bar_data(
+        &self,
+        symbol: &str,
+        exchange: &str,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let start_ = localize(self.tz, start).timestamp_millis();
+        let end_ = localize(self.tz, end).timestamp_millis();
+        let mut bars = LinkedList::new();
+        for path in self.year_files(symbol, exchange, interval, start, end) {
+            let batches = read_batches(&path, Some((start_, end_))).map_err(|e| {
+                DatabaseError::data("Parquet", format!("{}：{}", path.display(), e))
+            })?;
+            for batch in batches {
+                for bar in bars_from_batch(&batch, symbol, exchange, interval, self.tz)? {
+                    let timestamp = bar.datetime.timestamp_millis();
+                    if timestamp >= start_ && timestamp <= end_ {
+                        bars.push_back(MixData::BarData(bar));
+                    }
+                }
+            }
+        }
+        Ok(bars)
+    }
+
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let start_ = localize(self.tz, start).timestamp_millis();
+        let end_ = localize(self.tz, end).timestamp_millis();
+        let mut ticks = LinkedList::new();
+        for path in self.year_files(symbol, exchange, Interval::TICK, start, end) {
+            let batches = read_batches(&path, Some((start_, end_))).map_err(|e| {
+                DatabaseError::data("Parquet", format!("{}：{}", path.display(), e))
+            })?;
+            for batch in batches {
+                for tick in ticks_from_batch(&batch, symbol, exchange, self.tz)? {
+                    let timestamp = tick.datetime.timestamp_millis();
+                    if timestamp >= start_ && timestamp <= end_ {
+                        ticks.push_back(MixData::TickData(tick));
+                    }
+                }
+            }
+        }
+        Ok(ticks)
+    }
+
+    fn save_bar_data(&self, bars: Vec<BarData>) -> Result<(), DatabaseError> {
+        self.write_bar_data(&bars)
+            .map_err(|e| DatabaseError::data("Parquet", format!("写入K线数据失败：{}", e)))
+    }
+
+    fn save_tick_data(&self, ticks: Vec<TickData>) -> Result<(), DatabaseError> {
+        self.write_tick_data(&ticks)
+            .map_err(|e| DatabaseError::data("Parquet", format!("写入Tick数据失败：{}", e)))
+    }
+}
+
+fn bar_schema() -> Arc<Schema> {
+    let mut fields = vec![Field::new(
+        "datetime",
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        false,
+    )];
+    for name in BAR_VALUE_COLUMNS {
+        fields.push(Field::new(name, DataType::Float64, false));
+    }
+    Arc::new(Schema::new(fields))
+}
+
+fn tick_schema() -> Arc<Schema> {
+    let mut fields = vec![
+        Field::new(
+            "datetime",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("name", DataType::Utf8, false),
+    ];
+    for name in TICK_VALUE_COLUMNS {
+        fields.push(Field::new(name, DataType::Float64, false));
+    }
+    fields.push(Field::new(
+        "localtime",
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        false,
+    ));
+    Arc::new(Schema::new(fields))
+}
+
+pub(crate) fn write_batch(
+    path: &Path,
+    batch: &RecordBatch,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(PARQUET_ROW_GROUP_SIZE)
+        .build();
+
+    // Write to a temporary file first so that a failed write never corrupts existing data
+    let tmp_path = path.with_extension("parquet.tmp");
+    let file = std::fs::File::create(&tmp_path)?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props))?;
+    writer.write(batch)?;
+    writer.close()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Read record batches from a parquet file. When a datetime range is given,
+/// row groups whose datetime statistics fall outside of it are skipped.
+pub(crate) fn read_batches(
+    path: &Path,
+    range: Option<(i64, i64)>,
+) -> Result<Vec<RecordBatch>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    let mut builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+
+    if let Some((start, end)) = range {
+        let metadata = builder.metadata().clone();
+        let column_index = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .position(|column| column.name() == "datetime")
+            .ok_or("Parquet文件缺少datetime列")?;
+        let row_groups: Vec<usize> = metadata
+            .row_groups()
+            .iter()
+            .enumerate()
+            .filter(
+                |(_, row_group)| match row_group.column(column_index).statistics() {
+                    Some(Statistics::Int64(stats)) => match (stats.min_opt(), stats.max_opt()) {
+                        (Some(min), Some(max)) => *max >= start && *min <= end,
+                        _ => true,
+                    },
+                    _ => true,
+                },
+            )
+            .map(|(i, _)| i)
+            .collect();
+        builder = builder.with_row_groups(row_groups);
+    }
+
+    let mut batches = Vec::new();
+    for batch in builder.build()? {
+        batches.push(batch?);
+    }
+    Ok(batches)
+}
+
+fn datetime_from_millis(millis: i64, name: &str) -> Result<DateTime<Utc>, DatabaseError> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| DatabaseError::data("Parquet", format!("{}字段超出范围", name)))
+}
+
+fn parquet_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a ArrayRef, DatabaseError> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| DatabaseError::data("Parquet", format!("Parquet文件缺少{}列", name)))
+}
+
+fn column_type_error(name: &str) -> DatabaseError {
+    DatabaseError::data("Parquet", format!("Parquet文件中{}列类型错误", name))
+}
+
+fn timestamp_column<'a>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> Result<&'a TimestampMillisecondArray, DatabaseError> {
+    parquet_column(batch, name)?
+        .as_primitive_opt::<TimestampMillisecondType>()
+        .ok_or_else(|| column_type_error(name))
+}
+
+fn float_columns<'a>(
+    batch: &'a RecordBatch,
+    names: &[&str],
+) -> Result<Vec<&'a Float64Array>, DatabaseError> {
+    names
+        .iter()
+        .map(|name| {
+            parquet_column(batch, name)?
+                .as_primitive_opt::<Float64Type>()
+                .ok_or_else(|| column_type_error(name))
+        })
+        .collect()
+}
+
+fn bars_from_batch(
+    batch: &RecordBatch,
+    symbol: &str,
+    exchange: &str,
+    interval: Interval,
+    tz: Tz,
+) -> Result<Vec<BarData>, DatabaseError> {
+    let datetime = timestamp_column(batch, "datetime")?;
+    let columns = float_columns(batch, &BAR_VALUE_COLUMNS)?;
+
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(BarData {
+                symbol: symbol.to_string(),
+                exchange: exchange.to_string(),
+                datetime: datetime_from_millis(datetime.value(i), "datetime")?.with_timezone(&tz),
+                interval,
+                volume: columns[0].value(i),
+                turnover: columns[1].value(i),
+                open_interest: columns[2].value(i),
+                open_price: columns[3].value(i),
+                high_price: columns[4].value(i),
+                low_price: columns[5].value(i),
+                close_price: columns[6].value(i),
+                gateway_name: "DB",
+            })
+        })
+        .collect()
+}
+
+fn ticks_from_batch(
+    batch: &RecordBatch,
+    symbol: &str,
+    exchange: &str,
+    tz: Tz,
+) -> Result<Vec<TickData>, DatabaseError> {
+    let datetime = timestamp_column(batch, "datetime")?;
+    let name = parquet_column(batch, "name")?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| column_type_error("name"))?;
+    let localtime = timestamp_column(batch, "localtime")?;
+    let columns = float_columns(batch, &TICK_VALUE_COLUMNS)?;
+
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(TickData {
+                symbol: symbol.to_string(),
+                exchange: exchange.to_string(),
+                datetime: datetime_from_millis(datetime.value(i), "datetime")?.with_timezone(&tz),
+                name: name.value(i).to_string(),
+                volume: columns[0].value(i),
+                turnover: columns[1].value(i),
+                open_interest: columns[2].value(i),
+                last_price: columns[3].value(i),
+                last_volume: columns[4].value(i),
+                limit_up: columns[5].value(i),
+                limit_down: columns[6].value(i),
+                open_price: columns[7].value(i),
+                high_price: columns[8].value(i),
+                low_price: columns[9].value(i),
+                pre_close: columns[10].value(i),
+                bid_price_1: columns[11].value(i),
+                bid_price_2: columns[12].value(i),
+                bid_price_3: columns[13].value(i),
+                bid_price_4: columns[14].value(i),
+                bid_price_5: columns[15].value(i),
+                ask_price_1: columns[16].value(i),
+                ask_price_2: columns[17].value(i),
+                ask_price_3: columns[18].value(i),
+                ask_price_4: columns[19].value(i),
+                ask_price_5: columns[20].value(i),
+                bid_volume_1: columns[21].value(i),
+                bid_volume_2: columns[22].value(i),
+                bid_volume_3: columns[23].value(i),
+                bid_volume_4: columns[24].value(i),
+                bid_volume_5: columns[25].value(i),
+                ask_volume_1: columns[26].value(i),
+                ask_volume_2: columns[27].value(i),
+                ask_volume_3: columns[28].value(i),
+                ask_volume_4: columns[29].value(i),
+                ask_volume_5: columns[30].value(i),
+                localtime: datetime_from_millis(localtime.value(i), "localtime")?.naive_utc(),
+                gateway_name: "DB",
+            })
+        })
+        .collect()
+}
+
+use pyo3::{prelude::*, types::PyString};
+
+use super::database::{db_tz, DataError};
+use super::object::{extract_datetime, extract_exchange, extract_interval};
+
+type BarKey = (String, String, Interval);
+type TickKey = (String, String);
+
+/// Database kept in memory, filled directly from Python.
+/// Mainly used by strategy unit tests and notebooks, cloned handles share the same data.
+#[pyclass]
+#[derive(Clone)]
+pub struct MemoryDatabase {
+    bars: Arc<Mutex<HashMap<BarKey, BTreeMap<i64, BarData>>>>,
+    ticks: Arc<Mutex<HashMap<TickKey, BTreeMap<i64, TickData>>>>,
+    /// Timezone the start and end of loads are given in
+    tz: Tz,
+}
+
+impl MemoryDatabase {
+    pub fn save_bar_data(&self, bars: Vec<BarData>) {
+        let mut map = self.bars.lock().unwrap();
+        for bar in bars {
+            map.entry((bar.symbol.clone(), bar.exchange.clone(), bar.interval))
+                .or_default()
+                .insert(bar.datetime.timestamp_millis(), bar);
+        }
+    }
+
+    /// Keys of the data from start to end, both local times of the database timezone
+    fn millis_range(&self, start: NaiveDateTime, end: NaiveDateTime) -> RangeInclusive<i64> {
+        localize(self.tz, start).timestamp_millis()..=localize(self.tz, end).timestamp_millis()
+    }
+
+    pub fn save_tick_data(&self, ticks: Vec<TickData>) {
+        let mut map = self.ticks.lock().unwrap();
+        for tick in ticks {
+            map.entry((tick.symbol.clone(), tick.exchange.clone()))
+                .or_default()
+                .insert(tick.datetime.timestamp_millis(), tick);
+        }
+    }
+}
+
+#[pymethods]
+impl MemoryDatabase {
+    /// timezone defaults to database.timezone of vt_setting.json
+    #[new]
+    #[pyo3(signature = (timezone=None))]
+    pub fn __new__(timezone: Option<&str>) -> PyResult<Self> {
+        let tz = match timezone {
+            Some(timezone) => timezone
+                .parse()
+                .map_err(|e: chrono_tz::ParseError| SettingError::invalid("timezone", e))?,
+            None => db_tz()?,
+        };
+        Ok(MemoryDatabase {
+            bars: Default::default(),
+            ticks: Default::default(),
+            tz,
+        })
+    }
+
+    /// Add a list of BarData (vnpyrs or vnpy) into the database.
+    pub fn add_bar_data(&self, bars: Bound<'_, PyAny>) -> PyResult<()> {
+        let mut list = Vec::new();
+        for bar in bars.try_iter()? {
+            list.push(BarData::from_pyobject(&bar?)?);
+        }
+        self.save_bar_data(list);
+        Ok(())
+    }
+
+    /// Add a list of TickData (vnpyrs or vnpy) into the database.
+    pub fn add_tick_data(&self, ticks: Bound<'_, PyAny>) -> PyResult<()> {
+        let mut list = Vec::new();
+        for tick in ticks.try_iter()? {
+            list.push(TickData::from_pyobject(&tick?)?);
+        }
+        self.save_tick_data(list);
+        Ok(())
+    }
+
+    /// Add bars from a DataFrame whose columns are named after the BarData fields,
+    /// datetime can be either a column or the index.
+    pub fn add_bar_dataframe(
+        &self,
+        df: Bound<'_, PyAny>,
+        symbol: &str,
+        exchange: Bound<'_, PyAny>,
+        interval: Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        let exchange = extract_exchange(&exchange)?;
+        let interval = extract_interval(&interval)?;
+        let df = DataFrameColumns::new(df)?;
+        let datetime = df.datetime()?;
+
+        let open_price = df.required("open_price")?;
+        let high_price = df.required("high_price")?;
+        let low_price = df.required("low_price")?;
+        let close_price = df.required("close_price")?;
+        let volume = df.optional("volume", datetime.len())?;
+        let turnover = df.optional("turnover", datetime.len())?;
+        let open_interest = df.optional("open_interest", datetime.len())?;
+
+        let bars = (0..datetime.len())
+            .map(|i| BarData {
+                gateway_name: "DB",
+                symbol: symbol.to_string(),
+                exchange: exchange.clone(),
+                datetime: datetime[i],
+                interval,
+                volume: volume[i],
+                turnover: turnover[i],
+                open_interest: open_interest[i],
+                open_price: open_price[i],
+                high_price: high_price[i],
+                low_price: low_price[i],
+                close_price: close_price[i],
+            })
+            .collect();
+        self.save_bar_data(bars);
+        Ok(())
+    }
+
+    /// Add ticks from a DataFrame whose columns are named after the TickData fields,
+    /// datetime can be either a column or the index. Only last_price is required.
+    pub fn add_tick_dataframe(
+        &self,
+        df: Bound<'_, PyAny>,
+        symbol: &str,
+        exchange: Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        let exchange = extract_exchange(&exchange)?;
+        let df = DataFrameColumns::new(df)?;
+        let datetime = df.datetime()?;
+        let n = datetime.len();
+
+        let last_price = df.required("last_price")?;
+        let name: Vec<String> = if df.contains("name")? {
+            df.column("name")?
+        } else {
+            vec![String::new(); n]
+        };
+        let mut values = Vec::new();
+        for column in TICK_VALUE_COLUMNS {
+            if column == "last_price" {
+                values.push(last_price.clone());
+            } else {
+                values.push(df.optional(column, n)?);
+            }
+        }
+
+        let ticks = (0..n)
+            .map(|i| TickData {
+                gateway_name: "DB",
+                symbol: symbol.to_string(),
+                exchange: exchange.clone(),
+                datetime: datetime[i],
+                name: name[i].clone(),
+                volume: values[0][i],
+                turnover: values[1][i],
+                open_interest: values[2][i],
+                last_price: values[3][i],
+                last_volume: values[4][i],
+                limit_up: values[5][i],
+                limit_down: values[6][i],
+                open_price: values[7][i],
+                high_price: values[8][i],
+                low_price: values[9][i],
+                pre_close: values[10][i],
+                bid_price_1: values[11][i],
+                bid_price_2: values[12][i],
+                bid_price_3: values[13][i],
+                bid_price_4: values[14][i],
+                bid_price_5: values[15][i],
+                ask_price_1: values[16][i],
+                ask_price_2: values[17][i],
+                ask_price_3: values[18][i],
+                ask_price_4: values[19][i],
+                ask_price_5: values[20][i],
+                bid_volume_1: values[21][i],
+                bid_volume_2: values[22][i],
+                bid_volume_3: values[23][i],
+                bid_volume_4: values[24][i],
+                bid_volume_5: values[25][i],
+                ask_volume_1: values[26][i],
+                ask_volume_2: values[27][i],
+                ask_volume_3: values[28][i],
+                ask_volume_4: values[29][i],
+                ask_volume_5: values[30][i],
+                localtime: datetime[i].naive_local(),
+            })
+            .collect();
+        self.save_tick_data(ticks);
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.bars.lock().unwrap().clear();
+        self.ticks.lock().unwrap().clear();
+    }
+
+    pub fn bar_count(&self) -> usize {
+        self.bars.lock().unwrap().values().map(|v| v.len()).sum()
+    }
+
+    pub fn tick_count(&self) -> usize {
+        self.ticks.lock().unwrap().values().map(|v| v.len()).sum()
+    }
+}
+
+impl BaseDatabase for MemoryDatabase {
+    fn load_bar_data(
+        &self,
+        symbol: &str,
+        exchange: &str,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let key = (symbol.to_string(), exchange.to_string(), interval);
+        let mut bars = LinkedList::new();
+        if let Some(map) = self.bars.lock().unwrap().get(&key) {
+            // Returned in the database timezone, like the other backends do
+            for bar in map.range(self.millis_range(start, end)).map(|(_, bar)| bar) {
+                bars.push_back(MixData::BarData(BarData {
+                    datetime: bar.datetime.with_timezone(&self.tz),
+                    ..bar.clone()
+                }));
+            }
+        }
+        Ok(bars)
+    }
+
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<LinkedList<MixData>, DatabaseError> {
+        let key = (symbol.to_string(), exchange.to_string());
+        let mut ticks = LinkedList::new();
+        if let Some(map) = self.ticks.lock().unwrap().get(&key) {
+            for tick in map
+                .range(self.millis_range(start, end))
+                .map(|(_, tick)| tick)
+            {
+                ticks.push_back(MixData::TickData(TickData {
+                    datetime: tick.datetime.with_timezone(&self.tz),
+                    ..tick.clone()
+                }));
+            }
+        }
+        Ok(ticks)
+    }
+
+    fn save_bar_data(&self, bars: Vec<BarData>) -> Result<(), DatabaseError> {
+        self.save_bar_data(bars);
+        Ok(())
+    }
+
+    fn save_tick_data(&self, ticks: Vec<TickData>) -> Result<(), DatabaseError> {
+        self.save_tick_data(ticks);
+        Ok(())
+    }
+}
+
+/// Column access to a pandas DataFrame.
+struct DataFrameColumns<'py> {
+    df: Bound<'py, PyAny>,
+}
+
+impl<'py> DataFrameColumns<'py> {
+    fn new(df: Bound<'py, PyAny>) -> PyResult<Self> {
+        let mut columns = DataFrameColumns { df };
+        if !columns.contains("datetime")? {
+            // An unnamed index of datetimes is taken as the datetime column
+            let index = columns.df.getattr("index")?;
+            let inferred_type: String = index.getattr("inferred_type")?.extract()?;
+            if index.getattr("name")?.is_none() && inferred_type.starts_with("datetime") {
+                columns.df = columns.df.call_method1("rename_axis", ("datetime",))?;
+            }
+            columns.df = columns.df.call_method0("reset_index")?;
+        }
+        Ok(columns)
+    }
+
+    fn contains(&self, name: &str) -> PyResult<bool> {
+        self.df
+            .getattr("columns")?
+            .contains(PyString::new(self.df.py(), name))
+    }
+
+    fn column<T: for<'a> FromPyObject<'a>>(&self, name: &str) -> PyResult<T> {
+        self.df.get_item(name)?.call_method0("tolist")?.extract()
+    }
+
+    fn required(&self, name: &str) -> PyResult<Vec<f64>> {
+        if !self.contains(name)? {
+            return Err(DataError::new_err(format!("DataFrame缺少{}列", name)));
+        }
+        self.column(name)
+    }
+
+    fn optional(&self, name: &str, len: usize) -> PyResult<Vec<f64>> {
+        if self.contains(name)? {
+            self.column(name)
+        } else {
+            Ok(vec![0.0; len])
+        }
+    }
+
+    fn datetime(&self) -> PyResult<Vec<DateTime<Tz>>> {
+        if !self.contains("datetime")? {
+            return Err(DataError::new_err(
+                "DataFrame缺少datetime列，也没有时间类型的索引",
+            ));
+        }
+        let mut list = Vec::new();
+        for item in self
+            .df
+            .get_item("datetime")?
+            .call_method0("tolist")?
+            .try_iter()?
+        {
+            list.push(extract_datetime(&item?)?);
+        }
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(tz: Tz, datetime: &str, close_price: f64) -> BarData {
+        BarData {
+            gateway_name: "DB",
+            symbol: "ES".to_string(),
+            exchange: "CME".to_string(),
+            datetime: localize(tz, datetime.parse().unwrap()),
+            interval: Interval::HOUR,
+            volume: 0.0,
+            turnover: 0.0,
+            open_interest: 0.0,
+            open_price: close_price,
+            high_price: close_price,
+            low_price: close_price,
+            close_price,
+        }
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let tz = Tz::America__New_York;
+        let root = std::env::temp_dir().join(format!("vnpyrs_parquet_{}", std::process::id()));
+        let database = ParquetDatabase::connect(&root.to_string_lossy(), tz).unwrap();
+
+        // Around New Year in UTC, when it is still 2023 in New York
+        let bars = vec![
+            bar(tz, "2023-12-31T18:00:00", 1.0),
+            bar(tz, "2023-12-31T19:00:00", 2.0),
+            bar(tz, "2024-01-01T00:00:00", 3.0),
+        ];
+        database.save_bar_data(bars.clone()).unwrap();
+        // Saving again replaces the bar at the same datetime
+        database
+            .save_bar_data(vec![bar(tz, "2024-01-01T00:00:00", 4.0)])
+            .unwrap();
+
+        let load = |start: &str, end: &str| -> Vec<(DateTime<Tz>, f64)> {
+            database
+                .load_bar_data(
+                    "ES",
+                    "CME",
+                    Interval::HOUR,
+                    start.parse().unwrap(),
+                    end.parse().unwrap(),
+                )
+                .unwrap()
+                .into_iter()
+                .map(|data| match data {
+                    MixData::BarData(bar) => (bar.datetime, bar.close_price),
+                    MixData::TickData(_) => panic!("tick in bar data"),
+                })
+                .collect()
+        };
+        assert_eq!(
+            load("2023-12-31T17:00:00", "2023-12-31T19:00:00"),
+            vec![(bars[0].datetime, 1.0), (bars[1].datetime, 2.0)]
+        );
+        assert_eq!(
+            load("2023-12-31T19:00:00", "2024-01-01T01:00:00"),
+            vec![(bars[1].datetime, 2.0), (bars[2].datetime, 4.0)]
+        );
+        let rows = |year: i32| -> usize {
+            read_batches(&root.join(format!("ES/CME/1h/{}.parquet", year)), None)
+                .unwrap()
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum()
+        };
+        assert_eq!(rows(2023), 1);
+        assert_eq!(rows(2024), 2);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
@@ -1,13 +1,14 @@
 /*！Basic data structure used for general trading function in the trading platform.*/
 
-use pyo3::prelude::*;
-use std::{collections::HashSet, sync::LazyLock};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{collections::HashSet, str::FromStr, sync::LazyLock};
 
 use super::{
     constant::{Direction, Interval, Offset_, OrderType, Status},
-    database::DB_TZ,
+    database::db_tz,
+    utility::localize,
 };
-use chrono::{DateTime, NaiveDateTime};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use chrono_tz::Tz;
 
 pub static ACTIVE_STATUSES: LazyLock<HashSet<Status>> = LazyLock::new(|| {
@@ -100,9 +101,7 @@ impl Default for BarData {
             gateway_name: Default::default(),
             symbol: Default::default(),
             exchange: Default::default(),
-            datetime: NaiveDateTime::default()
-                .and_local_timezone(DB_TZ.clone())
-                .unwrap(),
+            datetime: localize(db_tz().unwrap_or(Tz::UTC), NaiveDateTime::default()),
             interval: Interval::MINUTE,
             volume: Default::default(),
             turnover: Default::default(),
@@ -123,6 +122,116 @@ impl BarData {
     }
 }
 
+/// Convert a python datetime into the database timezone,
+/// naive datetimes are treated as local time of the database timezone.
+pub fn extract_datetime(obj: &Bound<'_, PyAny>) -> PyResult<DateTime<Tz>> {
+    let tz = db_tz()?;
+    if let Ok(dt) = obj.extract::<DateTime<FixedOffset>>() {
+        return Ok(dt.with_timezone(&tz));
+    }
+    Ok(localize(tz, obj.extract()?))
+}
+
+/// Accept both vnpyrs enums/strings and vnpy's Enum objects.
+pub fn extract_interval(obj: &Bound<'_, PyAny>) -> PyResult<Interval> {
+    if let Ok(interval) = obj.extract::<Interval>() {
+        return Ok(interval);
+    }
+    let value: String = match obj.extract::<String>() {
+        Ok(value) => value,
+        Err(_) => obj.getattr("value")?.extract()?,
+    };
+    Interval::from_str(&value)
+        .map_err(|_| PyValueError::new_err("interval字段只能是1m,1h,d,w,tick中的一个"))
+}
+
+pub fn extract_exchange(obj: &Bound<'_, PyAny>) -> PyResult<String> {
+    match obj.extract::<String>() {
+        Ok(exchange) => Ok(exchange),
+        Err(_) => obj.getattr("value")?.extract(),
+    }
+}
+
+impl BarData {
+    /// Build bar data from a vnpyrs BarData or any object with the same attributes (e.g. vnpy's BarData).
+    pub fn from_pyobject(obj: &Bound<'_, PyAny>) -> PyResult<BarData> {
+        if let Ok(bar) = obj.extract::<BarData>() {
+            return Ok(bar);
+        }
+        let f = |name: &str| -> PyResult<f64> { obj.getattr(name)?.extract() };
+        Ok(BarData {
+            gateway_name: "DB",
+            symbol: obj.getattr("symbol")?.extract()?,
+            exchange: extract_exchange(&obj.getattr("exchange")?)?,
+            datetime: extract_datetime(&obj.getattr("datetime")?)?,
+            interval: extract_interval(&obj.getattr("interval")?)?,
+            volume: f("volume")?,
+            turnover: f("turnover")?,
+            open_interest: f("open_interest")?,
+            open_price: f("open_price")?,
+            high_price: f("high_price")?,
+            low_price: f("low_price")?,
+            close_price: f("close_price")?,
+        })
+    }
+}
+
+impl TickData {
+    /// Build tick data from a vnpyrs TickData or any object with the same attributes (e.g. vnpy's TickData).
+    pub fn from_pyobject(obj: &Bound<'_, PyAny>) -> PyResult<TickData> {
+        if let Ok(tick) = obj.extract::<TickData>() {
+            return Ok(tick);
+        }
+        let f = |name: &str| -> PyResult<f64> { obj.getattr(name)?.extract() };
+        let datetime = extract_datetime(&obj.getattr("datetime")?)?;
+        Ok(TickData {
+            gateway_name: "DB",
+            symbol: obj.getattr("symbol")?.extract()?,
+            exchange: extract_exchange(&obj.getattr("exchange")?)?,
+            datetime,
+            name: obj.getattr("name")?.extract()?,
+            volume: f("volume")?,
+            turnover: f("turnover")?,
+            open_interest: f("open_interest")?,
+            last_price: f("last_price")?,
+            last_volume: f("last_volume")?,
+            limit_up: f("limit_up")?,
+            limit_down: f("limit_down")?,
+            open_price: f("open_price")?,
+            high_price: f("high_price")?,
+            low_price: f("low_price")?,
+            pre_close: f("pre_close")?,
+            bid_price_1: f("bid_price_1")?,
+            bid_price_2: f("bid_price_2")?,
+            bid_price_3: f("bid_price_3")?,
+            bid_price_4: f("bid_price_4")?,
+            bid_price_5: f("bid_price_5")?,
+            ask_price_1: f("ask_price_1")?,
+            ask_price_2: f("ask_price_2")?,
+            ask_price_3: f("ask_price_3")?,
+            ask_price_4: f("ask_price_4")?,
+            ask_price_5: f("ask_price_5")?,
+            bid_volume_1: f("bid_volume_1")?,
+            bid_volume_2: f("bid_volume_2")?,
+            bid_volume_3: f("bid_volume_3")?,
+            bid_volume_4: f("bid_volume_4")?,
+            bid_volume_5: f("bid_volume_5")?,
+            ask_volume_1: f("ask_volume_1")?,
+            ask_volume_2: f("ask_volume_2")?,
+            ask_volume_3: f("ask_volume_3")?,
+            ask_volume_4: f("ask_volume_4")?,
+            ask_volume_5: f("ask_volume_5")?,
+            localtime: match obj
+                .getattr("localtime")?
+                .extract::<Option<NaiveDateTime>>()?
+            {
+                Some(localtime) => localtime,
+                None => datetime.naive_local(),
+            },
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum MixData {
     TickData(TickData),
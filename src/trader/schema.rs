@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono_tz::Tz;
+use strum::{Display, EnumString};
+
+use super::{database::DatabaseConfig, setting::SettingError};
+
+/// Timezone of naive datetimes of exchanges without a configured timezone
+pub const DEFAULT_STORAGE_TZ: Tz = Tz::Asia__Shanghai;
+
+pub const BAR_VALUE_COLUMNS: [&str; 7] = [
+    "volume",
+    "turnover",
+    "open_interest",
+    "open_price",
+    "high_price",
+    "low_price",
+    "close_price",
+];
+
+pub const TICK_VALUE_COLUMNS: [&str; 31] = [
+    "volume",
+    "turnover",
+    "open_interest",
+    "last_price",
+    "last_volume",
+    "limit_up",
+    "limit_down",
+    "open_price",
+    "high_price",
+    "low_price",
+    "pre_close",
+    "bid_price_1",
+    "bid_price_2",
+    "bid_price_3",
+    "bid_price_4",
+    "bid_price_5",
+    "ask_price_1",
+    "ask_price_2",
+    "ask_price_3",
+    "ask_price_4",
+    "ask_price_5",
+    "bid_volume_1",
+    "bid_volume_2",
+    "bid_volume_3",
+    "bid_volume_4",
+    "bid_volume_5",
+    "ask_volume_1",
+    "ask_volume_2",
+    "ask_volume_3",
+    "ask_volume_4",
+    "ask_volume_5",
+];
+
+/// Fields of bars read from the database, values follow the key fields
+pub const BAR_KEY_FIELDS: [&str; 4] = ["symbol", "exchange", "datetime", "interval"];
+/// Fields of ticks read from the database, values follow the key fields, localtime is the last one
+pub const TICK_KEY_FIELDS: [&str; 4] = ["symbol", "exchange", "datetime", "name"];
+
+/// How datetimes are stored in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, Hash)]
+pub enum DatetimeFormat {
+    /// Naive local time, as written by vnpy
+    #[strum(serialize = "naive")]
+    NAIVE,
+    /// Naive UTC time, or time with timezone
+    #[strum(serialize = "utc")]
+    UTC,
+    /// Milliseconds since the epoch
+    #[strum(serialize = "epoch_ms")]
+    EPOCHMS,
+}
+
+/// Table (or collection) and column names of an existing database, so it can be read as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaMapping {
+    pub bar_table: String,
+    pub tick_table: String,
+    /// Field name to column name, fields not in the map use their own name
+    columns: BTreeMap<String, String>,
+    pub datetime_format: DatetimeFormat,
+    /// Timezones of naive datetimes, by exchange and by vt_symbol
+    exchange_timezones: BTreeMap<String, Tz>,
+    symbol_timezones: BTreeMap<String, Tz>,
+}
+
+impl SchemaMapping {
+    /// Empty table names and datetime format use the defaults of the backend
+    pub fn new(
+        config: &DatabaseConfig,
+        default_tables: (&str, &str),
+        default_format: DatetimeFormat,
+    ) -> Result<SchemaMapping, SettingError> {
+        check_schema(
+            &config.bar_table,
+            &config.tick_table,
+            &config.columns,
+            &config.datetime_format,
+        )?;
+        let exchange_timezones =
+            parse_timezones("database.exchange_timezones", &config.exchange_timezones)?;
+        let symbol_timezones =
+            parse_timezones("database.symbol_timezones", &config.symbol_timezones)?;
+        let or_default = |name: &str, default: &str| {
+            if name.is_empty() {
+                default.to_string()
+            } else {
+                name.to_string()
+            }
+        };
+        Ok(SchemaMapping {
+            bar_table: or_default(&config.bar_table, default_tables.0),
+            tick_table: or_default(&config.tick_table, default_tables.1),
+            columns: config.columns.clone(),
+            datetime_format: if config.datetime_format.is_empty() {
+                default_format
+            } else {
+                DatetimeFormat::from_str(&config.datetime_format).unwrap()
+            },
+            exchange_timezones,
+            symbol_timezones,
+        })
+    }
+
+    /// Timezone naive datetimes of a contract are stored in:
+    /// the one of its vt_symbol, then the one of its exchange, then Asia/Shanghai
+    pub fn storage_tz(&self, symbol: &str, exchange: &str) -> Tz {
+        self.symbol_timezones
+            .get(&format!("{}.{}", symbol, exchange))
+            .or_else(|| self.exchange_timezones.get(exchange))
+            .copied()
+            .unwrap_or(DEFAULT_STORAGE_TZ)
+    }
+
+    /// Column of a field, None if it's mapped to "" (the table doesn't have it)
+    pub fn column<'a>(&'a self, field: &'a str) -> Option<&'a str> {
+        match self.columns.get(field) {
+            Some(column) if column.is_empty() => None,
+            Some(column) => Some(column),
+            None => Some(field),
+        }
+    }
+
+    pub fn bar_fields() -> impl Iterator<Item = &'static str> {
+        BAR_KEY_FIELDS.into_iter().chain(BAR_VALUE_COLUMNS)
+    }
+
+    pub fn tick_fields() -> impl Iterator<Item = &'static str> {
+        TICK_KEY_FIELDS
+            .into_iter()
+            .chain(TICK_VALUE_COLUMNS)
+            .chain(["localtime"])
+    }
+}
+
+/// Keys of symbol timezones are vt_symbols like BTCUSDT.BINANCE
+pub fn parse_timezones(
+    key: &str,
+    timezones: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, Tz>, SettingError> {
+    timezones
+        .iter()
+        .map(|(name, timezone)| {
+            if key == "database.symbol_timezones" && !name.contains('.') {
+                return Err(SettingError::invalid(
+                    key,
+                    format!("{}不是vt_symbol格式（代码.交易所）", name),
+                ));
+            }
+            let tz = timezone.parse::<Tz>().map_err(|e| {
+                SettingError::invalid(key, format!("{}的时区{}无效：{}", name, timezone, e))
+            })?;
+            Ok((name.clone(), tz))
+        })
+        .collect()
+}
+
+fn check_name(key: &str, name: &str) -> Result<(), SettingError> {
+    if name.contains(['"', '`', '\'', ';']) {
+        return Err(SettingError::invalid(
+            key,
+            format!("名称{}不能包含引号或分号", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the schema settings, shared by vt_setting.json and DatabaseConfig
+pub fn check_schema(
+    bar_table: &str,
+    tick_table: &str,
+    columns: &BTreeMap<String, String>,
+    datetime_format: &str,
+) -> Result<(), SettingError> {
+    check_name("database.bar_table", bar_table)?;
+    check_name("database.tick_table", tick_table)?;
+    for (field, column) in columns {
+        if !SchemaMapping::bar_fields()
+            .chain(SchemaMapping::tick_fields())
+            .any(|known| known == field)
+        {
+            return Err(SettingError::invalid(
+                "database.columns",
+                format!("未知的字段{}", field),
+            ));
+        }
+        if field == "datetime" && column.is_empty() {
+            return Err(SettingError::invalid(
+                "database.columns",
+                "datetime字段必须有对应的列",
+            ));
+        }
+        check_name("database.columns", column)?;
+    }
+    if !datetime_format.is_empty() && DatetimeFormat::from_str(datetime_format).is_err() {
+        return Err(SettingError::invalid(
+            "database.datetime_format",
+            format!(
+                "只能是naive,utc,epoch_ms中的一个，实际为{}",
+                datetime_format
+            ),
+        ));
+    }
+    Ok(())
+}
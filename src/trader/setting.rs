@@ -1,23 +1,75 @@
-use pyo3::prelude::*;
+use chrono_tz::Tz;
+use pyo3::{
+    prelude::*,
+    types::{PyBool, PyDict},
+};
 use serde::{Deserialize, Serialize};
-use std::{io::Write, sync::LazyLock};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    path::Path,
+    sync::{Arc, LazyLock, Mutex},
+};
 
-use crate::trader::utility::get_file_path;
+use crate::trader::{
+    schema::{check_schema, parse_timezones},
+    utility::get_file_path,
+};
 
-pub fn get_localzone_name() -> String {
+#[derive(Debug, Clone)]
+pub enum SettingError {
+    /// The setting file exists but can't be read
+    Read { path: String, message: String },
+    /// The setting file is not valid json
+    Parse { path: String, message: String },
+    /// A setting item has an unusable value
+    Invalid { key: String, message: String },
+}
+
+impl SettingError {
+    pub fn invalid(key: &str, message: impl fmt::Display) -> Self {
+        SettingError::Invalid {
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::Read { path, message } => {
+                write!(f, "配置文件{}读取失败：{}", path, message)
+            }
+            SettingError::Parse { path, message } => {
+                write!(f, "配置文件{}格式错误：{}", path, message)
+            }
+            SettingError::Invalid { key, message } => {
+                write!(f, "配置项{}错误：{}", key, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingError {}
+
+pub fn get_localzone_name() -> Result<String, SettingError> {
     Python::with_gil(|py| {
-        let tzlocal = PyModule::import(py, "tzlocal").unwrap();
-        tzlocal
-            .getattr("get_localzone_name")
-            .unwrap()
-            .call0()
-            .unwrap()
+        PyModule::import(py, "tzlocal")?
+            .getattr("get_localzone_name")?
+            .call0()?
             .extract()
-            .unwrap()
+    })
+    .map_err(|e| {
+        SettingError::invalid(
+            "database.timezone",
+            format!("无法获取本地时区（{}），请在配置文件中设置", e),
+        )
     })
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SettingDict {
     #[serde(default, rename = "font.family")]
     pub font_family: String,
@@ -58,93 +110,388 @@ pub struct SettingDict {
     pub database_user: String,
     #[serde(default, rename = "database.password")]
     pub database_password: String,
+    #[serde(default, rename = "database.concurrency")]
+    pub database_concurrency: u16,
+    #[serde(default, rename = "database.retry")]
+    pub database_retry: u16,
+    #[serde(default, rename = "database.retry_interval")]
+    pub database_retry_interval: f64,
+    /// Table of bars, or collection for mongodb, empty for the vnpy name
+    #[serde(default, rename = "database.bar_table")]
+    pub database_bar_table: String,
+    #[serde(default, rename = "database.tick_table")]
+    pub database_tick_table: String,
+    /// Field name to column name, "" for fields the table doesn't have
+    #[serde(default, rename = "database.columns")]
+    pub database_columns: BTreeMap<String, String>,
+    /// naive, utc or epoch_ms, empty for the vnpy format
+    #[serde(default, rename = "database.datetime_format")]
+    pub database_datetime_format: String,
+    /// Exchange to the timezone its naive datetimes are stored in, Asia/Shanghai if not set
+    #[serde(default, rename = "database.exchange_timezones")]
+    pub database_exchange_timezones: BTreeMap<String, String>,
+    /// vt_symbol to timezone, overrides the timezone of the exchange
+    #[serde(default, rename = "database.symbol_timezones")]
+    pub database_symbol_timezones: BTreeMap<String, String>,
 }
 
-const SETTING_FILENAME: &str = "vt_setting.json";
-pub static SETTINGS: LazyLock<SettingDict> = LazyLock::new(|| {
-    let mut setting = SettingDict {
-        font_family: "微软雅黑".to_string(),
-        font_size: 12,
-        email_server: "smtp.qq.com".to_string(),
-        email_port: 465,
-        email_username: "".to_string(),
-        email_password: "".to_string(),
-        email_sender: "".to_string(),
-        email_receiver: "".to_string(),
-
-        datafeed_name: "".to_string(),
-        datafeed_username: "".to_string(),
-        datafeed_password: "".to_string(),
-
-        database_timezone: get_localzone_name(),
-        database_name: "sqlite".to_string(),
-        database_database: "database.db".to_string(),
-        database_host: "".to_string(),
-        database_port: 0,
-        database_user: "".to_string(),
-        database_password: "".to_string(),
-    };
-    if let Ok(file_content) = std::fs::read_to_string(get_file_path(SETTING_FILENAME)) {
-        let in_json_file: SettingDict = serde_json::from_str(&file_content).unwrap();
-        if !in_json_file.font_family.is_empty() {
-            setting.font_family = in_json_file.font_family;
-        }
-        if in_json_file.font_size != 0 {
-            setting.font_size = in_json_file.font_size;
-        }
-        if !in_json_file.email_server.is_empty() {
-            setting.email_server = in_json_file.email_server;
-        }
-        if in_json_file.email_port != 0 {
-            setting.email_port = in_json_file.email_port;
-        }
-        if !in_json_file.email_username.is_empty() {
-            setting.email_username = in_json_file.email_username;
-        }
-        if !in_json_file.email_password.is_empty() {
-            setting.email_password = in_json_file.email_password;
-        }
-        if !in_json_file.email_sender.is_empty() {
-            setting.email_sender = in_json_file.email_sender;
-        }
-        if !in_json_file.email_receiver.is_empty() {
-            setting.email_receiver = in_json_file.email_receiver;
-        }
-        if !in_json_file.datafeed_name.is_empty() {
-            setting.datafeed_name = in_json_file.datafeed_name;
-        }
-        if !in_json_file.datafeed_username.is_empty() {
-            setting.datafeed_username = in_json_file.datafeed_username;
+impl Default for SettingDict {
+    fn default() -> Self {
+        SettingDict {
+            font_family: "微软雅黑".to_string(),
+            font_size: 12,
+            email_server: "smtp.qq.com".to_string(),
+            email_port: 465,
+            email_username: "".to_string(),
+            email_password: "".to_string(),
+            email_sender: "".to_string(),
+            email_receiver: "".to_string(),
+
+            datafeed_name: "".to_string(),
+            datafeed_username: "".to_string(),
+            datafeed_password: "".to_string(),
+
+            // Empty means the local timezone
+            database_timezone: "".to_string(),
+            database_name: "sqlite".to_string(),
+            database_database: "database.db".to_string(),
+            database_host: "".to_string(),
+            database_port: 0,
+            database_user: "".to_string(),
+            database_password: "".to_string(),
+            database_concurrency: 4,
+            database_retry: 0,
+            database_retry_interval: 1.0,
+            database_bar_table: "".to_string(),
+            database_tick_table: "".to_string(),
+            database_columns: BTreeMap::new(),
+            database_datetime_format: "".to_string(),
+            database_exchange_timezones: BTreeMap::new(),
+            database_symbol_timezones: BTreeMap::new(),
         }
-        if !in_json_file.datafeed_password.is_empty() {
-            setting.datafeed_password = in_json_file.datafeed_password;
+    }
+}
+
+const SETTING_FILENAME: &str = "vt_setting.json";
+/// Settings of the current project, in the working directory
+const LOCAL_SETTING_FILENAME: &str = "vnpyrs_setting.json";
+/// database.port can be set by VNPYRS_DATABASE_PORT
+const ENV_PREFIX: &str = "VNPYRS_";
+
+struct LoadedSettings {
+    setting: Arc<SettingDict>,
+    /// Effective value of every key and where it comes from
+    values: BTreeMap<String, (Value, String)>,
+}
+
+static LOADED_SETTINGS: Mutex<Option<Arc<LoadedSettings>>> = Mutex::new(None);
+static OVERRIDES: LazyLock<Mutex<Map<String, Value>>> = LazyLock::new(|| Mutex::new(Map::new()));
+
+/// Settings merged from, in increasing priority: defaults, vt_setting.json,
+/// vnpyrs_setting.json in the working directory, VNPYRS_* environment variables
+/// and overrides set from Python. They are loaded at first use and kept until reload_settings.
+pub fn try_settings() -> Result<Arc<SettingDict>, SettingError> {
+    Ok(loaded_settings()?.setting.clone())
+}
+
+fn loaded_settings() -> Result<Arc<LoadedSettings>, SettingError> {
+    if let Some(loaded) = LOADED_SETTINGS.lock().unwrap().as_ref() {
+        return Ok(loaded.clone());
+    }
+    // Loading may need the GIL for the local timezone, so it's done without holding the lock
+    let overrides = OVERRIDES.lock().unwrap().clone();
+    let loaded = Arc::new(load_settings(&overrides)?);
+    Ok(LOADED_SETTINGS
+        .lock()
+        .unwrap()
+        .get_or_insert(loaded)
+        .clone())
+}
+
+/// Read every layer again, the previous settings are kept if the new ones are invalid
+pub fn reload_settings() -> Result<Arc<SettingDict>, SettingError> {
+    let overrides = OVERRIDES.lock().unwrap().clone();
+    let loaded = Arc::new(load_settings(&overrides)?);
+    *LOADED_SETTINGS.lock().unwrap() = Some(loaded.clone());
+    Ok(loaded.setting.clone())
+}
+
+fn default_values() -> Map<String, Value> {
+    match serde_json::to_value(SettingDict::default()) {
+        Ok(Value::Object(values)) => values,
+        _ => unreachable!("SettingDict is always a json object"),
+    }
+}
+
+fn load_settings(overrides: &Map<String, Value>) -> Result<LoadedSettings, SettingError> {
+    let defaults = default_values();
+    let mut values: BTreeMap<String, (Value, String)> = defaults
+        .iter()
+        .map(|(key, value)| (key.clone(), (value.clone(), "default".to_string())))
+        .collect();
+
+    let path = get_file_path(SETTING_FILENAME);
+    if let Some(layer) = read_setting_file(&path)? {
+        merge_layer(
+            &defaults,
+            &mut values,
+            layer,
+            &format!("file:{}", path.display()),
+        )?;
+    }
+    if let Ok(cwd) = env::current_dir() {
+        let path = cwd.join(LOCAL_SETTING_FILENAME);
+        if let Some(layer) = read_setting_file(&path)? {
+            merge_layer(
+                &defaults,
+                &mut values,
+                layer,
+                &format!("local:{}", path.display()),
+            )?;
         }
-        if !in_json_file.database_timezone.is_empty() {
-            setting.database_timezone = in_json_file.database_timezone;
+    }
+    for (key, default) in defaults.iter() {
+        let name = env_name(key);
+        if let Ok(raw) = env::var(&name) {
+            // Environment variables are strings, numbers and objects are parsed as json
+            let value = match default {
+                Value::String(_) => Value::String(raw),
+                _ => serde_json::from_str(&raw).map_err(|e| {
+                    SettingError::invalid(key, format!("{}（来源：env:{}）", e, name))
+                })?,
+            };
+            let mut layer = Map::new();
+            layer.insert(key.clone(), value);
+            merge_layer(&defaults, &mut values, layer, &format!("env:{}", name))?;
         }
-        if !in_json_file.database_name.is_empty() {
-            setting.database_name = in_json_file.database_name;
+    }
+    merge_layer(&defaults, &mut values, overrides.clone(), "override")?;
+
+    if values["database.timezone"].0.as_str() == Some("") {
+        values.insert(
+            "database.timezone".to_string(),
+            (Value::String(get_localzone_name()?), "local".to_string()),
+        );
+    }
+
+    let merged: Map<String, Value> = values
+        .iter()
+        .map(|(key, (value, _))| (key.clone(), value.clone()))
+        .collect();
+    let setting: SettingDict =
+        serde_json::from_value(Value::Object(merged)).map_err(|e| SettingError::invalid("*", e))?;
+    validate_settings(&setting)?;
+    Ok(LoadedSettings {
+        setting: Arc::new(setting),
+        values,
+    })
+}
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('.', "_"))
+}
+
+fn read_setting_file(path: &Path) -> Result<Option<Map<String, Value>>, SettingError> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(SettingError::Read {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            })
         }
-        if !in_json_file.database_database.is_empty() {
-            setting.database_database = in_json_file.database_database;
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| SettingError::Parse {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// Check the type of every known key in the layer before it's merged.
+/// Keys used by other vnpy modules are left alone.
+fn merge_layer(
+    defaults: &Map<String, Value>,
+    values: &mut BTreeMap<String, (Value, String)>,
+    layer: Map<String, Value>,
+    source: &str,
+) -> Result<(), SettingError> {
+    for (key, value) in layer {
+        if !defaults.contains_key(&key) {
+            continue;
         }
-        if !in_json_file.database_host.is_empty() {
-            setting.database_host = in_json_file.database_host;
+        let mut single = defaults.clone();
+        single.insert(key.clone(), value.clone());
+        if let Err(e) = serde_json::from_value::<SettingDict>(Value::Object(single)) {
+            return Err(SettingError::invalid(
+                &key,
+                format!("{}（来源：{}）", e, source),
+            ));
         }
-        if in_json_file.database_port != 0 {
-            setting.database_port = in_json_file.database_port;
+        values.insert(key, (value, source.to_string()));
+    }
+    Ok(())
+}
+
+fn validate_settings(setting: &SettingDict) -> Result<(), SettingError> {
+    if let Err(e) = setting.database_timezone.parse::<Tz>() {
+        return Err(SettingError::invalid("database.timezone", e));
+    }
+    if !["sqlite", "mysql", "postgresql", "mongodb", "parquet"]
+        .contains(&setting.database_name.as_str())
+    {
+        return Err(SettingError::invalid(
+            "database.name",
+            format!("不支持的数据库{}", setting.database_name),
+        ));
+    }
+    if setting.database_concurrency == 0 {
+        return Err(SettingError::invalid(
+            "database.concurrency",
+            "并发数必须大于0",
+        ));
+    }
+    if setting.database_retry_interval <= 0.0 {
+        return Err(SettingError::invalid(
+            "database.retry_interval",
+            "重试间隔必须大于0",
+        ));
+    }
+    check_schema(
+        &setting.database_bar_table,
+        &setting.database_tick_table,
+        &setting.database_columns,
+        &setting.database_datetime_format,
+    )?;
+    parse_timezones(
+        "database.exchange_timezones",
+        &setting.database_exchange_timezones,
+    )?;
+    parse_timezones(
+        "database.symbol_timezones",
+        &setting.database_symbol_timezones,
+    )?;
+    Ok(())
+}
+
+fn json_to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::String(value) => value.into_pyobject(py)?.into_any().unbind(),
+        Value::Bool(value) => value.into_pyobject(py)?.to_owned().into_any().unbind(),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => value.into_pyobject(py)?.into_any().unbind(),
+            None => number
+                .as_f64()
+                .unwrap_or_default()
+                .into_pyobject(py)?
+                .into_any()
+                .unbind(),
+        },
+        Value::Object(values) => {
+            let dict = PyDict::new(py);
+            for (key, value) in values {
+                dict.set_item(key, json_to_py(py, value)?)?;
+            }
+            dict.into_any().unbind()
         }
-        if !in_json_file.database_user.is_empty() {
-            setting.database_user = in_json_file.database_user;
+        _ => py.None(),
+    })
+}
+
+fn py_to_json(key: &str, value: &Bound<'_, PyAny>) -> Result<Value, SettingError> {
+    if value.is_instance_of::<PyBool>() {
+        return Ok(Value::Bool(value.extract().unwrap_or_default()));
+    }
+    if let Ok(value) = value.extract::<i64>() {
+        return Ok(Value::from(value));
+    }
+    if let Ok(value) = value.extract::<f64>() {
+        return Ok(Value::from(value));
+    }
+    if let Ok(value) = value.extract::<String>() {
+        return Ok(Value::String(value));
+    }
+    if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut values = Map::new();
+        for (item_key, item_value) in dict.iter() {
+            let item_key: String = item_key
+                .extract()
+                .map_err(|_| SettingError::invalid(key, "dict的键必须是str"))?;
+            values.insert(item_key, py_to_json(key, &item_value)?);
         }
-        if !in_json_file.database_password.is_empty() {
-            setting.database_password = in_json_file.database_password;
+        return Ok(Value::Object(values));
+    }
+    Err(SettingError::invalid(
+        key,
+        "只支持str、int、float、dict类型的值",
+    ))
+}
+
+/// Effective settings as a dict.
+#[pyfunction]
+pub fn get_settings(py: Python<'_>) -> PyResult<Py<PyDict>> {
+    let loaded = loaded_settings()?;
+    let dict = PyDict::new(py);
+    for (key, (value, _)) in loaded.values.iter() {
+        dict.set_item(key, json_to_py(py, value)?)?;
+    }
+    Ok(dict.unbind())
+}
+
+/// Effective settings with their sources, as {key: (value, source)}.
+/// Source is one of default, local (timezone of the system), file:<path>, local:<path>, env:<name> and override.
+#[pyfunction]
+pub fn dump_settings(py: Python<'_>) -> PyResult<Py<PyDict>> {
+    let loaded = loaded_settings()?;
+    let dict = PyDict::new(py);
+    for (key, (value, source)) in loaded.values.iter() {
+        dict.set_item(key, (json_to_py(py, value)?, source))?;
+    }
+    Ok(dict.unbind())
+}
+
+/// Override settings from Python, they take priority over every other layer.
+/// Nothing is changed if any of them is invalid.
+#[pyfunction]
+pub fn set_settings(overrides: &Bound<'_, PyDict>) -> PyResult<()> {
+    let mut new_overrides = OVERRIDES.lock().unwrap().clone();
+    for (key, value) in overrides.iter() {
+        let key: String = key.extract()?;
+        if !default_values().contains_key(&key) {
+            return Err(SettingError::invalid(&key, "未知的配置项").into());
         }
-    } else {
-        println!("文件{}未找到，使用默认值", SETTING_FILENAME);
-        let to_json_file = serde_json::to_string_pretty(&setting).unwrap();
-        let mut file = std::fs::File::create(get_file_path(SETTING_FILENAME)).unwrap();
-        file.write_all(to_json_file.as_bytes()).unwrap();
-    }
-    setting
-});
+        new_overrides.insert(key.clone(), py_to_json(&key, &value)?);
+    }
+    let loaded = Arc::new(load_settings(&new_overrides)?);
+    *OVERRIDES.lock().unwrap() = new_overrides;
+    *LOADED_SETTINGS.lock().unwrap() = Some(loaded);
+    Ok(())
+}
+
+/// Remove every override set by set_settings.
+#[pyfunction]
+pub fn clear_settings() -> PyResult<()> {
+    OVERRIDES.lock().unwrap().clear();
+    reload_settings()?;
+    Ok(())
+}
+
+/// Read the setting files and environment variables again, e.g. after vt_setting.json is edited.
+#[pyfunction]
+#[pyo3(name = "reload_settings")]
+pub fn py_reload_settings() -> PyResult<()> {
+    reload_settings()?;
+    Ok(())
+}
+
+#[pymodule]
+pub fn setting(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(get_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(dump_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(set_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(clear_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(py_reload_settings, m)?)?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
-use chrono::{DateTime, NaiveTime, Timelike};
+use chrono::{
+    DateTime, LocalResult, NaiveDateTime, NaiveTime, Offset, TimeDelta, TimeZone, Timelike,
+};
 use chrono_tz::Tz;
 use pyo3::prelude::*;
 use rust_decimal::prelude::*;
@@ -50,6 +52,22 @@ pub fn get_file_path(filename: &str) -> PathBuf {
     COMBIN_DIRS.1.join(filename)
 }
 
+/// Attach a timezone to a local time the way Python does with fold=0:
+/// a repeated hour takes the earlier offset, and a time skipped by a DST gap
+/// keeps the offset before the gap, which moves it after the gap.
+pub fn localize(tz: Tz, datetime: NaiveDateTime) -> DateTime<Tz> {
+    match tz.from_local_datetime(&datetime) {
+        LocalResult::Single(dt) => dt,
+        LocalResult::Ambiguous(earlier, _) => earlier,
+        LocalResult::None => {
+            let offset = tz
+                .offset_from_utc_datetime(&(datetime - TimeDelta::days(1)))
+                .fix();
+            tz.from_utc_datetime(&(datetime - TimeDelta::seconds(offset.local_minus_utc() as i64)))
+        }
+    }
+}
+
 pub fn round_to(value: f64, target: f64) -> f64 {
     let value: Decimal = Decimal::from_str(&value.to_string()).unwrap();
     let target: Decimal = Decimal::from_str(&target.to_string()).unwrap();
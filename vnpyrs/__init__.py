@@ -14,6 +14,7 @@ from functools import partial
 import numpy as np
 import talib
 import subprocess
+import warnings
 
 from abc import ABC
 from copy import copy
@@ -22,6 +23,7 @@ from typing import Any, Callable, List
 from vnpyrs.trader.constant import Interval, Direction, Offset_
 from vnpyrs.trader.object import BarData, TickData, OrderData, TradeData
 from vnpyrs.trader.utility import BarGenerator
+from vnpyrs.trader.database import DatabaseConfig
 
 import vnpyrs.optimize
 import sys
@@ -38,7 +40,7 @@ from datetime import date, datetime, timedelta
 from pandas import DataFrame
 import plotly.graph_objects as go
 from plotly.subplots import make_subplots
-from vnpyrs.backtesting import BacktestingEngine, BacktestingMode
+from vnpyrs.backtesting import BacktestingEngine, BacktestingMode, BacktestStatistics
 from vnpyrs import CandleChartDialog
 
 vnpyrs.backtesting.OptimizationSetting = OptimizationSetting
@@ -1043,13 +1045,15 @@ class TargetPosTemplate(CtaTemplate):
             self.active_orderids.extend(vt_orderids)
 
 
-def member_calculate_statistics(self, df: DataFrame = None, output=True) -> dict:
-    """"""
-    self.output(_("开始计算策略统计指标"))
-
-    # Check DataFrame input exterior
+def member_calculate_statistics(self, df: DataFrame = None, output=True):
+    """
+    Statistics are calculated in Rust from the daily results and returned as BacktestStatistics,
+    which works like a dict. A DataFrame passed in is still calculated by pandas into a dict.
+    """
     if df is None:
-        df: DataFrame = self.daily_df
+        return self.rs_calculate_statistics(output)
+
+    self.output(_("开始计算策略统计指标"))
 
     # Init all statistics default value
     start_date: str = ""
@@ -1247,6 +1251,25 @@ def member_calculate_statistics(self, df: DataFrame = None, output=True) -> dict
     return statistics
 
 
+def member_get_equity_curve(self, as_dataframe: bool = True):
+    """
+    Equity curve recorded at every bar or tick when set_parameters is called with record_equity=True.
+    Returns a DataFrame indexed by datetime, or a dict of numpy arrays when as_dataframe is False.
+    """
+    data = self.rs_get_equity_curve()
+    timezone = data.pop("timezone")
+    arrays = {
+        key: np.frombuffer(value, dtype="datetime64[ns]" if key == "datetime" else np.float64)
+        for key, value in data.items()
+    }
+    if not as_dataframe:
+        return arrays
+
+    df = DataFrame(arrays).set_index("datetime")
+    df.index = df.index.tz_localize("UTC").tz_convert(timezone)
+    return df
+
+
 def member_show_chart(self, df: DataFrame = None) -> go.Figure:
     """"""
     # Check DataFrame input exterior
@@ -1291,18 +1314,19 @@ def member_run_bf_optimization(
     optimization_setting: OptimizationSetting,
     output: bool = True,
     max_workers: int = None,
+    callback: Callable[[tuple, int, int, float], None] = None,
 ) -> list:
-    """"""
+    """
+    callback is called with (result, finished, total, eta) as each setting finishes
+    """
     if not check_optimization_setting(optimization_setting):
         return
 
-    evaluate_func: callable = wrap_evaluate(self, optimization_setting.target_name)
-    results: list = run_bf_optimization(
-        evaluate_func,
-        optimization_setting,
-        get_target_value,
-        max_workers=max_workers,
-        output=self.output,
+    results: list = self.rs_run_bf_optimization(
+        optimization_setting.generate_settings(),
+        optimization_setting.target_name,
+        max_workers,
+        callback,
     )
 
     if output:
@@ -1319,19 +1343,32 @@ def member_run_ga_optimization(
     output: bool = True,
     max_workers: int = None,
     ngen_size: int = 30,
+    population_size: int = 100,
+    crossover_rate: float = 0.95,
+    mutation_rate: float = 0.1,
+    tournament_size: int = 3,
+    seed: int = 0,
+    callback: Callable[[tuple, int, float], None] = None,
 ) -> list:
-    """"""
+    """
+    Every evaluated setting is returned as (setting, target, statistics, generation).
+    callback is called with (result, generation, eta) as each setting finishes
+    """
     if not check_optimization_setting(optimization_setting):
         return
 
-    evaluate_func: callable = wrap_evaluate(self, optimization_setting.target_name)
-    results: list = run_ga_optimization(
-        evaluate_func,
-        optimization_setting,
-        get_target_value,
-        max_workers=max_workers,
-        ngen_size=ngen_size,
-        output=self.output,
+    results: list = self.rs_run_ga_optimization(
+        optimization_setting.generate_params(),
+        optimization_setting.target_name,
+        max_workers,
+        population_size,
+        ngen_size,
+        crossover_rate,
+        mutation_rate,
+        tournament_size,
+        seed,
+        callback,
+        optimization_setting.budget,
     )
 
     if output:
@@ -1342,6 +1379,35 @@ def member_run_ga_optimization(
     return results
 
 
+def member_run_walk_forward(
+    self,
+    optimization_setting: OptimizationSetting,
+    in_sample: int,
+    out_sample: int,
+    unit: str = "DAYS",
+    anchored: bool = False,
+    max_workers: int = None,
+    callback: Callable = None,
+):
+    """
+    in_sample and out_sample are counted in days or bars, depending on unit.
+    callback is called with (window, finished, total, eta) after each window
+    """
+    if not check_optimization_setting(optimization_setting):
+        return
+
+    return self.rs_run_walk_forward(
+        optimization_setting.generate_settings(),
+        optimization_setting.target_name,
+        in_sample,
+        out_sample,
+        unit,
+        anchored,
+        max_workers,
+        callback,
+    )
+
+
 def evaluate(
     target_name: str,
     strategy_class: CtaTemplate,
@@ -1355,12 +1421,13 @@ def evaluate(
     capital: int,
     end: datetime,
     mode: BacktestingMode,
+    database_config: DatabaseConfig,
     setting: dict,
 ) -> tuple:
     """
     Function for running in multiprocessing.pool
     """
-    engine: BacktestingEngine = BacktestingEngine()
+    engine: BacktestingEngine = BacktestingEngine(database_config)
 
     engine.set_parameters(
         vt_symbol=vt_symbol,
@@ -1378,8 +1445,7 @@ def evaluate(
     engine.add_strategy(strategy_class, setting)
     engine.load_data()
     engine.run_backtesting()
-    engine.calculate_result()
-    statistics: dict = engine.calculate_statistics(output=False)
+    statistics: dict = engine.calculate_statistics(output=False).to_dict()
 
     target_value: float = statistics[target_name]
     return (setting, target_value, statistics)
@@ -1389,6 +1455,12 @@ def wrap_evaluate(engine: BacktestingEngine, target_name: str) -> callable:
     """
     Wrap evaluate function with given setting from backtesting engine.
     """
+    warnings.warn(
+        "wrap_evaluate is deprecated, use BacktestingEngine.run_bf_optimization "
+        "or BacktestingEngine.run_ga_optimization instead",
+        DeprecationWarning,
+        stacklevel=2,
+    )
     func: callable = partial(
         evaluate,
         target_name,
@@ -1403,6 +1475,7 @@ def wrap_evaluate(engine: BacktestingEngine, target_name: str) -> callable:
         engine.capital,
         engine.end,
         str(engine.mode),
+        engine.database_config,
     )
     return func
 
@@ -1416,9 +1489,11 @@ def get_target_value(result: list) -> float:
 
 BacktestingEngine.calculate_statistics = member_calculate_statistics
 BacktestingEngine.show_chart = member_show_chart
+BacktestingEngine.get_equity_curve = member_get_equity_curve
 BacktestingEngine.run_bf_optimization = member_run_bf_optimization
 BacktestingEngine.run_optimization = member_run_bf_optimization
 BacktestingEngine.run_ga_optimization = member_run_ga_optimization
+BacktestingEngine.run_walk_forward = member_run_walk_forward
 
 
 def member_exec_(self):
@@ -187,7 +187,7 @@ class BacktesterEngine:
             return
 
         self.result_df = engine.calculate_result()
-        self.result_statistics = engine.calculate_statistics(output=False)
+        self.result_statistics = engine.calculate_statistics(output=False).to_dict()
 
         # Clear thread object handler.
         self.thread = None
@@ -1,4 +1,4 @@
-from typing import Dict, List, Callable, Tuple
+from typing import Dict, List, Callable, Tuple, Union
 from itertools import product
 from concurrent.futures import ProcessPoolExecutor
 from random import random, choice
@@ -8,7 +8,15 @@ from multiprocessing.context import BaseContext
 from _collections_abc import dict_keys, dict_values, Iterable
 
 from tqdm import tqdm
-from deap import creator, base, tools, algorithms
+
+from vnpyrs.backtesting import ParameterRange, sample_settings
+
+# deap is only needed by the python run_ga_optimization,
+# BacktestingEngine.run_ga_optimization runs the genetic algorithm natively
+try:
+    from deap import creator, base, tools, algorithms
+except ImportError:
+    creator = None
 
 
 def _(str):
@@ -21,8 +29,9 @@ KEY_FUNC = Callable[[list], float]
 
 
 # Create individual class used in genetic algorithm optimization
-creator.create("FitnessMax", base.Fitness, weights=(1.0,))
-creator.create("Individual", list, fitness=creator.FitnessMax)
+if creator:
+    creator.create("FitnessMax", base.Fitness, weights=(1.0,))
+    creator.create("Individual", list, fitness=creator.FitnessMax)
 
 
 class OptimizationSetting:
@@ -32,8 +41,11 @@ class OptimizationSetting:
 
     def __init__(self) -> None:
         """"""
-        self.params: Dict[str, List] = {}
+        self.params: Dict[str, Union[List, ParameterRange]] = {}
         self.target_name: str = ""
+        self.sampler: str = "GRID"
+        self.budget: int = None
+        self.seed: int = 0
 
     def add_parameter(
         self, name: str, start: float, end: float = None, step: float = None
@@ -60,14 +72,96 @@ class OptimizationSetting:
 
         return True, _("范围参数添加成功，数量{}").format(len(value_list))
 
+    def add_categorical(self, name: str, values: list) -> Tuple[bool, str]:
+        """"""
+        if not values:
+            return False, _("参数取值不能为空")
+
+        self.params[name] = list(values)
+        return True, _("离散参数添加成功，数量{}").format(len(values))
+
+    def add_boolean(self, name: str) -> Tuple[bool, str]:
+        """"""
+        self.params[name] = [False, True]
+        return True, _("布尔参数添加成功")
+
+    def add_range(
+        self,
+        name: str,
+        start: float,
+        end: float,
+        log: bool = False,
+        integer: bool = False,
+        count: int = None,
+    ) -> Tuple[bool, str]:
+        """
+        Range without a count is continuous, which only samplers other than GRID can draw from,
+        unless the values are integers
+        """
+        try:
+            self.params[name] = ParameterRange(start, end, log, integer, count)
+        except ValueError as e:
+            return False, str(e)
+
+        return True, _("范围参数添加成功")
+
+    def set_sampler(
+        self, sampler: str = "GRID", budget: int = None, seed: int = 0
+    ) -> Tuple[bool, str]:
+        """
+        GRID tries every combination, RANDOM, LHS and SOBOL draw budget settings.
+        The genetic algorithm stops after budget evaluations.
+        """
+        if sampler not in ("GRID", "RANDOM", "LHS", "SOBOL"):
+            return False, _("采样方式只能是GRID,RANDOM,LHS,SOBOL中的一个")
+
+        if sampler != "GRID" and not budget:
+            return False, _("随机采样必须设置评估次数")
+
+        if budget is not None and budget <= 0:
+            return False, _("评估次数必须大于0")
+
+        self.sampler = sampler
+        self.budget = budget
+        self.seed = seed
+        return True, _("采样方式设置成功")
+
     def set_target(self, target_name: str) -> None:
         """"""
         self.target_name = target_name
 
+    def generate_params(self) -> Dict[str, List]:
+        """
+        Values of every parameter, a continuous range gets the values drawn by the sampler
+        """
+        params: Dict[str, List] = {}
+        sampled: List[dict] = None
+        for name, space in self.params.items():
+            if not isinstance(space, ParameterRange):
+                params[name] = space
+                continue
+
+            values: list = space.grid()
+            if values is None:
+                if self.sampler == "GRID":
+                    raise ValueError(
+                        _("参数{}是连续范围，需要设置count或者使用RANDOM,LHS,SOBOL采样").format(name)
+                    )
+                if sampled is None:
+                    sampled = self.generate_settings()
+                values = sorted({setting[name] for setting in sampled})
+            params[name] = values
+
+        return params
+
     def generate_settings(self) -> List[dict]:
         """"""
-        keys: dict_keys = self.params.keys()
-        values: dict_values = self.params.values()
+        if self.sampler != "GRID":
+            return sample_settings(self.params, self.sampler, self.budget, self.seed)
+
+        params: Dict[str, List] = self.generate_params()
+        keys: dict_keys = params.keys()
+        values: dict_values = params.values()
         products: list = list(product(*values))
 
         settings: list = []
@@ -82,7 +176,13 @@ def check_optimization_setting(
     optimization_setting: OptimizationSetting, output: OUTPUT_FUNC = print
 ) -> bool:
     """"""
-    if not optimization_setting.generate_settings():
+    try:
+        settings: List[dict] = optimization_setting.generate_settings()
+    except ValueError as e:
+        output(str(e))
+        return False
+
+    if not settings:
         output(_("优化参数组合为空，请检查"))
         return False
 
@@ -130,6 +230,9 @@ def run_ga_optimization(
     output: OUTPUT_FUNC = print,
 ) -> List[Tuple]:
     """Run genetic algorithm optimization"""
+    if not creator:
+        raise ImportError(_("run_ga_optimization需要安装deap"))
+
     # Define functions for generate parameter randomly
     buf: List[Dict] = optimization_setting.generate_settings()
     settings: List[Tuple] = [list(d.items()) for d in buf]
@@ -203,6 +306,46 @@ def run_ga_optimization(
         return results
 
 
+# History data and engine parameters of an optimization worker process
+_worker_state: dict = {}
+
+
+def init_worker(
+    snapshot_path: str, parameters: dict, strategy_class: type, target_name: str
+) -> None:
+    """
+    Initializer of the worker processes started by rs_run_bf_optimization.
+    The history data is read once from the snapshot instead of the database.
+    """
+    from vnpyrs.backtesting import BacktestingEngine
+
+    engine = BacktestingEngine()
+    engine.set_parameters(**parameters)
+    engine.load_history_snapshot(snapshot_path)
+
+    _worker_state["parameters"] = parameters
+    _worker_state["strategy_class"] = strategy_class
+    _worker_state["target_name"] = target_name
+
+
+def evaluate_worker(setting: dict) -> tuple:
+    """
+    Run one setting against the history data attached in init_worker.
+    """
+    from vnpyrs.backtesting import BacktestingEngine
+
+    engine = BacktestingEngine()
+    engine.set_output(lambda msg: None)
+    engine.set_parameters(**_worker_state["parameters"])
+    engine.add_strategy(_worker_state["strategy_class"], setting)
+    engine.load_data()
+    engine.run_backtesting()
+    statistics: dict = engine.rs_calculate_statistics(False).to_dict()
+
+    target_value: float = statistics[_worker_state["target_name"]]
+    return (setting, target_value, statistics)
+
+
 def ga_evaluate(
     cache: dict, evaluate_func: callable, key_func: callable, parameters: list
 ) -> float: